@@ -2,5 +2,12 @@
 
 pub mod prelude;
 
+pub mod conversation;
+pub mod conversation_member;
+pub mod file;
+pub mod message;
 pub mod post;
+pub mod refresh_token;
+pub mod sea_orm_active_enums;
 pub mod user;
+pub mod ws_ticket;
@@ -1,4 +1,10 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.0
 
+pub use super::conversation::Entity as Conversation;
+pub use super::conversation_member::Entity as ConversationMember;
+pub use super::file::Entity as File;
+pub use super::message::Entity as Message;
 pub use super::post::Entity as Post;
+pub use super::refresh_token::Entity as RefreshToken;
 pub use super::user::Entity as User;
+pub use super::ws_ticket::Entity as WsTicket;
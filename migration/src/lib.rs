@@ -2,6 +2,16 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_post_table;
 mod m20250703_135737_create_user_table;
+mod m20261017_000001_create_refresh_token_table;
+mod m20261017_000002_add_role_to_user;
+mod m20261017_000003_add_post_user_foreign_key;
+mod m20261017_000004_fix_nullability_and_timestamps;
+mod m20261017_000005_add_post_search_vector;
+mod m20261017_000006_create_ws_ticket_table;
+mod m20261017_000007_create_ws_broadcast_spill_table;
+mod m20261017_000008_create_chat_tables;
+mod m20261018_000001_create_file_table;
+mod m20261018_000002_add_purpose_to_file;
 
 pub struct Migrator;
 
@@ -11,6 +21,16 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20250703_135737_create_user_table::Migration),
             Box::new(m20220101_000001_create_post_table::Migration),
+            Box::new(m20261017_000001_create_refresh_token_table::Migration),
+            Box::new(m20261017_000002_add_role_to_user::Migration),
+            Box::new(m20261017_000003_add_post_user_foreign_key::Migration),
+            Box::new(m20261017_000004_fix_nullability_and_timestamps::Migration),
+            Box::new(m20261017_000005_add_post_search_vector::Migration),
+            Box::new(m20261017_000006_create_ws_ticket_table::Migration),
+            Box::new(m20261017_000007_create_ws_broadcast_spill_table::Migration),
+            Box::new(m20261017_000008_create_chat_tables::Migration),
+            Box::new(m20261018_000001_create_file_table::Migration),
+            Box::new(m20261018_000002_add_purpose_to_file::Migration),
         ]
     }
 }
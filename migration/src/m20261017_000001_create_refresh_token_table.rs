@@ -0,0 +1,66 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RefreshToken::Table)
+                    .if_not_exists()
+                    .col(pk_auto(RefreshToken::Id))
+                    .col(integer(RefreshToken::UserId))
+                    .col(string(RefreshToken::TokenHash).unique_key())
+                    .col(string(RefreshToken::FamilyId))
+                    .col(timestamp(RefreshToken::ExpiresAt))
+                    .col(timestamp_null(RefreshToken::RevokedAt))
+                    .col(timestamp(RefreshToken::CreatedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_refresh_token_user_id")
+                            .from(RefreshToken::Table, RefreshToken::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_refresh_token_family_id")
+                    .table(RefreshToken::Table)
+                    .col(RefreshToken::FamilyId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RefreshToken::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RefreshToken {
+    Table,
+    Id,
+    UserId,
+    TokenHash,
+    FamilyId,
+    ExpiresAt,
+    RevokedAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
@@ -0,0 +1,115 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Posts whose author no longer exists would make the constraint fail.
+        // Deleting them cannot be undone, so leave the decision to an operator.
+        let db = manager.get_connection();
+        let orphaned = db
+            .query_one(
+                db.get_database_backend().build(
+                    Query::select()
+                        .expr_as(Expr::col(Post::Id).count(), Alias::new("count"))
+                        .from(Post::Table)
+                        .and_where(
+                            Expr::col(Post::UserId).not_in_subquery(
+                                Query::select()
+                                    .column(User::Id)
+                                    .from(User::Table)
+                                    .to_owned(),
+                            ),
+                        ),
+                ),
+            )
+            .await?
+            .map(|row| row.try_get::<i64>("", "count"))
+            .transpose()?
+            .unwrap_or_default();
+        if orphaned > 0 {
+            return Err(DbErr::Migration(format!(
+                "{orphaned} posts belong to users that no longer exist; reassign or delete them \
+                 (post.user_id NOT IN (SELECT id FROM \"user\")) before adding fk_post_user_id"
+            )));
+        }
+
+        // Deleting a user deletes their posts.
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_post_user_id")
+                    .from(Post::Table, Post::UserId)
+                    .to(User::Table, User::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .on_update(ForeignKeyAction::NoAction)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_post_user_id")
+                    .table(Post::Table)
+                    .col(Post::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_post_created_at")
+                    .table(Post::Table)
+                    .col(Post::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_post_created_at")
+                    .table(Post::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_post_user_id")
+                    .table(Post::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .name("fk_post_user_id")
+                    .table(Post::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Post {
+    Table,
+    Id,
+    UserId,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
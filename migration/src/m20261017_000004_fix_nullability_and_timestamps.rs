@@ -0,0 +1,88 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Timestamp columns moved to `timestamptz`. Existing values were written as UTC.
+const TIMESTAMP_COLUMNS: &[(&str, &str)] = &[
+    ("user", "created_at"),
+    ("user", "updated_at"),
+    ("post", "created_at"),
+    ("post", "updated_at"),
+    ("refresh_token", "expires_at"),
+    ("refresh_token", "revoked_at"),
+    ("refresh_token", "created_at"),
+];
+
+/// Tables whose `updated_at` is maintained by the `set_updated_at` trigger.
+const UPDATED_AT_TABLES: &[&str] = &["user", "post"];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // The entities declare these as optional; the original migrations made them NOT NULL.
+        db.execute_unprepared(r#"ALTER TABLE "user" ALTER COLUMN "avatar" DROP NOT NULL"#)
+            .await?;
+        db.execute_unprepared(r#"ALTER TABLE "post" ALTER COLUMN "banner" DROP NOT NULL"#)
+            .await?;
+
+        for (table, column) in TIMESTAMP_COLUMNS {
+            db.execute_unprepared(&format!(
+                r#"ALTER TABLE "{table}" ALTER COLUMN "{column}" TYPE timestamptz USING "{column}" AT TIME ZONE 'UTC'"#
+            ))
+            .await?;
+        }
+
+        db.execute_unprepared(
+            r#"CREATE OR REPLACE FUNCTION set_updated_at() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at = now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql"#,
+        )
+        .await?;
+
+        for table in UPDATED_AT_TABLES {
+            db.execute_unprepared(&format!(
+                r#"CREATE TRIGGER "{table}_set_updated_at" BEFORE UPDATE ON "{table}" FOR EACH ROW EXECUTE FUNCTION set_updated_at()"#
+            ))
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        for table in UPDATED_AT_TABLES {
+            db.execute_unprepared(&format!(
+                r#"DROP TRIGGER IF EXISTS "{table}_set_updated_at" ON "{table}""#
+            ))
+            .await?;
+        }
+        db.execute_unprepared("DROP FUNCTION IF EXISTS set_updated_at()")
+            .await?;
+
+        for (table, column) in TIMESTAMP_COLUMNS {
+            db.execute_unprepared(&format!(
+                r#"ALTER TABLE "{table}" ALTER COLUMN "{column}" TYPE timestamp USING "{column}" AT TIME ZONE 'UTC'"#
+            ))
+            .await?;
+        }
+
+        db.execute_unprepared(r#"UPDATE "post" SET "banner" = '' WHERE "banner" IS NULL"#)
+            .await?;
+        db.execute_unprepared(r#"ALTER TABLE "post" ALTER COLUMN "banner" SET NOT NULL"#)
+            .await?;
+        db.execute_unprepared(r#"UPDATE "user" SET "avatar" = '' WHERE "avatar" IS NULL"#)
+            .await?;
+        db.execute_unprepared(r#"ALTER TABLE "user" ALTER COLUMN "avatar" SET NOT NULL"#)
+            .await?;
+
+        Ok(())
+    }
+}
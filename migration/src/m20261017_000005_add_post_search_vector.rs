@@ -0,0 +1,39 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // Title matches weigh more than body matches when ranking. The text search
+        // configuration must match `SEARCH_CONFIG` in `src/utils/search.rs`.
+        db.execute_unprepared(
+            r#"ALTER TABLE "post" ADD COLUMN "search_vector" tsvector GENERATED ALWAYS AS (
+                setweight(to_tsvector('english', coalesce("title", '')), 'A')
+                || setweight(to_tsvector('english', coalesce("text", '')), 'B')
+            ) STORED"#,
+        )
+        .await?;
+
+        db.execute_unprepared(
+            r#"CREATE INDEX "idx_post_search_vector" ON "post" USING GIN ("search_vector")"#,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared(r#"DROP INDEX IF EXISTS "idx_post_search_vector""#)
+            .await?;
+        db.execute_unprepared(r#"ALTER TABLE "post" DROP COLUMN IF EXISTS "search_vector""#)
+            .await?;
+
+        Ok(())
+    }
+}
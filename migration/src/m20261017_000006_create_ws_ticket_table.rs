@@ -0,0 +1,47 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+/// Holds WebSocket tickets until they are redeemed, so a ticket issued by one
+/// instance can be redeemed by any other.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WsTicket::Table)
+                    .if_not_exists()
+                    .col(string(WsTicket::TicketHash).primary_key())
+                    .col(text(WsTicket::Claims))
+                    .col(timestamp_with_time_zone(WsTicket::ExpiresAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ws_ticket_expires_at")
+                    .table(WsTicket::Table)
+                    .col(WsTicket::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WsTicket::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WsTicket {
+    Table,
+    TicketHash,
+    Claims,
+    ExpiresAt,
+}
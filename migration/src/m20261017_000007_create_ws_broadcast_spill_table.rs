@@ -0,0 +1,57 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+/// Holds WebSocket broadcasts too large for a `NOTIFY` payload. The
+/// notification carries the row id instead, and old rows are pruned by the
+/// publishing instance.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WsBroadcastSpill::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WsBroadcastSpill::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(text(WsBroadcastSpill::Message))
+                    .col(
+                        timestamp_with_time_zone(WsBroadcastSpill::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ws_broadcast_spill_created_at")
+                    .table(WsBroadcastSpill::Table)
+                    .col(WsBroadcastSpill::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WsBroadcastSpill::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WsBroadcastSpill {
+    Table,
+    Id,
+    Message,
+    CreatedAt,
+}
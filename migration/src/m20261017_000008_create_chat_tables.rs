@@ -0,0 +1,184 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+/// Direct and group conversations, their members and messages. A member's
+/// `last_read_message_id` is their read receipt; message ids only grow, so
+/// anything above it is unread.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Conversation::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Conversation::Id))
+                    .col(string(Conversation::Kind))
+                    .col(string_null(Conversation::Name))
+                    .col(string_null(Conversation::DirectKey).unique_key())
+                    .col(integer_null(Conversation::LastMessageId))
+                    .col(
+                        timestamp_with_time_zone(Conversation::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        timestamp_with_time_zone(Conversation::UpdatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ConversationMember::Table)
+                    .if_not_exists()
+                    .col(integer(ConversationMember::ConversationId))
+                    .col(integer(ConversationMember::UserId))
+                    .col(integer_null(ConversationMember::LastReadMessageId))
+                    .col(
+                        timestamp_with_time_zone(ConversationMember::JoinedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(ConversationMember::ConversationId)
+                            .col(ConversationMember::UserId),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_conversation_member_conversation_id")
+                            .from(
+                                ConversationMember::Table,
+                                ConversationMember::ConversationId,
+                            )
+                            .to(Conversation::Table, Conversation::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_conversation_member_user_id")
+                            .from(ConversationMember::Table, ConversationMember::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_conversation_member_user_id")
+                    .table(ConversationMember::Table)
+                    .col(ConversationMember::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Message::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Message::Id))
+                    .col(integer(Message::ConversationId))
+                    .col(integer(Message::SenderId))
+                    .col(text(Message::Body))
+                    .col(
+                        timestamp_with_time_zone(Message::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_message_conversation_id")
+                            .from(Message::Table, Message::ConversationId)
+                            .to(Conversation::Table, Conversation::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_message_sender_id")
+                            .from(Message::Table, Message::SenderId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // History and replay both read one conversation's messages by id.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_message_conversation_id_id")
+                    .table(Message::Table)
+                    .col(Message::ConversationId)
+                    .col(Message::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Bumped whenever a message is sent, so conversations sort by activity.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"CREATE TRIGGER "conversation_set_updated_at" BEFORE UPDATE ON "conversation" FOR EACH ROW EXECUTE FUNCTION set_updated_at()"#,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Message::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(ConversationMember::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Conversation::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Conversation {
+    Table,
+    Id,
+    Kind,
+    Name,
+    DirectKey,
+    LastMessageId,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ConversationMember {
+    Table,
+    ConversationId,
+    UserId,
+    LastReadMessageId,
+    JoinedAt,
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    Id,
+    ConversationId,
+    SenderId,
+    Body,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
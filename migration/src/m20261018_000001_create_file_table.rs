@@ -0,0 +1,79 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+/// One row per stored upload. Direct uploads start out `pending` and become
+/// `ready` once confirmed; `checksum` is only known for files that passed
+/// through the server. A user with files cannot be deleted until their files
+/// are, so no row disappears while its object stays in storage.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(File::Table)
+                    .if_not_exists()
+                    .col(pk_auto(File::Id))
+                    .col(string(File::Key).unique_key())
+                    .col(integer(File::OwnerId))
+                    .col(string(File::OriginalName))
+                    .col(big_integer(File::Size))
+                    .col(string(File::ContentType))
+                    .col(string_null(File::Checksum))
+                    .col(string(File::Status))
+                    .col(
+                        timestamp_with_time_zone(File::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_file_owner_id")
+                            .from(File::Table, File::OwnerId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Restrict),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Listings read one owner's files by id.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_file_owner_id_id")
+                    .table(File::Table)
+                    .col(File::OwnerId)
+                    .col(File::Id)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(File::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum File {
+    Table,
+    Id,
+    Key,
+    OwnerId,
+    OriginalName,
+    Size,
+    ContentType,
+    Checksum,
+    Status,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
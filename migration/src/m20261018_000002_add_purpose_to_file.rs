@@ -0,0 +1,37 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+/// What a file was uploaded as, which decides the types and size it may have.
+/// Existing files were all uploaded as attachments.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .add_column_if_not_exists(string(File::Purpose).default("attachment"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .drop_column(File::Purpose)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum File {
+    Table,
+    Purpose,
+}
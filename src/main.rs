@@ -1,18 +1,37 @@
-use actix_web::{App, HttpServer, middleware::Logger, web};
-use aws_sdk_s3::config::Region;
+use actix_web::{
+    App, HttpServer,
+    middleware::{Logger, from_fn},
+    web,
+};
 use migration::{Migrator, MigratorTrait};
 use sea_orm::{Database, DatabaseConnection};
 
-use crate::utils::app_state::AppState;
+use std::{sync::Arc, time::Duration};
+
+use crate::utils::{
+    app_state::AppState,
+    config::{BroadcastBackend, Config, Profile},
+    events,
+    password::PasswordService,
+    policy, upload_sweep, ws_broadcast,
+    ws_hub::{Hub, Topics},
+    ws_ticket::WsTickets,
+};
 
 mod routes;
 mod utils;
 
-#[derive(Debug)]
 struct MainError {
     error: String,
 }
 
+// `main` prints its error with `Debug`, so keep multi-line messages readable.
+impl std::fmt::Debug for MainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
 impl std::fmt::Display for MainError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.error)
@@ -42,9 +61,29 @@ async fn main() -> Result<(), MainError> {
     dotenv::dotenv().ok();
     env_logger::init();
 
-    let address = utils::constants::ADDRESS.clone();
-    let port = *utils::constants::PORT;
-    let db: DatabaseConnection = Database::connect(utils::constants::DATABASE_URL.clone())
+    let config = Arc::new(Config::load().map_err(|e| MainError {
+        error: e.to_string(),
+    })?);
+    let passwords = Arc::new(PasswordService::new(config.password));
+    // Tickets are stored in the database: any worker or instance may redeem one.
+    let ws_tickets = Arc::new(WsTickets::new(Duration::from_secs(
+        config.websocket.ticket_ttl_seconds,
+    )));
+    let hub = Hub::new(
+        Topics::new()
+            .rule("room:*", policy::open_topic)
+            .rule("user:*", policy::own_user_topic)
+            .rule(events::POSTS_TOPIC, policy::read_only_topic)
+            .rule("user:*:posts", policy::read_only_topic)
+            .rule(events::PRESENCE_TOPIC, policy::read_only_topic),
+        &config.websocket,
+        config.sse.replay_buffer,
+    );
+    log::info!("Starting with {:?} profile", config.profile);
+
+    let address = config.server.address.clone();
+    let port = config.server.port;
+    let db: DatabaseConnection = Database::connect(config.database.url.clone())
         .await
         .map_err(|e| MainError {
             error: e.to_string(),
@@ -53,41 +92,126 @@ async fn main() -> Result<(), MainError> {
         error: e.to_string(),
     })?;
 
-    // Initialize AWS S3 client
-    let aws_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(Region::new(utils::constants::AWS_REGION.clone()))
-        .load()
-        .await;
+    // Drift is fatal under the test profile so CI catches it; elsewhere it is logged.
+    let drift = utils::schema_check::check(&db)
+        .await
+        .map_err(|e| MainError {
+            error: e.to_string(),
+        })?;
+    if !drift.is_empty() {
+        let report = format!("schema drift detected:\n  - {}", drift.join("\n  - "));
+        if config.profile == Profile::Test {
+            return Err(MainError { error: report });
+        }
+        log::warn!("{report}");
+    }
 
-    // Create S3 client with proper configuration
-    let s3_config = aws_sdk_s3::config::Builder::from(&aws_config)
-        .force_path_style(false) // Use virtual-hosted-style requests
-        .build();
-    let s3_client = aws_sdk_s3::Client::from_conf(s3_config);
+    if config.websocket.broadcast == BroadcastBackend::Postgres {
+        ws_broadcast::start_postgres(&hub, db.clone(), config.database.url.clone());
+    }
 
-    HttpServer::new(move || {
+    let storage = utils::storage::from_config(&config)
+        .await
+        .map_err(|e| MainError {
+            error: e.to_string(),
+        })?;
+    log::info!("Storing files in {:?} storage", config.storage.backend);
+    // A missing bucket or bad credentials would otherwise only show on the
+    // first upload; like drift, this is only fatal under the test profile.
+    if let Err(e) = storage.list("", 1).await {
+        let report = format!("storage is not reachable: {e}");
+        if config.profile == Profile::Test {
+            return Err(MainError { error: report });
+        }
+        log::warn!("{report}");
+    }
+    upload_sweep::start(
+        db.clone(),
+        storage.clone(),
+        Duration::from_secs(config.storage.pending_upload_ttl_seconds),
+    );
+
+    let sessions = hub.clone();
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
+            .wrap(from_fn(
+                routes::middlewares::request_id_middlewares::request_id_middleware,
+            ))
+            .app_data(utils::validation::json_config())
+            .app_data(utils::validation::query_config())
+            .app_data(utils::validation::multipart_config(&config.storage))
             .app_data(web::Data::new(AppState {
                 db: db.clone(),
-                s3_client: s3_client.clone(),
+                storage: storage.clone(),
+                config: config.clone(),
+                passwords: passwords.clone(),
+                ws_tickets: ws_tickets.clone(),
+                hub: hub.clone(),
             }))
             .configure(routes::user_routes::user_routes)
             .configure(routes::auth_routes::auth_routes)
             .configure(routes::post_routes::post_routes)
+            .configure(routes::chat_routes::chat_routes)
             .configure(routes::file_routes::file_routes)
             .configure(routes::websocket_routes::websocket_routes)
             .configure(routes::websocket_routes::websocket_api_routes)
+            .configure(routes::sse_routes::sse_routes)
+            .configure(routes::storage_routes::storage_routes)
     })
     .bind(format!("{address}:{port}"))
     .map_err(|e| MainError {
         error: e.to_string(),
     })?
-    .run()
-    .await
-    .map_err(|e| MainError {
+    // Signals are handled below so WebSocket sessions close before the
+    // workers stop.
+    .disable_signals()
+    .run();
+
+    let server_handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        log::info!("shutting down, closing websocket sessions");
+        sessions.shutdown(WS_SHUTDOWN_GRACE).await;
+        server_handle.stop(true).await;
+    });
+
+    server.await.map_err(|e| MainError {
         error: e.to_string(),
     })?;
 
     Ok(())
 }
+
+/// How long sockets get to close after the server is asked to stop.
+const WS_SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("cannot listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("cannot listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -5,6 +5,9 @@ pub fn auth_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/auth")
             .service(handlers::auth_handler::register)
-            .service(handlers::auth_handler::login),
+            .service(handlers::auth_handler::login)
+            .service(handlers::auth_handler::refresh)
+            .service(handlers::auth_handler::logout)
+            .service(handlers::auth_handler::ws_ticket),
     );
 }
@@ -0,0 +1,15 @@
+use crate::routes::{handlers, middlewares::auth_middlewares};
+use actix_web::{middleware::from_fn, web};
+
+pub fn chat_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/chat")
+            .wrap(from_fn(auth_middlewares::auth_middleware))
+            .service(handlers::chat_handler::create_conversation)
+            .service(handlers::chat_handler::list_conversations)
+            .service(handlers::chat_handler::get_conversation)
+            .service(handlers::chat_handler::list_messages)
+            .service(handlers::chat_handler::mark_read)
+            .service(handlers::chat_handler::unread_counts),
+    );
+}
@@ -5,6 +5,12 @@ pub fn file_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/file")
             .wrap(from_fn(auth_middlewares::auth_middleware))
-            .service(handlers::file_handler::upload_file),
+            .service(handlers::file_handler::upload_file)
+            .service(handlers::file_handler::presign_upload)
+            .service(handlers::file_handler::confirm_upload)
+            .service(handlers::file_handler::presign_download)
+            .service(handlers::file_handler::list_files)
+            .service(handlers::file_handler::get_file)
+            .service(handlers::file_handler::delete_file),
     );
 }
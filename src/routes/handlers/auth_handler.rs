@@ -1,27 +1,60 @@
 use actix_web::{post, web};
-use entity::user;
-use sea_orm::{
-    ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, EntityTrait, QueryFilter,
-};
+use entity::{sea_orm_active_enums::Role, user};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
-use sha256::digest;
+use validator::Validate;
 
-use crate::utils::{api_response::ApiResponse, app_state::AppState, jwt::generate_jwt};
+use crate::utils::{
+    api_response::ApiResponse,
+    app_error::{AppError, ErrorCode},
+    app_state::AppState,
+    jwt::JwtClaims,
+    password::Verification,
+    session::{self, TokenPair},
+    validation::{ValidatedJson, http_scheme, not_blank, password_strength},
+};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct RegisterRequest {
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = 100, message = "must be at most 100 characters")
+    )]
     pub name: String,
+    #[validate(
+        email(message = "must be a valid email address"),
+        length(max = 254, message = "must be at most 254 characters")
+    )]
     pub email: String,
+    #[validate(custom(function = "password_strength"))]
     pub password: String,
+    #[validate(
+        url(message = "must be a valid URL"),
+        custom(function = "http_scheme"),
+        length(max = 2048, message = "must be at most 2048 characters")
+    )]
     pub avatar: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: UserResponse,
 }
 
+#[derive(Deserialize, Validate)]
+pub struct RefreshRequest {
+    #[validate(length(min = 1, max = 256, message = "must not be empty"))]
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+}
+
 #[derive(Serialize)]
 pub struct UserResponse {
     pub id: i32,
@@ -30,94 +63,131 @@ pub struct UserResponse {
     pub created_at: String,
     pub updated_at: String,
     pub avatar: Option<String>,
+    pub role: Role,
 }
 
 #[post("/register")]
 pub async fn register(
     state: web::Data<AppState>,
-    body: web::Json<RegisterRequest>,
-) -> Result<ApiResponse<user::Model>, ApiResponse<String>> {
+    body: ValidatedJson<RegisterRequest>,
+) -> Result<ApiResponse<user::Model>, AppError> {
+    let passwords = state.passwords.clone();
+    let password = body.password.clone();
+    let password_hash = web::block(move || passwords.hash(&password))
+        .await?
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))?;
+
     let user = user::ActiveModel {
         name: Set(body.name.clone()),
         email: Set(body.email.clone()),
-        password: Set(digest(body.password.clone())),
+        password: Set(password_hash),
         avatar: Set(body.avatar.clone()),
         ..Default::default()
     };
 
-    let user = user.insert(&state.db).await;
-
-    match user {
-        Ok(user) => Ok(ApiResponse::new(
-            200,
-            format!("User created successfully: {}", user.id),
-            user,
-        )),
-        Err(db_err) => Err(ApiResponse::new(
-            500,
-            "Failed to create user".to_string(),
-            db_err.to_string(),
-        )),
-    }
+    let user = user
+        .insert(&state.db)
+        .await
+        .map_err(|db_err| match AppError::from(db_err) {
+            AppError::Conflict(code, _) => {
+                AppError::Conflict(code, "Email is already registered".to_string())
+            }
+            e => e,
+        })?;
+
+    Ok(ApiResponse::new(
+        200,
+        format!("User created successfully: {}", user.id),
+        user,
+    ))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct LoginRequest {
+    #[validate(
+        email(message = "must be a valid email address"),
+        length(max = 254, message = "must be at most 254 characters")
+    )]
     pub email: String,
+    #[validate(length(min = 1, max = 128, message = "must be between 1 and 128 characters"))]
     pub password: String,
 }
 
+fn invalid_credentials() -> AppError {
+    AppError::Unauthorized(
+        ErrorCode::AuthInvalidCredentials,
+        "Invalid email or password".to_string(),
+    )
+}
+
 #[post("/login")]
 pub async fn login(
     state: web::Data<AppState>,
-    body: web::Json<LoginRequest>,
-) -> Result<ApiResponse<LoginResponse>, ApiResponse<String>> {
-    let user = user::Entity::find()
-        .filter(
-            Condition::all()
-                .add(user::Column::Email.eq(body.email.clone()))
-                .add(user::Column::Password.eq(digest(body.password.clone()))),
-        )
+    body: ValidatedJson<LoginRequest>,
+) -> Result<ApiResponse<LoginResponse>, AppError> {
+    let Some(user) = user::Entity::find()
+        .filter(user::Column::Email.eq(body.email.clone()))
         .one(&state.db)
-        .await;
-
-    let user = match user {
-        Ok(user) => user,
-        Err(db_err) => {
-            return Err(ApiResponse::new(
-                500,
-                "Database error".to_string(),
-                db_err.to_string(),
-            ));
-        }
+        .await?
+    else {
+        // Hash anyway, or unknown emails would answer measurably faster.
+        let passwords = state.passwords.clone();
+        let password = body.password.clone();
+        web::block(move || passwords.verify_unknown(&password)).await?;
+        return Err(invalid_credentials());
     };
 
-    println!(
-        "user: {:?}, password: {:?}, email: {:?}",
-        user,
-        digest(body.password.clone()),
-        body.email
-    );
+    // Verify in Rust rather than comparing hashes in SQL, so salted hashes work.
+    let passwords = state.passwords.clone();
+    let password = body.password.clone();
+    let stored = user.password.clone();
+    let verification = web::block(move || passwords.verify(&password, &stored)).await?;
 
-    if user.is_none() {
-        return Err(ApiResponse::new(
-            401,
-            "User not found".to_string(),
-            "User not found".to_string(),
-        ));
-    }
+    let needs_rehash = match verification {
+        Verification::Valid { needs_rehash } => needs_rehash,
+        Verification::Invalid => return Err(invalid_credentials()),
+    };
 
-    let user = user.unwrap();
+    // Upgrade legacy SHA-256 rows and hashes with outdated parameters. A failure
+    // here must not block the login, the upgrade is retried next time.
+    let user = if needs_rehash {
+        let passwords = state.passwords.clone();
+        let password = body.password.clone();
+        match web::block(move || passwords.hash(&password)).await {
+            Ok(Ok(password_hash)) => {
+                let mut active: user::ActiveModel = user.clone().into();
+                active.password = Set(password_hash);
+                match active.update(&state.db).await {
+                    Ok(updated) => updated,
+                    Err(db_err) => {
+                        log::warn!("Failed to rehash password for user {}: {db_err}", user.id);
+                        user
+                    }
+                }
+            }
+            _ => {
+                log::warn!("Failed to rehash password for user {}", user.id);
+                user
+            }
+        }
+    } else {
+        user
+    };
 
-    let token = generate_jwt(user.id, user.email.clone()).expect("Failed to generate JWT");
+    let TokenPair {
+        access_token,
+        refresh_token,
+    } = session::start_session(&state.db, &state.config.auth, &user).await?;
 
     let response = LoginResponse {
-        token,
+        token: access_token,
+        refresh_token,
         user: UserResponse {
             id: user.id,
             name: user.name,
             email: user.email,
             avatar: user.avatar,
+            role: user.role,
             created_at: user.created_at.to_string(),
             updated_at: user.updated_at.to_string(),
         },
@@ -129,3 +199,65 @@ pub async fn login(
         response,
     ))
 }
+
+#[post("/refresh")]
+pub async fn refresh(
+    state: web::Data<AppState>,
+    body: ValidatedJson<RefreshRequest>,
+) -> Result<ApiResponse<TokenResponse>, AppError> {
+    let TokenPair {
+        access_token,
+        refresh_token,
+    } = session::rotate_session(&state.db, &state.config.auth, &body.refresh_token).await?;
+
+    Ok(ApiResponse::new(
+        200,
+        "Token refreshed".to_string(),
+        TokenResponse {
+            token: access_token,
+            refresh_token,
+        },
+    ))
+}
+
+#[post("/logout")]
+pub async fn logout(
+    state: web::Data<AppState>,
+    body: ValidatedJson<RefreshRequest>,
+) -> Result<ApiResponse<String>, AppError> {
+    session::end_session(&state.db, &body.refresh_token).await?;
+
+    Ok(ApiResponse::new(
+        200,
+        "Logged out".to_string(),
+        "".to_string(),
+    ))
+}
+
+#[derive(Serialize)]
+pub struct WsTicketResponse {
+    pub ticket: String,
+    /// Seconds until the ticket can no longer be redeemed.
+    pub expires_in: u64,
+}
+
+/// Trades the caller's access token for a single-use `/ws?ticket=` ticket.
+#[post(
+    "/ws-ticket",
+    wrap = "actix_web::middleware::from_fn(crate::routes::middlewares::auth_middlewares::auth_middleware)"
+)]
+pub async fn ws_ticket(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+) -> Result<ApiResponse<WsTicketResponse>, AppError> {
+    let ticket = state.ws_tickets.issue(&state.db, &claims).await?;
+
+    Ok(ApiResponse::new(
+        200,
+        "WebSocket ticket issued".to_string(),
+        WsTicketResponse {
+            ticket,
+            expires_in: state.ws_tickets.ttl().as_secs(),
+        },
+    ))
+}
@@ -0,0 +1,396 @@
+use std::collections::{BTreeSet, HashMap};
+
+use actix_web::{get, post, web};
+use entity::{conversation, conversation_member, message, sea_orm_active_enums::ConversationKind};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set, SqlErr,
+    TransactionTrait, prelude::DateTimeWithTimeZone,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    routes::handlers::post_handler::AuthorSummary,
+    utils::{
+        api_response::ApiResponse,
+        app_error::{AppError, FieldError, parse_id},
+        app_state::AppState,
+        chat::{self, conversation_not_found, conversations_of},
+        jwt::JwtClaims,
+        list_query::{FieldKind, ListParams, ListSpec, Page},
+        validation::{ValidatedJson, not_blank},
+    },
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateConversationRequest {
+    pub kind: ConversationKind,
+    /// Required for groups; direct conversations have no name.
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = 100, message = "must be at most 100 characters")
+    )]
+    pub name: Option<String>,
+    /// The other members; the caller is always added.
+    #[validate(length(min = 1, max = 100, message = "must list between 1 and 100 users"))]
+    pub member_ids: Vec<i32>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReadRequest {
+    pub message_id: i32,
+}
+
+/// A member with their read receipt.
+#[derive(Debug, Serialize)]
+pub struct MemberResponse {
+    #[serde(flatten)]
+    pub user: AuthorSummary,
+    pub last_read_message_id: Option<i32>,
+    pub joined_at: DateTimeWithTimeZone,
+}
+
+/// A conversation as seen by the caller.
+#[derive(Debug, Serialize)]
+pub struct ConversationResponse {
+    #[serde(flatten)]
+    pub conversation: conversation::Model,
+    pub members: Vec<MemberResponse>,
+    pub unread_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadCount {
+    pub conversation_id: i32,
+    pub unread_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadResponse {
+    pub total: u64,
+    pub conversations: Vec<UnreadCount>,
+}
+
+/// Sorts and filters allowed on `/chat/conversations`. The default puts the
+/// most recently active conversations first.
+fn conversation_list_spec() -> ListSpec<conversation::Entity> {
+    ListSpec::new(conversation::Column::Id)
+        .sortable("created_at", conversation::Column::CreatedAt)
+        .sortable("updated_at", conversation::Column::UpdatedAt)
+        .filterable("kind", conversation::Column::Kind, FieldKind::Text)
+        .default_sort("-updated_at")
+}
+
+/// Sorts and filters allowed on a conversation's history. Newest first.
+fn message_list_spec() -> ListSpec<message::Entity> {
+    ListSpec::new(message::Column::Id)
+        .sortable("created_at", message::Column::CreatedAt)
+        .filterable("sender_id", message::Column::SenderId, FieldKind::Integer)
+        .filterable(
+            "created_at",
+            message::Column::CreatedAt,
+            FieldKind::Timestamp,
+        )
+        .searchable(message::Column::Body)
+        .date_range(message::Column::CreatedAt)
+        .default_sort("-id")
+}
+
+/// Members and the caller's unread counts for a set of conversations, loaded
+/// in two queries.
+async fn conversation_details(
+    db: &DatabaseConnection,
+    user_id: i32,
+    conversation_ids: Vec<i32>,
+) -> Result<(HashMap<i32, Vec<MemberResponse>>, HashMap<i32, u64>), AppError> {
+    let mut members: HashMap<i32, Vec<MemberResponse>> = HashMap::new();
+    for (member, user) in conversation_member::Entity::find()
+        .filter(conversation_member::Column::ConversationId.is_in(conversation_ids))
+        .find_also_related(entity::user::Entity)
+        .all(db)
+        .await?
+    {
+        let Some(user) = user else {
+            continue;
+        };
+        members
+            .entry(member.conversation_id)
+            .or_default()
+            .push(MemberResponse {
+                user: user.into(),
+                last_read_message_id: member.last_read_message_id,
+                joined_at: member.joined_at,
+            });
+    }
+    let unread = chat::unread_counts(db, user_id).await?;
+    Ok((members, unread))
+}
+
+async fn conversation_response(
+    db: &DatabaseConnection,
+    user_id: i32,
+    conversation: conversation::Model,
+) -> Result<ConversationResponse, AppError> {
+    let (mut members, unread) = conversation_details(db, user_id, vec![conversation.id]).await?;
+    Ok(ConversationResponse {
+        members: members.remove(&conversation.id).unwrap_or_default(),
+        unread_count: unread.get(&conversation.id).copied().unwrap_or(0),
+        conversation,
+    })
+}
+
+/// Creates a group, or opens the direct conversation with one user. Asking
+/// for a direct conversation that already exists returns it.
+#[post("/conversations")]
+pub async fn create_conversation(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    body: ValidatedJson<CreateConversationRequest>,
+) -> Result<ApiResponse<ConversationResponse>, AppError> {
+    let others: BTreeSet<i32> = body
+        .member_ids
+        .iter()
+        .copied()
+        .filter(|id| *id != claims.user_id)
+        .collect();
+
+    let mut problems = Vec::new();
+    if others.is_empty() {
+        problems.push(FieldError::new(
+            "member_ids",
+            "must include at least one other user",
+        ));
+    }
+    match body.kind {
+        ConversationKind::Direct if others.len() > 1 => problems.push(FieldError::new(
+            "member_ids",
+            "a direct conversation has exactly one other user",
+        )),
+        ConversationKind::Direct if body.name.is_some() => {
+            problems.push(FieldError::new("name", "direct conversations have no name"))
+        }
+        ConversationKind::Group if body.name.is_none() => {
+            problems.push(FieldError::new("name", "is required for a group"))
+        }
+        _ => {}
+    }
+    if !problems.is_empty() {
+        return Err(AppError::Validation(problems));
+    }
+
+    let found: BTreeSet<i32> = entity::user::Entity::find()
+        .filter(entity::user::Column::Id.is_in(others.iter().copied()))
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|user| user.id)
+        .collect();
+    let unknown: Vec<String> = others.difference(&found).map(|id| id.to_string()).collect();
+    if !unknown.is_empty() {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "member_ids",
+            format!("unknown users: {}", unknown.join(", ")),
+        )]));
+    }
+
+    // Direct conversations are keyed by their two members, lowest id first.
+    let direct_key = match body.kind {
+        ConversationKind::Direct => {
+            let other = *others.first().expect("checked above");
+            Some(format!(
+                "{}:{}",
+                other.min(claims.user_id),
+                other.max(claims.user_id)
+            ))
+        }
+        ConversationKind::Group => None,
+    };
+    if let Some(key) = &direct_key
+        && let Some(existing) = find_direct(&state.db, key).await?
+    {
+        let response = conversation_response(&state.db, claims.user_id, existing).await?;
+        return Ok(ApiResponse::new(
+            200,
+            "Conversation found".to_string(),
+            response,
+        ));
+    }
+
+    let txn = state.db.begin().await?;
+    let created = conversation::ActiveModel {
+        kind: Set(body.kind),
+        name: Set(body.name.clone()),
+        direct_key: Set(direct_key.clone()),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await;
+    let conversation = match created {
+        Ok(conversation) => conversation,
+        // Both users opened it at the same moment; use the one that won.
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => {
+            txn.rollback().await?;
+            let key = direct_key.as_deref().unwrap_or_default();
+            let existing = find_direct(&state.db, key)
+                .await?
+                .ok_or_else(conversation_not_found)?;
+            let response = conversation_response(&state.db, claims.user_id, existing).await?;
+            return Ok(ApiResponse::new(
+                200,
+                "Conversation found".to_string(),
+                response,
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    conversation_member::Entity::insert_many(std::iter::once(claims.user_id).chain(others).map(
+        |user_id| conversation_member::ActiveModel {
+            conversation_id: Set(conversation.id),
+            user_id: Set(user_id),
+            ..Default::default()
+        },
+    ))
+    .exec(&txn)
+    .await?;
+    txn.commit().await?;
+
+    let response = conversation_response(&state.db, claims.user_id, conversation).await?;
+    Ok(ApiResponse::new(
+        200,
+        "Conversation created".to_string(),
+        response,
+    ))
+}
+
+async fn find_direct(
+    db: &DatabaseConnection,
+    key: &str,
+) -> Result<Option<conversation::Model>, AppError> {
+    Ok(conversation::Entity::find()
+        .filter(conversation::Column::DirectKey.eq(key))
+        .one(db)
+        .await?)
+}
+
+#[get("/conversations")]
+pub async fn list_conversations(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    params: ListParams,
+) -> Result<ApiResponse<Page<ConversationResponse>>, AppError> {
+    let plan = conversation_list_spec().plan(&params, &state.config.auth.jwt_secret)?;
+    let select = conversation::Entity::find()
+        .filter(conversation::Column::Id.in_subquery(conversations_of(claims.user_id)));
+    let page = plan.fetch(&state.db, select).await?;
+
+    let ids = page
+        .items
+        .iter()
+        .map(|conversation| conversation.id)
+        .collect();
+    let (mut members, unread) = conversation_details(&state.db, claims.user_id, ids).await?;
+    let page = page.map(|conversation| ConversationResponse {
+        members: members.remove(&conversation.id).unwrap_or_default(),
+        unread_count: unread.get(&conversation.id).copied().unwrap_or(0),
+        conversation,
+    });
+
+    Ok(ApiResponse::new(200, page.summary("Conversations"), page))
+}
+
+#[get("/conversations/{id}")]
+pub async fn get_conversation(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    id: web::Path<String>,
+) -> Result<ApiResponse<ConversationResponse>, AppError> {
+    let conversation_id = parse_id(&id, "conversation")?;
+    chat::membership(&state.db, conversation_id, claims.user_id).await?;
+
+    let conversation = conversation::Entity::find_by_id(conversation_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(conversation_not_found)?;
+    let response = conversation_response(&state.db, claims.user_id, conversation).await?;
+
+    Ok(ApiResponse::new(
+        200,
+        "Conversation found".to_string(),
+        response,
+    ))
+}
+
+/// Message history. Cursor pagination is the default here; pass
+/// `pagination=page` for page numbers.
+#[get("/conversations/{id}/messages")]
+pub async fn list_messages(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    id: web::Path<String>,
+    mut params: ListParams,
+) -> Result<ApiResponse<Page<message::Model>>, AppError> {
+    let conversation_id = parse_id(&id, "conversation")?;
+    chat::membership(&state.db, conversation_id, claims.user_id).await?;
+
+    if params.pagination.is_none() {
+        params.pagination = Some("cursor".to_string());
+    }
+    let plan = message_list_spec().plan(&params, &state.config.auth.jwt_secret)?;
+    let select =
+        message::Entity::find().filter(message::Column::ConversationId.eq(conversation_id));
+    let page = plan.fetch(&state.db, select).await?;
+
+    Ok(ApiResponse::new(200, page.summary("Messages"), page))
+}
+
+/// Moves the caller's read receipt forward and tells the other members.
+#[post("/conversations/{id}/read")]
+pub async fn mark_read(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    id: web::Path<String>,
+    body: ValidatedJson<ReadRequest>,
+) -> Result<ApiResponse<conversation_member::Model>, AppError> {
+    let conversation_id = parse_id(&id, "conversation")?;
+    let member = chat::mark_read(
+        &state.db,
+        &state.hub,
+        claims.user_id,
+        conversation_id,
+        body.message_id,
+    )
+    .await?;
+
+    Ok(ApiResponse::new(
+        200,
+        "Read receipt saved".to_string(),
+        member,
+    ))
+}
+
+/// Unread counts across the caller's conversations, for badges.
+#[get("/unread")]
+pub async fn unread_counts(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+) -> Result<ApiResponse<UnreadResponse>, AppError> {
+    let mut conversations: Vec<UnreadCount> = chat::unread_counts(&state.db, claims.user_id)
+        .await?
+        .into_iter()
+        .map(|(conversation_id, unread_count)| UnreadCount {
+            conversation_id,
+            unread_count,
+        })
+        .collect();
+    conversations.sort_by_key(|count| count.conversation_id);
+    let total = conversations.iter().map(|count| count.unread_count).sum();
+
+    Ok(ApiResponse::new(
+        200,
+        format!("Unread messages: {total}"),
+        UnreadResponse {
+            total,
+            conversations,
+        },
+    ))
+}
@@ -1,113 +1,749 @@
 use crate::utils::{
     api_response::ApiResponse,
+    app_error::{AppError, ErrorCode, parse_id},
     app_state::AppState,
-    constants::{AWS_REGION, S3_BUCKET_NAME},
+    file_type::{self, FileType, SNIFF_LEN, sniff},
+    jwt::JwtClaims,
+    list_query::{FieldKind, ListParams, ListSpec, Page},
+    policy::{Action, authorize},
+    storage::{ObjectStore, PresignedRequest, object_not_found},
+    upload_sweep,
+    validation::{MultipartBody, ValidatedJson, media_type, not_blank},
 };
-use actix_multipart::form::{MultipartForm, tempfile::TempFile};
-use actix_web::{post, web};
-use aws_sdk_s3::primitives::ByteStream;
+use actix_multipart::{
+    Field, MultipartError,
+    form::{FieldReader, Limits, MultipartForm},
+};
+use actix_web::{FromRequest, HttpRequest, delete, get, post, rt, web};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use entity::sea_orm_active_enums::{FilePurpose, FileStatus};
+use futures_util::{TryStreamExt, future::LocalBoxFuture};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
 use serde::{Deserialize, Serialize};
-use std::io::Read;
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 use uuid::Uuid;
-
-#[derive(MultipartForm)]
-pub struct FileUploadForm {
-    #[multipart(rename = "file")]
-    pub file: TempFile,
-}
+use validator::Validate;
 
 #[derive(Deserialize, Serialize)]
 pub struct FileUploadResponse {
     pub message: String,
+    pub id: i32,
     pub file_url: String,
     pub file_key: String,
+    pub size: u64,
+    pub content_type: String,
+    /// Reads the file without credentials until it expires.
+    pub download_url: String,
+}
+
+/// A file's row, with where to find it.
+#[derive(Serialize)]
+pub struct FileResponse {
+    #[serde(flatten)]
+    pub file: entity::file::Model,
+    pub file_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UploadQuery {
+    /// Decides the allowed types and size; `attachment` when absent.
+    pub purpose: Option<FilePurpose>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PresignUploadRequest {
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = 255, message = "must be at most 255 characters")
+    )]
+    pub file_name: String,
+    #[validate(
+        custom(function = "media_type"),
+        length(max = 255, message = "must be at most 255 characters")
+    )]
+    pub content_type: String,
+    /// Exact size of the file in bytes.
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub size: u64,
+    /// Decides the allowed types and size; `attachment` when absent.
+    pub purpose: Option<FilePurpose>,
+}
+
+#[derive(Serialize)]
+pub struct PresignUploadResponse {
+    pub id: i32,
+    /// Pass to `/file/confirm` once the upload succeeded.
+    pub file_key: String,
+    pub method: String,
+    pub upload_url: String,
+    /// Send these headers exactly as given.
+    pub headers: BTreeMap<String, String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct FileKeyRequest {
+    #[validate(custom(function = "not_blank"))]
+    pub file_key: String,
+}
+
+#[derive(Deserialize)]
+pub struct FileKeyQuery {
+    pub file_key: String,
+}
+
+#[derive(Serialize)]
+pub struct DownloadUrlResponse {
+    pub file_key: String,
+    pub download_url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A fresh key under `uploads/{user_id}/`. The extension comes from the
+/// file's type, never from the name the client gave it.
+fn new_file_key(user_id: i32, file_type: FileType) -> String {
+    format!(
+        "uploads/{user_id}/{}.{}",
+        Uuid::new_v4(),
+        file_type.extension
+    )
+}
+
+fn file_not_found() -> AppError {
+    AppError::NotFound(ErrorCode::FileNotFound, "File not found".to_string())
+}
+
+fn download_ttl(state: &AppState) -> Duration {
+    Duration::from_secs(state.config.storage.download_url_ttl_seconds)
+}
+
+/// Sorts and filters allowed on `/file/list`.
+fn file_list_spec() -> ListSpec<entity::file::Entity> {
+    ListSpec::new(entity::file::Column::Id)
+        .sortable("created_at", entity::file::Column::CreatedAt)
+        .sortable("size", entity::file::Column::Size)
+        .sortable("original_name", entity::file::Column::OriginalName)
+        .filterable("status", entity::file::Column::Status, FieldKind::Text)
+        .filterable("purpose", entity::file::Column::Purpose, FieldKind::Text)
+        .filterable(
+            "content_type",
+            entity::file::Column::ContentType,
+            FieldKind::Text,
+        )
+        .filterable(
+            "created_at",
+            entity::file::Column::CreatedAt,
+            FieldKind::Timestamp,
+        )
+        .searchable(entity::file::Column::OriginalName)
+        .date_range(entity::file::Column::CreatedAt)
+        .default_sort("-created_at")
+}
+
+async fn find_file(state: &AppState, id: &str) -> Result<entity::file::Model, AppError> {
+    let file_id = parse_id(id, "file")?;
+    entity::file::Entity::find_by_id(file_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(file_not_found)
+}
+
+/// Removes a file that failed its checks, object and row, and returns `error`.
+async fn reject(state: &AppState, file: &entity::file::Model, error: AppError) -> AppError {
+    if let Err(e) = state.storage.delete(&file.key).await {
+        return e;
+    }
+    match entity::file::Entity::delete_by_id(file.id)
+        .exec(&state.db)
+        .await
+    {
+        Ok(_) => error,
+        Err(e) => e.into(),
+    }
+}
+
+/// What confirming a direct upload learns from reading it back.
+struct ReadBack {
+    /// The first [`SNIFF_LEN`] bytes, or all of them if shorter.
+    head: Vec<u8>,
+    size: u64,
+    checksum: String,
+}
+
+/// Reads a stored object through once, keeping its first bytes and hashing
+/// all of them.
+async fn read_back(state: &AppState, key: &str) -> Result<ReadBack, AppError> {
+    let mut object = state.storage.get(key).await?.ok_or_else(object_not_found)?;
+    let mut head = Vec::new();
+    let mut size = 0u64;
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = object
+        .body
+        .try_next()
+        .await
+        .map_err(|e| AppError::Storage(e.to_string()))?
+    {
+        if head.len() < SNIFF_LEN {
+            head.extend_from_slice(&chunk);
+        }
+        size += chunk.len() as u64;
+        hasher.update(&chunk);
+    }
+    Ok(ReadBack {
+        head,
+        size,
+        checksum: format!("{:x}", hasher.finalize()),
+    })
+}
+
+/// The `file` field of an upload, counted against its purpose's limit and
+/// the form's, and hashed as it is read. Keeps returning `None` once the
+/// field is exhausted, which the field itself does not allow polling for.
+struct IncomingFile<'a> {
+    field: Field,
+    limits: &'a mut Limits,
+    finished: bool,
+    purpose: FilePurpose,
+    max_bytes: u64,
+    size: u64,
+    hasher: Sha256,
+}
+
+impl IncomingFile<'_> {
+    async fn next(&mut self) -> Result<Option<Bytes>, AppError> {
+        if self.finished {
+            return Ok(None);
+        }
+        let Some(chunk) = self.field.try_next().await? else {
+            self.finished = true;
+            return Ok(None);
+        };
+        self.size += chunk.len() as u64;
+        if self.size > self.max_bytes {
+            return Err(file_type::too_large(self.purpose, self.max_bytes));
+        }
+        // Streamed to storage, so only the total counts.
+        self.limits.try_consume_limits(chunk.len(), false)?;
+        self.hasher.update(&chunk);
+        Ok(Some(chunk))
+    }
+}
+
+/// Body of `/file/upload`. Fields other than `file` are ignored.
+#[derive(MultipartForm)]
+pub struct UploadForm {
+    pub file: StoredFile,
+}
+
+/// The `file` field of an upload, already in storage under `key`. The object
+/// is deleted when this is dropped without [`StoredFile::keep`], such as when
+/// the rest of the form or the row fails.
+pub struct StoredFile {
+    storage: Arc<dyn ObjectStore>,
+    pub key: String,
+    pub file_name: String,
+    pub purpose: FilePurpose,
+    /// Sniffed from the content, whatever the client declared.
+    pub file_type: FileType,
+    pub size: u64,
+    pub checksum: String,
+    kept: bool,
+}
+
+impl StoredFile {
+    /// Leaves the object in storage once a row accounts for it.
+    fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for StoredFile {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+        let storage = self.storage.clone();
+        let key = std::mem::take(&mut self.key);
+        rt::spawn(async move {
+            if let Err(e) = storage.delete(&key).await {
+                log::warn!("failed to delete orphaned object {key}: {e}");
+            }
+        });
+    }
+}
+
+impl<'t> FieldReader<'t> for StoredFile {
+    type Future = LocalBoxFuture<'t, Result<Self, MultipartError>>;
+
+    fn read_field(req: &'t HttpRequest, field: Field, limits: &'t mut Limits) -> Self::Future {
+        Box::pin(async move {
+            let name = field.name().unwrap_or_default().to_string();
+            store_field(req, field, limits)
+                .await
+                .map_err(|source| MultipartError::Field { name, source })
+        })
+    }
+}
+
+/// Streams a field straight to storage, so the file is never held in memory
+/// as a whole. The type is sniffed from the first bytes before anything is
+/// stored, and it is the sniffed type that the object is stored with.
+async fn store_field(
+    req: &HttpRequest,
+    field: Field,
+    limits: &mut Limits,
+) -> Result<StoredFile, actix_web::Error> {
+    let state = req
+        .app_data::<web::Data<AppState>>()
+        .expect("AppState is registered");
+    let claims = JwtClaims::extract(req).await?;
+    let query = web::Query::<UploadQuery>::extract(req).await?;
+    let purpose = query.purpose.unwrap_or(FilePurpose::Attachment);
+
+    // Get file info
+    let file_name = field
+        .content_disposition()
+        .and_then(|disposition| disposition.get_filename())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| {
+            AppError::BadRequest(
+                ErrorCode::FileNameRequired,
+                "File name is required".to_string(),
+            )
+        })?;
+    let declared_type = field.content_type().map(|ct| ct.essence_str().to_string());
+
+    let mut incoming = IncomingFile {
+        field,
+        limits,
+        finished: false,
+        purpose,
+        max_bytes: file_type::max_bytes(purpose, &state.config.storage),
+        size: 0,
+        hasher: Sha256::new(),
+    };
+
+    // Read enough to tell the type before storing anything
+    let mut head = Vec::new();
+    while head.len() < SNIFF_LEN
+        && let Some(chunk) = incoming.next().await?
+    {
+        head.extend_from_slice(&chunk);
+    }
+    let detected = sniff(&head);
+    file_type::check(purpose, declared_type.as_deref(), detected)?;
+
+    // Stream to storage; dropping the writer on error discards the upload
+    let key = new_file_key(claims.user_id, detected);
+    let mut writer = state.storage.writer(&key, detected.mime).await?;
+    writer.write(Bytes::from(head)).await?;
+    while let Some(chunk) = incoming.next().await? {
+        writer.write(chunk).await?;
+    }
+    writer.finish().await?;
+
+    Ok(StoredFile {
+        storage: state.storage.clone(),
+        key,
+        file_name,
+        purpose,
+        file_type: detected,
+        size: incoming.size,
+        checksum: format!("{:x}", incoming.hasher.finalize()),
+        kept: false,
+    })
+}
+
+async fn upload_response(
+    state: &AppState,
+    message: &str,
+    file: entity::file::Model,
+) -> Result<FileUploadResponse, AppError> {
+    Ok(FileUploadResponse {
+        message: message.to_string(),
+        id: file.id,
+        file_url: state.storage.url(&file.key),
+        download_url: state
+            .storage
+            .presign_get(&file.key, download_ttl(state))
+            .await?,
+        size: file.size as u64,
+        content_type: file.content_type,
+        file_key: file.key,
+    })
 }
 
+/// Stores the `file` field of a multipart body as it arrives; see
+/// [`StoredFile`]. `?purpose=` decides the allowed types and size.
 #[post("/upload")]
 pub async fn upload_file(
     state: web::Data<AppState>,
-    MultipartForm(form): MultipartForm<FileUploadForm>,
-) -> Result<ApiResponse<FileUploadResponse>, ApiResponse<String>> {
-    // Get file info
-    let file_name = form.file.file_name.as_ref().ok_or_else(|| {
-        ApiResponse::new(400, "File name is required".to_string(), "".to_string())
-    })?;
-
-    let content_type = form
-        .file
-        .content_type
-        .as_ref()
-        .map(|ct| ct.to_string())
-        .unwrap_or_else(|| "application/octet-stream".to_string());
-
-    // Generate unique file key
-    let file_extension = std::path::Path::new(file_name)
-        .extension()
-        .and_then(std::ffi::OsStr::to_str)
-        .unwrap_or("bin");
-
-    let file_key = format!("uploads/{}.{}", Uuid::new_v4(), file_extension);
-
-    // Read file content
-    let file_path = form.file.file.path();
-    let mut file_content = Vec::new();
-    let mut file = match std::fs::File::open(file_path) {
-        Ok(f) => f,
-        Err(e) => {
-            return Err(ApiResponse::new(
-                500,
-                format!("Failed to read file: {e}"),
-                "".to_string(),
-            ));
+    claims: JwtClaims,
+    MultipartBody(form): MultipartBody<UploadForm>,
+) -> Result<ApiResponse<FileUploadResponse>, AppError> {
+    let stored = form.file;
+    let file = entity::file::ActiveModel {
+        key: Set(stored.key.clone()),
+        owner_id: Set(claims.user_id),
+        original_name: Set(stored.file_name.clone()),
+        size: Set(stored.size as i64),
+        content_type: Set(stored.file_type.mime.to_string()),
+        checksum: Set(Some(stored.checksum.clone())),
+        status: Set(FileStatus::Ready),
+        purpose: Set(stored.purpose),
+        ..Default::default()
+    }
+    .insert(&state.db)
+    .await?;
+    stored.keep();
+
+    let response = upload_response(&state, "File uploaded successfully", file).await?;
+
+    Ok(ApiResponse::new(
+        200,
+        "File uploaded successfully".to_string(),
+        response,
+    ))
+}
+
+/// Issues a request that uploads one file straight to storage, bypassing
+/// this server. The type and exact size are part of the signature, and the
+/// key is under the caller's prefix. The type must be allowed for the
+/// purpose; the content itself is checked on confirmation. The file is
+/// `pending` until confirmed with `/file/confirm`.
+#[post("/presign-upload")]
+pub async fn presign_upload(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    body: ValidatedJson<PresignUploadRequest>,
+) -> Result<ApiResponse<PresignUploadResponse>, AppError> {
+    let purpose = body.purpose.unwrap_or(FilePurpose::Attachment);
+    let max_bytes = file_type::max_bytes(purpose, &state.config.storage);
+    if body.size > max_bytes {
+        return Err(file_type::too_large(purpose, max_bytes));
+    }
+    let declared = file_type::allowed_type(purpose, &body.content_type)?;
+
+    let file = entity::file::ActiveModel {
+        key: Set(new_file_key(claims.user_id, declared)),
+        owner_id: Set(claims.user_id),
+        original_name: Set(body.file_name.clone()),
+        size: Set(body.size as i64),
+        content_type: Set(declared.mime.to_string()),
+        checksum: Set(None),
+        status: Set(FileStatus::Pending),
+        purpose: Set(purpose),
+        ..Default::default()
+    }
+    .insert(&state.db)
+    .await?;
+
+    let ttl = Duration::from_secs(state.config.storage.upload_url_ttl_seconds);
+    let PresignedRequest {
+        method,
+        url,
+        headers,
+    } = state
+        .storage
+        .presign_put(&file.key, &file.content_type, body.size, ttl)
+        .await?;
+
+    let response = PresignUploadResponse {
+        id: file.id,
+        file_key: file.key,
+        method,
+        upload_url: url,
+        headers,
+        expires_at: Utc::now() + ttl,
+    };
+
+    Ok(ApiResponse::new(
+        200,
+        "Upload URL created".to_string(),
+        response,
+    ))
+}
+
+/// Marks a direct upload `ready` once it has arrived, records its checksum
+/// and returns it like `/file/upload` does. Its content must be of the type
+/// it was signed for; files that fail a check, or are confirmed after they
+/// expired, are deleted. Only the uploader may confirm; confirming twice is harmless.
+#[post("/confirm")]
+pub async fn confirm_upload(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    body: ValidatedJson<FileKeyRequest>,
+) -> Result<ApiResponse<FileUploadResponse>, AppError> {
+    let file = entity::file::Entity::find()
+        .filter(entity::file::Column::Key.eq(&body.file_key))
+        .filter(entity::file::Column::OwnerId.eq(claims.user_id))
+        .one(&state.db)
+        .await?
+        .ok_or_else(file_not_found)?;
+
+    let pending_ttl = Duration::from_secs(state.config.storage.pending_upload_ttl_seconds);
+    let file = if file.status == FileStatus::Ready {
+        file
+    } else if upload_sweep::is_expired(&file, pending_ttl) {
+        // The sweep would delete it anyway.
+        return Err(reject(&state, &file, file_not_found()).await);
+    } else {
+        let meta = state
+            .storage
+            .head(&file.key)
+            .await?
+            .ok_or_else(object_not_found)?;
+
+        // The signature fixes the size, but the limit may have been lowered since.
+        let max_bytes = file_type::max_bytes(file.purpose, &state.config.storage);
+        if meta.size > max_bytes {
+            let error = file_type::too_large(file.purpose, max_bytes);
+            return Err(reject(&state, &file, error).await);
+        }
+
+        // The signature fixes the declared type, not what was sent under it.
+        // The object cannot be replaced once stored, so what is read here is
+        // what the checksum will keep describing.
+        let stored = read_back(&state, &file.key).await?;
+        let detected = sniff(&stored.head);
+        if let Err(error) = file_type::check(file.purpose, Some(&file.content_type), detected) {
+            return Err(reject(&state, &file, error).await);
         }
+
+        let mut file: entity::file::ActiveModel = file.into();
+        file.size = Set(stored.size as i64);
+        file.checksum = Set(Some(stored.checksum));
+        file.status = Set(FileStatus::Ready);
+        file.update(&state.db).await?
+    };
+
+    let response = upload_response(&state, "File upload confirmed", file).await?;
+
+    Ok(ApiResponse::new(
+        200,
+        "File upload confirmed".to_string(),
+        response,
+    ))
+}
+
+/// A short-lived URL that reads a private file. Owners and admins only.
+#[get("/presign-download")]
+pub async fn presign_download(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    query: web::Query<FileKeyQuery>,
+) -> Result<ApiResponse<DownloadUrlResponse>, AppError> {
+    let file = entity::file::Entity::find()
+        .filter(entity::file::Column::Key.eq(&query.file_key))
+        .one(&state.db)
+        .await?
+        .ok_or_else(file_not_found)?;
+
+    authorize(&claims, Action::ReadFile(&file))?;
+    if file.status != FileStatus::Ready {
+        return Err(object_not_found());
+    }
+
+    let ttl = download_ttl(&state);
+    let response = DownloadUrlResponse {
+        download_url: state.storage.presign_get(&file.key, ttl).await?,
+        file_key: file.key,
+        expires_at: Utc::now() + ttl,
+    };
+
+    Ok(ApiResponse::new(
+        200,
+        "Download URL created".to_string(),
+        response,
+    ))
+}
+
+/// The caller's own files, pending ones included.
+#[get("/list")]
+pub async fn list_files(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    params: ListParams,
+) -> Result<ApiResponse<Page<FileResponse>>, AppError> {
+    let plan = file_list_spec().plan(&params, &state.config.auth.jwt_secret)?;
+    let select =
+        entity::file::Entity::find().filter(entity::file::Column::OwnerId.eq(claims.user_id));
+    let page = plan.fetch(&state.db, select).await?;
+
+    let page = page.map(|file| FileResponse {
+        file_url: state.storage.url(&file.key),
+        file,
+        download_url: None,
+    });
+
+    Ok(ApiResponse::new(200, page.summary("Files"), page))
+}
+
+/// A file's metadata, with a fresh `download_url` once it is ready.
+#[get("/{id}")]
+pub async fn get_file(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    id: web::Path<String>,
+) -> Result<ApiResponse<FileResponse>, AppError> {
+    let file = find_file(&state, &id).await?;
+    authorize(&claims, Action::ReadFile(&file))?;
+
+    let download_url = if file.status == FileStatus::Ready {
+        Some(
+            state
+                .storage
+                .presign_get(&file.key, download_ttl(&state))
+                .await?,
+        )
+    } else {
+        None
+    };
+
+    let response = FileResponse {
+        file_url: state.storage.url(&file.key),
+        file,
+        download_url,
     };
 
-    if let Err(e) = file.read_to_end(&mut file_content) {
-        return Err(ApiResponse::new(
-            500,
-            format!("Failed to read file content: {e}"),
-            "".to_string(),
-        ));
+    Ok(ApiResponse::new(200, "File found".to_string(), response))
+}
+
+/// Deletes the object, then the row. A failure in between leaves the row,
+/// so retrying finishes the job; deleting a missing object is not an error.
+#[delete("/{id}")]
+pub async fn delete_file(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    id: web::Path<String>,
+) -> Result<ApiResponse<FileResponse>, AppError> {
+    let file = find_file(&state, &id).await?;
+    authorize(&claims, Action::DeleteFile(&file))?;
+
+    state.storage.delete(&file.key).await?;
+    entity::file::Entity::delete_by_id(file.id)
+        .exec(&state.db)
+        .await?;
+
+    let response = FileResponse {
+        file_url: state.storage.url(&file.key),
+        file,
+        download_url: None,
+    };
+
+    Ok(ApiResponse::new(200, "File deleted".to_string(), response))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+
+    use actix_web::{
+        App, HttpMessage,
+        dev::{Payload, Service},
+        error::PayloadError,
+        http::StatusCode,
+        test,
+    };
+    use entity::sea_orm_active_enums::Role;
+    use futures_util::{Stream, stream};
+    use sea_orm::DatabaseConnection;
+
+    use super::*;
+    use crate::utils::{
+        config::{Config, FileConfig},
+        password::PasswordService,
+        storage::{MemoryStorage, UrlSigner},
+        validation,
+        ws_hub::{Hub, Topics},
+        ws_ticket::WsTickets,
+    };
+
+    const BOUNDARY: &str = "test-boundary";
+
+    /// A multipart body with `data` as its `file` field, cut into small
+    /// chunks so the upload arrives in pieces as it would over a network.
+    fn multipart(data: &[u8]) -> Payload {
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; \
+             filename=\"photo.png\"\r\nContent-Type: image/png\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        let chunks: Vec<_> = body
+            .chunks(256)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let stream: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> =
+            Box::pin(stream::iter(chunks));
+        Payload::from(stream)
     }
 
-    // Upload to S3
-    let body = ByteStream::from(file_content);
-
-    let upload_result = state
-        .s3_client
-        .put_object()
-        .bucket(S3_BUCKET_NAME.clone())
-        .key(&file_key)
-        .body(body)
-        .content_type(&content_type)
-        .send()
+    #[actix_web::test]
+    async fn uploads_over_the_limit_are_refused_and_discarded() {
+        let config = Arc::new(
+            Config::from_sources(
+                |key| match key {
+                    "DATABASE_URL" => Some("postgres://unused".to_string()),
+                    "STORAGE_BACKEND" => Some("memory".to_string()),
+                    "STORAGE_MAX_AVATAR_BYTES" => Some("2048".to_string()),
+                    _ => None,
+                },
+                FileConfig::default(),
+            )
+            .unwrap(),
+        );
+        let storage = Arc::new(MemoryStorage::new(UrlSigner::new(&config)));
+        let state = web::Data::new(AppState {
+            // Nothing is stored in the database before the body is read.
+            db: DatabaseConnection::Disconnected,
+            storage: storage.clone(),
+            config: config.clone(),
+            passwords: Arc::new(PasswordService::new(config.password)),
+            ws_tickets: Arc::new(WsTickets::new(Duration::from_secs(30))),
+            hub: Hub::new(Topics::new(), &config.websocket, config.sse.replay_buffer),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(state)
+                .app_data(validation::multipart_config(&config.storage))
+                .wrap_fn(|req, srv| {
+                    req.extensions_mut().insert(JwtClaims {
+                        user_id: 7,
+                        exp: usize::MAX,
+                        iat: 0,
+                        email: "user@example.com".to_string(),
+                        role: Role::User,
+                        sid: "session".to_string(),
+                    });
+                    srv.call(req)
+                })
+                .service(upload_file),
+        )
         .await;
 
-    match upload_result {
-        Ok(_) => {
-            let file_url = format!(
-                "https://{}.s3.{}.amazonaws.com/{}",
-                S3_BUCKET_NAME.clone(),
-                AWS_REGION.clone(),
-                file_key
-            );
-
-            let response = FileUploadResponse {
-                message: "File uploaded successfully".to_string(),
-                file_url,
-                file_key,
-            };
-
-            Ok(ApiResponse::new(
-                200,
-                "File uploaded successfully".to_string(),
-                response,
+        // Over the limit only after the first bytes, once writing has begun.
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.resize(4096, 0);
+        let (req, _) = test::TestRequest::post()
+            .uri("/upload?purpose=avatar")
+            .insert_header((
+                "content-type",
+                format!("multipart/form-data; boundary={BOUNDARY}"),
             ))
-        }
-        Err(e) => Err(ApiResponse::new(
-            500,
-            format!("S3 upload failed: {e}"),
-            "".to_string(),
-        )),
+            .to_request()
+            .replace_payload(multipart(&data));
+        let response = test::call_service(&app, req).await;
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "PAYLOAD_TOO_LARGE");
+        assert!(storage.list("", 10).await.unwrap().is_empty());
     }
 }
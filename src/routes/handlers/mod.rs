@@ -1,5 +1,8 @@
 pub mod auth_handler;
+pub mod chat_handler;
 pub mod file_handler;
 pub mod post_handler;
+pub mod sse_handler;
+pub mod storage_handler;
 pub mod user_handler;
 pub mod websocket_handler;
@@ -1,329 +1,444 @@
-use crate::utils::{api_response::ApiResponse, app_state::AppState, jwt::JwtClaims};
+use crate::utils::{
+    api_response::ApiResponse,
+    app_error::{AppError, ErrorCode, FieldError, parse_id},
+    app_state::AppState,
+    events::{PostEventKind, publish_post_event},
+    jwt::JwtClaims,
+    list_query::{FieldKind, ListParams, ListSpec, Page},
+    policy::{Action, authorize},
+    search::TsQuery,
+    validation::{ValidatedJson, http_scheme, not_blank},
+};
 use actix_web::{delete, get, post, put, web};
-use chrono::{self, NaiveDate};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, Condition, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
-    Set,
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait,
+    FromQueryResult, LoaderTrait, QueryFilter, QuerySelect, Set,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use validator::Validate;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct CreatePostRequest {
-    pub user_id: String, // Accept as string from JSON
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = 200, message = "must be at most 200 characters")
+    )]
     pub title: String,
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = 50000, message = "must be at most 50000 characters")
+    )]
     pub text: String,
+    #[validate(
+        url(message = "must be a valid URL"),
+        custom(function = "http_scheme"),
+        length(max = 2048, message = "must be at most 2048 characters")
+    )]
     pub banner: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct UpdatePostRequest {
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = 200, message = "must be at most 200 characters")
+    )]
     pub title: String,
+    #[validate(
+        custom(function = "not_blank"),
+        length(max = 50000, message = "must be at most 50000 characters")
+    )]
     pub text: String,
+    #[validate(
+        url(message = "must be a valid URL"),
+        custom(function = "http_scheme"),
+        length(max = 2048, message = "must be at most 2048 characters")
+    )]
     pub banner: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct PaginationQuery {
-    pub page: Option<u64>,
-    pub limit: Option<u64>,
-    pub search: Option<String>,
-    pub sort_by: Option<String>,
-    pub sort_order: Option<String>,
-    pub start_date: Option<String>,
-    pub end_date: Option<String>,
+/// Post-specific options of `/post/posts/list`; shared listing parameters
+/// are read by [`ListParams`].
+#[derive(Debug, Deserialize)]
+pub struct PostListQuery {
+    pub include: Option<String>,
+    pub search_mode: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct PaginationMeta {
-    pub current_page: u64,
-    pub per_page: u64,
-    pub total_items: u64,
-    pub total_pages: u64,
+#[derive(Debug, Deserialize)]
+pub struct IncludeQuery {
+    pub include: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct PostsResponse {
-    pub posts: Vec<entity::post::Model>,
-    pub pagination: PaginationMeta,
+/// Related data requested with `?include=author`.
+#[derive(Debug, Default)]
+pub struct Includes {
+    pub author: bool,
 }
 
-#[get("/{id}")]
-pub async fn get_post(
-    state: web::Data<AppState>,
-    id: web::Path<String>,
-) -> Result<ApiResponse<entity::post::Model>, ApiResponse<String>> {
-    let post_id = id
-        .to_string()
-        .parse::<i32>()
-        .map_err(|_| ApiResponse::new(400, "Invalid post ID format".to_string(), "".to_string()))?;
-
-    let post = entity::post::Entity::find_by_id(post_id)
-        .one(&state.db)
-        .await
-        .map_err(|_| ApiResponse::new(500, "Database error".to_string(), "".to_string()))?;
-
-    match post {
-        Some(post) => Ok(ApiResponse::new(200, "Post found".to_string(), post)),
-        None => Err(ApiResponse::new(
-            404,
-            "Post not found".to_string(),
-            "".to_string(),
-        )),
+impl Includes {
+    pub fn parse(raw: Option<&str>) -> Result<Self, AppError> {
+        let mut includes = Includes::default();
+        for part in raw.unwrap_or("").split(',').map(str::trim) {
+            match part {
+                "" => {}
+                "author" => includes.author = true,
+                other => {
+                    return Err(AppError::Validation(vec![FieldError::new(
+                        "include",
+                        format!("Unknown include \"{other}\". Supported: author"),
+                    )]));
+                }
+            }
+        }
+        Ok(includes)
     }
 }
 
-#[get("/posts/list")]
-pub async fn posts(
-    state: web::Data<AppState>,
-    query: web::Query<PaginationQuery>,
-) -> Result<ApiResponse<PostsResponse>, ApiResponse<String>> {
-    let page = query.page.unwrap_or(1);
-    let per_page = query.limit.unwrap_or(10);
-
-    // Ensure page is at least 1
-    let page = if page < 1 { 1 } else { page };
-
-    // Limit per_page to reasonable bounds
-    let per_page = if per_page > 100 {
-        100
-    } else if per_page < 1 {
-        10
-    } else {
-        per_page
-    };
-
-    // Build query with filters
-    let mut query_builder = entity::post::Entity::find();
+/// How `search` is matched on the post list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Ranked full-text search over title and body (the default).
+    FullText,
+    /// Case-sensitive substring match on title and body.
+    Contains,
+}
 
-    // Search by title and text
-    if let Some(search_term) = &query.search {
-        if !search_term.is_empty() {
-            let search_condition =
-                Condition::any().add(entity::post::Column::Title.contains(search_term));
-            query_builder = query_builder.filter(search_condition);
+impl SearchMode {
+    pub fn parse(raw: Option<&str>) -> Result<Self, AppError> {
+        match raw.unwrap_or("fulltext") {
+            "fulltext" => Ok(SearchMode::FullText),
+            "contains" => Ok(SearchMode::Contains),
+            other => Err(AppError::Validation(vec![FieldError::new(
+                "search_mode",
+                format!("Unknown search mode \"{other}\". Supported: fulltext, contains"),
+            )])),
         }
     }
+}
 
-    // Date range filtering
-    if let Some(start_date_str) = &query.start_date {
-        match NaiveDate::parse_from_str(start_date_str, "%Y-%m-%d") {
-            Ok(start_date) => {
-                let start_datetime = start_date.and_hms_opt(0, 0, 0).unwrap().and_utc();
-                query_builder =
-                    query_builder.filter(entity::post::Column::CreatedAt.gte(start_datetime));
-            }
-            Err(_) => {
-                return Err(ApiResponse::new(
-                    400,
-                    "Invalid start_date format. Use YYYY-MM-DD".to_string(),
-                    "Bad Request".to_string(),
-                ));
-            }
+/// Generated `tsvector` over a post's title and body.
+const POST_SEARCH_VECTOR: &str = r#""post"."search_vector""#;
+
+/// Relevance and highlighted snippets for a full-text match. Matched words are
+/// wrapped in `<mark>` tags; the rest of the text is returned as stored.
+#[derive(Debug, Serialize, FromQueryResult)]
+pub struct SearchHit {
+    #[serde(skip_serializing)]
+    pub id: i32,
+    pub rank: f32,
+    pub title_highlight: String,
+    pub text_highlight: String,
+}
+
+/// Public fields of a user, as embedded in posts and presence listings.
+#[derive(Debug, Serialize)]
+pub struct AuthorSummary {
+    pub id: i32,
+    pub name: String,
+    pub avatar: Option<String>,
+}
+
+impl From<entity::user::Model> for AuthorSummary {
+    fn from(user: entity::user::Model) -> Self {
+        Self {
+            id: user.id,
+            name: user.name,
+            avatar: user.avatar,
         }
     }
+}
 
-    if let Some(end_date_str) = &query.end_date {
-        match NaiveDate::parse_from_str(end_date_str, "%Y-%m-%d") {
-            Ok(end_date) => {
-                let end_datetime = end_date.and_hms_opt(23, 59, 59).unwrap().and_utc();
-                query_builder =
-                    query_builder.filter(entity::post::Column::CreatedAt.lte(end_datetime));
-            }
-            Err(_) => {
-                return Err(ApiResponse::new(
-                    400,
-                    "Invalid end_date format. Use YYYY-MM-DD".to_string(),
-                    "Bad Request".to_string(),
-                ));
-            }
+/// A post, optionally with its author embedded.
+#[derive(Debug, Serialize)]
+pub struct PostResponse {
+    #[serde(flatten)]
+    pub post: entity::post::Model,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<AuthorSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<SearchHit>,
+}
+
+impl From<entity::post::Model> for PostResponse {
+    fn from(post: entity::post::Model) -> Self {
+        Self {
+            post,
+            author: None,
+            search: None,
         }
     }
+}
 
-    // Sorting
-    let sort_by = query.sort_by.as_deref().unwrap_or("created_at");
-    let sort_order = query.sort_order.as_deref().unwrap_or("desc");
-
-    match sort_by {
-        "title" => {
-            query_builder = if sort_order == "asc" {
-                query_builder.order_by_asc(entity::post::Column::Title)
-            } else {
-                query_builder.order_by_desc(entity::post::Column::Title)
-            };
-        }
-        "created_at" => {
-            query_builder = if sort_order == "asc" {
-                query_builder.order_by_asc(entity::post::Column::CreatedAt)
-            } else {
-                query_builder.order_by_desc(entity::post::Column::CreatedAt)
-            };
-        }
-        _ => {
-            query_builder = query_builder.order_by_desc(entity::post::Column::CreatedAt);
+impl From<(entity::post::Model, Option<entity::user::Model>)> for PostResponse {
+    fn from((post, author): (entity::post::Model, Option<entity::user::Model>)) -> Self {
+        Self {
+            post,
+            author: author.map(AuthorSummary::from),
+            search: None,
         }
     }
+}
 
-    let paginator = query_builder.paginate(&state.db, per_page);
+/// Sorts and filters allowed on `/post/posts/list`. `search` is handled by
+/// the handler, which adds full-text matching and relevance ranking.
+fn post_list_spec() -> ListSpec<entity::post::Entity> {
+    ListSpec::new(entity::post::Column::Id)
+        .sortable("created_at", entity::post::Column::CreatedAt)
+        .sortable("updated_at", entity::post::Column::UpdatedAt)
+        .sortable("title", entity::post::Column::Title)
+        .filterable("user_id", entity::post::Column::UserId, FieldKind::Integer)
+        .filterable(
+            "created_at",
+            entity::post::Column::CreatedAt,
+            FieldKind::Timestamp,
+        )
+        .filterable(
+            "updated_at",
+            entity::post::Column::UpdatedAt,
+            FieldKind::Timestamp,
+        )
+        .filterable("banner", entity::post::Column::Banner, FieldKind::Text)
+        .date_range(entity::post::Column::CreatedAt)
+        .default_sort("-created_at")
+        .relevance()
+}
 
-    let total_items = paginator.num_items().await.map_err(|db_err| {
-        ApiResponse::new(500, "Database error".to_string(), db_err.to_string())
-    })?;
+/// Ranks and highlights only the posts on the current page; `ts_headline`
+/// reads the whole document, so it is too costly to run over every match.
+async fn search_hits(
+    db: &DatabaseConnection,
+    ts_query: &TsQuery,
+    ids: Vec<i32>,
+) -> Result<HashMap<i32, SearchHit>, DbErr> {
+    let hits = entity::post::Entity::find()
+        .select_only()
+        .column(entity::post::Column::Id)
+        .column_as(ts_query.rank(POST_SEARCH_VECTOR), "rank")
+        .column_as(
+            ts_query.headline(r#""post"."title""#, ", HighlightAll=true"),
+            "title_highlight",
+        )
+        .column_as(
+            ts_query.headline(
+                r#""post"."text""#,
+                ", MaxFragments=2, MaxWords=30, MinWords=10",
+            ),
+            "text_highlight",
+        )
+        .filter(entity::post::Column::Id.is_in(ids))
+        .into_model::<SearchHit>()
+        .all(db)
+        .await?;
+    Ok(hits.into_iter().map(|hit| (hit.id, hit)).collect())
+}
 
-    let total_pages = paginator.num_pages().await.map_err(|db_err| {
-        ApiResponse::new(500, "Database error".to_string(), db_err.to_string())
-    })?;
+fn post_not_found() -> AppError {
+    AppError::NotFound(ErrorCode::PostNotFound, "Post not found".to_string())
+}
 
-    let posts = paginator.fetch_page(page - 1).await.map_err(|db_err| {
-        ApiResponse::new(500, "Database error".to_string(), db_err.to_string())
-    })?;
+#[get("/{id}")]
+pub async fn get_post(
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<IncludeQuery>,
+) -> Result<ApiResponse<PostResponse>, AppError> {
+    let post_id = parse_id(&id, "post")?;
+    let includes = Includes::parse(query.include.as_deref())?;
+
+    let find = entity::post::Entity::find_by_id(post_id);
+    let post: PostResponse = if includes.author {
+        find.find_also_related(entity::user::Entity)
+            .one(&state.db)
+            .await?
+            .map(PostResponse::from)
+    } else {
+        find.one(&state.db).await?.map(PostResponse::from)
+    }
+    .ok_or_else(post_not_found)?;
 
-    let pagination = PaginationMeta {
-        current_page: page,
-        per_page,
-        total_items,
-        total_pages,
+    Ok(ApiResponse::new(200, "Post found".to_string(), post))
+}
+
+#[get("/posts/list")]
+pub async fn posts(
+    state: web::Data<AppState>,
+    params: ListParams,
+    query: web::Query<PostListQuery>,
+) -> Result<ApiResponse<Page<PostResponse>>, AppError> {
+    let includes = Includes::parse(query.include.as_deref())?;
+    let search_mode = SearchMode::parse(query.search_mode.as_deref())?;
+    let mut plan = post_list_spec().plan(&params, &state.config.auth.jwt_secret)?;
+
+    let mut select = entity::post::Entity::find();
+
+    // Search by title and text. Full-text queries that reduce to nothing (only
+    // stop words or punctuation) fall back to a substring match.
+    let mut ts_query = None;
+    if let Some(search_term) = plan.search().map(str::to_string) {
+        let parsed = match search_mode {
+            SearchMode::FullText => TsQuery::parse(&search_term),
+            SearchMode::Contains => None,
+        };
+        match parsed {
+            Some(parsed) if parsed.has_lexemes(&state.db).await? => {
+                select = select.filter(parsed.matches(POST_SEARCH_VECTOR));
+                if plan.sorts_by_relevance() {
+                    plan.rank_by(parsed.rank(POST_SEARCH_VECTOR));
+                }
+                ts_query = Some(parsed);
+            }
+            _ => {
+                let search_condition = Condition::any()
+                    .add(entity::post::Column::Title.contains(&search_term))
+                    .add(entity::post::Column::Text.contains(&search_term));
+                select = select.filter(search_condition);
+            }
+        }
+    }
+
+    let page = plan.fetch(&state.db, select).await?;
+
+    // Authors are loaded for the whole page in one query.
+    let mut page = if includes.author {
+        let mut authors = page
+            .items
+            .load_one(entity::user::Entity, &state.db)
+            .await?
+            .into_iter();
+        page.map(|post| PostResponse::from((post, authors.next().flatten())))
+    } else {
+        page.map(PostResponse::from)
     };
 
-    let response = PostsResponse { posts, pagination };
+    if let Some(ts_query) = &ts_query {
+        let ids = page.items.iter().map(|post| post.post.id).collect();
+        let mut hits = search_hits(&state.db, ts_query, ids).await?;
+        for post in &mut page.items {
+            post.search = hits.remove(&post.post.id);
+        }
+    }
 
-    Ok(ApiResponse::new(
-        200,
-        format!("Posts found: {total_items} (page {page} of {total_pages})"),
-        response,
-    ))
+    Ok(ApiResponse::new(200, page.summary("Posts"), page))
 }
 
 #[post("/create")]
 pub async fn create_post(
     state: web::Data<AppState>,
-    body: web::Json<CreatePostRequest>,
-) -> Result<ApiResponse<entity::post::Model>, ApiResponse<String>> {
-    // Parse user_id from string to integer
-    let user_id = body
-        .user_id
-        .parse::<i32>()
-        .map_err(|_| ApiResponse::new(400, "Invalid user_id format".to_string(), "".to_string()))?;
-
+    claims: JwtClaims,
+    body: ValidatedJson<CreatePostRequest>,
+) -> Result<ApiResponse<entity::post::Model>, AppError> {
     let new_post = entity::post::ActiveModel {
-        user_id: Set(user_id),
+        user_id: Set(claims.user_id),
         title: Set(body.title.clone()),
         text: Set(body.text.clone()),
-        created_at: Set(chrono::Utc::now().naive_utc()),
-        updated_at: Set(chrono::Utc::now().naive_utc()),
         banner: Set(body.banner.clone()),
         ..Default::default()
     };
 
-    let post = new_post
-        .insert(&state.db)
-        .await
-        .map_err(|_| ApiResponse::new(500, "Database error".to_string(), "".to_string()))?;
+    let post = new_post.insert(&state.db).await?;
+    publish_post_event(&state.hub, PostEventKind::Created, claims.user_id, &post);
+
     Ok(ApiResponse::new(200, "Post created".to_string(), post))
 }
 
 #[put("/update/{id}")]
 pub async fn update_post(
     state: web::Data<AppState>,
-    body: web::Json<UpdatePostRequest>,
+    claims: JwtClaims,
+    body: ValidatedJson<UpdatePostRequest>,
     id: web::Path<String>,
-) -> Result<ApiResponse<entity::post::Model>, ApiResponse<String>> {
-    let post_id = id
-        .to_string()
-        .parse::<i32>()
-        .map_err(|_| ApiResponse::new(400, "Invalid post ID format".to_string(), "".to_string()))?;
+) -> Result<ApiResponse<entity::post::Model>, AppError> {
+    let post_id = parse_id(&id, "post")?;
 
     let post = entity::post::Entity::find_by_id(post_id)
         .one(&state.db)
-        .await
-        .map_err(|_| ApiResponse::new(500, "Database error".to_string(), "".to_string()))?;
-
-    match post {
-        Some(post) => {
-            let mut post_active: entity::post::ActiveModel = post.into();
-            post_active.title = Set(body.title.clone());
-            post_active.text = Set(body.text.clone());
-            post_active.updated_at = Set(chrono::Utc::now().naive_utc());
-            post_active.banner = Set(body.banner.clone());
-
-            let updated_post = post_active
-                .update(&state.db)
-                .await
-                .map_err(|_| ApiResponse::new(500, "Database error".to_string(), "".to_string()))?;
-
-            Ok(ApiResponse::new(
-                200,
-                "Post updated".to_string(),
-                updated_post,
-            ))
-        }
-        None => Err(ApiResponse::new(
-            404,
-            "Post not found".to_string(),
-            "".to_string(),
-        )),
-    }
+        .await?
+        .ok_or_else(post_not_found)?;
+
+    authorize(&claims, Action::UpdatePost(&post))?;
+
+    let mut post_active: entity::post::ActiveModel = post.into();
+    post_active.title = Set(body.title.clone());
+    post_active.text = Set(body.text.clone());
+    post_active.banner = Set(body.banner.clone());
+
+    let updated_post = post_active.update(&state.db).await?;
+    publish_post_event(
+        &state.hub,
+        PostEventKind::Updated,
+        claims.user_id,
+        &updated_post,
+    );
+
+    Ok(ApiResponse::new(
+        200,
+        "Post updated".to_string(),
+        updated_post,
+    ))
 }
 
 #[delete("/delete/{id}")]
 pub async fn delete_post(
     state: web::Data<AppState>,
+    claims: JwtClaims,
     id: web::Path<String>,
-) -> Result<ApiResponse<entity::post::Model>, ApiResponse<String>> {
-    let post_id = id
-        .to_string()
-        .parse::<i32>()
-        .map_err(|_| ApiResponse::new(400, "Invalid post ID format".to_string(), "".to_string()))?;
+) -> Result<ApiResponse<entity::post::Model>, AppError> {
+    let post_id = parse_id(&id, "post")?;
 
     let post = entity::post::Entity::find_by_id(post_id)
         .one(&state.db)
-        .await
-        .map_err(|_| ApiResponse::new(500, "Database error".to_string(), "".to_string()))?;
-
-    match post {
-        Some(post) => {
-            let post_clone = post.clone();
-            let post_active: entity::post::ActiveModel = post.into();
-            post_active
-                .delete(&state.db)
-                .await
-                .map_err(|_| ApiResponse::new(500, "Database error".to_string(), "".to_string()))?;
-
-            Ok(ApiResponse::new(
-                200,
-                "Post deleted".to_string(),
-                post_clone,
-            ))
-        }
-        None => Err(ApiResponse::new(
-            404,
-            "Post not found".to_string(),
-            "".to_string(),
-        )),
-    }
+        .await?
+        .ok_or_else(post_not_found)?;
+
+    authorize(&claims, Action::DeletePost(&post))?;
+
+    let post_clone = post.clone();
+    let post_active: entity::post::ActiveModel = post.into();
+    post_active.delete(&state.db).await?;
+    publish_post_event(
+        &state.hub,
+        PostEventKind::Deleted,
+        claims.user_id,
+        &post_clone,
+    );
+
+    Ok(ApiResponse::new(
+        200,
+        "Post deleted".to_string(),
+        post_clone,
+    ))
 }
 
 #[get("/posts/my-posts")]
 pub async fn get_posts_by_user(
     state: web::Data<AppState>,
     claims: JwtClaims,
-) -> Result<ApiResponse<Vec<entity::post::Model>>, ApiResponse<String>> {
+    query: web::Query<IncludeQuery>,
+) -> Result<ApiResponse<Vec<PostResponse>>, AppError> {
     let user_id = claims.user_id;
+    let includes = Includes::parse(query.include.as_deref())?;
+
+    let find = entity::post::Entity::find().filter(entity::post::Column::UserId.eq(user_id));
+    let user_posts: Vec<PostResponse> = if includes.author {
+        find.find_also_related(entity::user::Entity)
+            .all(&state.db)
+            .await?
+            .into_iter()
+            .map(PostResponse::from)
+            .collect()
+    } else {
+        find.all(&state.db)
+            .await?
+            .into_iter()
+            .map(PostResponse::from)
+            .collect()
+    };
 
-    let posts_result = entity::post::Entity::find()
-        .filter(entity::post::Column::UserId.eq(user_id))
-        .all(&state.db)
-        .await;
-
-    match posts_result {
-        Ok(user_posts) => Ok(ApiResponse::new(
-            200,
-            format!("Posts found: {}", user_posts.len()),
-            user_posts,
-        )),
-        Err(_) => Err(ApiResponse::new(
-            500,
-            "Database error".to_string(),
-            "".to_string(),
-        )),
-    }
+    Ok(ApiResponse::new(
+        200,
+        format!("Posts found: {}", user_posts.len()),
+        user_posts,
+    ))
 }
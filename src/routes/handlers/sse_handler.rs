@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, rt, web};
+use actix_web_lab::sse::{Data, Event, Sse};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    routes::handlers::websocket_handler::{authenticate, seconds_until},
+    utils::{
+        app_error::{AppError, ErrorCode, FieldError},
+        app_state::AppState,
+        events::{PresenceEventKind, publish_presence_event},
+        jwt::JwtClaims,
+        policy::TopicAccess,
+        session::is_session_active,
+        ws_hub::{Delivery, Hub, Registration, Resume},
+        ws_protocol::WsError,
+    },
+};
+
+/// Most topics one stream may follow.
+const MAX_TOPICS: usize = 32;
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// Comma-separated topics, as on the WebSocket.
+    pub topics: Option<String>,
+    pub ticket: Option<String>,
+    /// For clients that reconnect by hand; `EventSource` sends the
+    /// `Last-Event-ID` header itself.
+    pub last_event_id: Option<String>,
+}
+
+/// Topic errors become HTTP errors here, since there is no socket to report
+/// them on.
+fn topic_error(error: WsError) -> AppError {
+    match error.code {
+        ErrorCode::Forbidden => AppError::Forbidden(error.message),
+        code => AppError::BadRequest(code, error.message),
+    }
+}
+
+/// Streams hub messages on `topics` as Server-Sent Events, for clients that
+/// cannot keep a WebSocket open. Authenticated like `/ws`, minus the
+/// subprotocol. Each event's data is the WebSocket `message` frame.
+pub async fn event_stream(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    query: web::Query<EventsQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (claims, _) = authenticate(&req, &state, query.ticket.as_deref()).await?;
+
+    let mut topics: Vec<String> = Vec::new();
+    for topic in query
+        .topics
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+    {
+        if !topic.is_empty() && !topics.iter().any(|seen| seen == topic) {
+            topics.push(topic.to_string());
+        }
+    }
+    if topics.is_empty() || topics.len() > MAX_TOPICS {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "topics",
+            format!("must list between 1 and {MAX_TOPICS} topics"),
+        )]));
+    }
+    for topic in &topics {
+        state
+            .hub
+            .authorize(&claims, topic, TopicAccess::Subscribe)
+            .map_err(topic_error)?;
+    }
+
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|header| header.to_str().ok())
+        .or(query.last_event_id.as_deref())
+        .filter(|id| !id.is_empty());
+
+    let registration = state.hub.connect(&claims)?;
+    let resume = match last_event_id {
+        Some(last_event_id) => Some(state.hub.resume(registration.id, &topics, last_event_id)),
+        None => {
+            for topic in &topics {
+                state.hub.subscribe(registration.id, topic, false);
+            }
+            None
+        }
+    };
+
+    let (events, receiver) = mpsc::channel(state.hub.buffer());
+    let keep_alive = Duration::from_secs(state.config.sse.keep_alive_seconds);
+    rt::spawn(run_stream(
+        state.clone(),
+        claims,
+        registration,
+        resume,
+        events,
+    ));
+
+    Ok(Sse::from_infallible_receiver(receiver)
+        .with_keep_alive(keep_alive)
+        .respond_to(&req))
+}
+
+fn message_event(hub: &Hub, delivery: &Delivery) -> Event {
+    Data::new(delivery.frame.to_string())
+        .id(hub.event_id(delivery.seq))
+        .into()
+}
+
+/// Named event carrying an error code, such as `close` or `reset`.
+fn code_event(name: &'static str, code: ErrorCode) -> Data {
+    Data::new(serde_json::json!({ "code": code }).to_string()).event(name)
+}
+
+async fn run_stream(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    registration: Registration,
+    resume: Option<Resume>,
+    events: mpsc::Sender<Event>,
+) {
+    let hub = state.hub.clone();
+    let Registration {
+        id,
+        mut deliveries,
+        mut shutdown,
+        joined,
+    } = registration;
+    if joined {
+        publish_presence_event(&hub, PresenceEventKind::Join, claims.user_id);
+    }
+
+    let expiry = tokio::time::sleep(seconds_until(claims.exp));
+    tokio::pin!(expiry);
+    let period = Duration::from_secs(state.config.websocket.session_check_seconds);
+    let mut session_check = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+
+    let reason = 'stream: {
+        match resume {
+            Some(Resume::Replay(missed)) => {
+                for delivery in &missed {
+                    if events.send(message_event(&hub, delivery)).await.is_err() {
+                        break 'stream None;
+                    }
+                }
+            }
+            // An empty id clears the client's `Last-Event-ID`, so its next
+            // reconnect starts afresh instead of failing the same way.
+            Some(Resume::Gap) => {
+                let reset = code_event("reset", ErrorCode::EventsMissed).id("");
+                if events.send(reset.into()).await.is_err() {
+                    break 'stream None;
+                }
+            }
+            None => {}
+        }
+
+        loop {
+            tokio::select! {
+                _ = events.closed() => break 'stream None,
+                _ = &mut expiry => break 'stream Some(ErrorCode::AuthTokenExpired),
+                _ = shutdown.changed() => break 'stream Some(ErrorCode::ServerShuttingDown),
+                _ = session_check.tick() => match is_session_active(&state.db, &claims.sid).await {
+                    Ok(true) => {}
+                    Ok(false) => break 'stream Some(ErrorCode::AuthSessionRevoked),
+                    Err(e) => log::warn!("event stream session check failed: {e}"),
+                },
+                delivery = deliveries.recv() => match delivery {
+                    Some(delivery) => {
+                        if events.send(message_event(&hub, &delivery)).await.is_err() {
+                            break 'stream None;
+                        }
+                    }
+                    None => break 'stream Some(ErrorCode::SlowConsumer),
+                },
+            }
+        }
+    };
+
+    if hub.disconnect(id) {
+        publish_presence_event(&hub, PresenceEventKind::Leave, claims.user_id);
+    }
+    // Best effort: the stream ends once `events` is dropped.
+    if let Some(code) = reason {
+        events.try_send(code_event("close", code).into()).ok();
+    }
+}
@@ -0,0 +1,105 @@
+use actix_web::{HttpRequest, HttpResponse, http::header, web};
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+
+use crate::utils::{
+    app_error::{AppError, ErrorCode},
+    app_state::AppState,
+    storage::{UrlSigner, object_exists, object_not_found},
+};
+
+#[derive(Deserialize)]
+pub struct SignedQuery {
+    pub expires: Option<i64>,
+    pub signature: Option<String>,
+}
+
+fn invalid_signature() -> AppError {
+    AppError::Forbidden("Invalid or expired signature".to_string())
+}
+
+/// Streams an object of the `local` or `memory` backend to whoever holds a
+/// URL from `presign_get`. The signature is the only credential, so nothing
+/// is served without one.
+pub async fn serve_object(
+    state: web::Data<AppState>,
+    key: web::Path<String>,
+    query: web::Query<SignedQuery>,
+) -> Result<HttpResponse, AppError> {
+    let signer = UrlSigner::new(&state.config);
+    let signed = match (query.expires, query.signature.as_deref()) {
+        (Some(expires), Some(signature)) => signer.verify_get(&key, expires, signature),
+        _ => false,
+    };
+    if !signed {
+        return Err(invalid_signature());
+    }
+
+    let object = state
+        .storage
+        .get(&key)
+        .await?
+        .ok_or_else(object_not_found)?;
+    let content_type = object
+        .meta
+        .content_type
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((header::CACHE_CONTROL, "private, no-store"))
+        .no_chunking(object.meta.size)
+        .streaming(object.body))
+}
+
+/// Stores the body under `key` for whoever holds a request from
+/// `presign_put`. `Content-Type` and `Content-Length` must be the ones it was
+/// signed for, so the size and type of the object are fixed by the signer.
+/// Nothing stored under `key` is ever replaced, so an object cannot change
+/// after it was checked and confirmed.
+pub async fn upload_object(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    key: web::Path<String>,
+    query: web::Query<SignedQuery>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, AppError> {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let content_type = header(header::CONTENT_TYPE).unwrap_or_default();
+    let size = header(header::CONTENT_LENGTH).and_then(|value| value.parse::<u64>().ok());
+    let signed = match (size, query.expires, query.signature.as_deref()) {
+        (Some(size), Some(expires), Some(signature)) => {
+            UrlSigner::new(&state.config).verify_put(&key, content_type, size, expires, signature)
+        }
+        _ => false,
+    };
+    let Some(size) = size.filter(|_| signed) else {
+        return Err(invalid_signature());
+    };
+
+    // Spares reading the body; the writer still refuses a key taken meanwhile.
+    if state.storage.head(&key).await?.is_some() {
+        return Err(object_exists());
+    }
+    // Dropping the writer on error discards the upload
+    let mut writer = state.storage.create_writer(&key, content_type).await?;
+    let mut received = 0u64;
+    while let Some(chunk) = payload.try_next().await.map_err(|e| {
+        AppError::BadRequest(ErrorCode::InvalidRequest, format!("Upload failed: {e}"))
+    })? {
+        received += chunk.len() as u64;
+        writer.write(chunk).await?;
+    }
+    if received != size {
+        return Err(AppError::BadRequest(
+            ErrorCode::InvalidRequest,
+            format!("Expected {size} bytes, received {received}"),
+        ));
+    }
+    writer.finish().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
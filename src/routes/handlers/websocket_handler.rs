@@ -1,33 +1,511 @@
-use actix_web::{Error, HttpRequest, HttpResponse, rt, web};
-use actix_ws::{Message, handle};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    Error, HttpRequest, HttpResponse,
+    http::header::{self, HeaderValue},
+    rt, web,
+};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, ProtocolError, Session, handle};
+use entity::user;
 use futures_util::StreamExt as _;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    routes::{
+        handlers::post_handler::AuthorSummary,
+        middlewares::auth_middlewares::{session_revoked, verify_access_token},
+    },
+    utils::{
+        api_response::ApiResponse,
+        app_error::{AppError, ErrorCode},
+        app_state::AppState,
+        chat,
+        events::{PresenceEventKind, message_event, publish_presence_event, user_topic},
+        jwt::JwtClaims,
+        policy::TopicAccess,
+        session::is_session_active,
+        ws_hub::{ConnectionId, Registration},
+        ws_protocol::{
+            ClientMessage, ClientMessageType, PROTOCOL_VERSION, ReplayOptions, SendMessage,
+            ServerMessage, SubscribeOptions, WsError,
+        },
+    },
+};
+
+/// Subprotocol a client offers alongside its token, as in
+/// `Sec-WebSocket-Protocol: bearer, <token>`. Echoed back on success.
+const BEARER_PROTOCOL: &str = "bearer";
+
+#[derive(Deserialize)]
+pub struct WsQuery {
+    pub ticket: Option<String>,
+}
 
-/// WebSocket handler using modern actix-web v4 approach
+/// Authenticates the handshake, then upgrades. The claims stay with the
+/// connection for its whole life.
 pub async fn websocket_handler(
     req: HttpRequest,
     stream: web::Payload,
+    state: web::Data<AppState>,
+    query: web::Query<WsQuery>,
 ) -> Result<HttpResponse, Error> {
-    let (res, mut session, mut msg_stream) = handle(&req, stream)?;
+    let (claims, via_protocol) = authenticate(&req, &state, query.ticket.as_deref()).await?;
+    let registration = state.hub.connect(&claims)?;
+
+    let (mut res, session, msg_stream) = match handle(&req, stream) {
+        Ok(upgrade) => upgrade,
+        Err(e) => {
+            state.hub.disconnect(registration.id);
+            return Err(e);
+        }
+    };
+    if via_protocol {
+        res.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(BEARER_PROTOCOL),
+        );
+    }
+
+    rt::spawn(run_session(
+        state,
+        claims,
+        registration,
+        session,
+        msg_stream,
+    ));
+
+    Ok(res)
+}
+
+/// A user with live connections, and how many (one per open tab or device).
+#[derive(Debug, Serialize)]
+pub struct OnlineUser {
+    #[serde(flatten)]
+    pub user: AuthorSummary,
+    pub connections: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PresenceResponse {
+    pub users: Vec<OnlineUser>,
+}
+
+/// Lists users with a live WebSocket connection on this instance.
+pub async fn presence(
+    state: web::Data<AppState>,
+) -> Result<ApiResponse<PresenceResponse>, AppError> {
+    let online = state.hub.online_users();
+    let mut users: HashMap<i32, user::Model> = user::Entity::find()
+        .filter(user::Column::Id.is_in(online.iter().map(|(user_id, _)| *user_id)))
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+
+    let users: Vec<OnlineUser> = online
+        .into_iter()
+        .filter_map(|(user_id, connections)| {
+            users.remove(&user_id).map(|user| OnlineUser {
+                user: user.into(),
+                connections,
+            })
+        })
+        .collect();
+
+    Ok(ApiResponse::new(
+        200,
+        format!("Users online: {}", users.len()),
+        PresenceResponse { users },
+    ))
+}
+
+/// Accepts, in order: an `Authorization: Bearer` header, a token offered as
+/// the `bearer` subprotocol, or a ticket from `POST /auth/ws-ticket`. Returns
+/// whether the subprotocol was used, since it must then be echoed.
+pub async fn authenticate(
+    req: &HttpRequest,
+    state: &AppState,
+    ticket: Option<&str>,
+) -> Result<(JwtClaims, bool), AppError> {
+    if let Some(token) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+    {
+        return Ok((verify_access_token(state, token).await?, false));
+    }
+
+    if let Some(token) = protocol_token(req) {
+        return Ok((verify_access_token(state, token).await?, true));
+    }
+
+    if let Some(ticket) = ticket {
+        let claims = state
+            .ws_tickets
+            .redeem(&state.db, ticket)
+            .await?
+            .ok_or_else(|| {
+                AppError::Unauthorized(
+                    ErrorCode::AuthTokenInvalid,
+                    "Invalid or expired ticket".to_string(),
+                )
+            })?;
+        // The token behind the ticket may have expired or been revoked since.
+        if seconds_until(claims.exp).is_zero() {
+            return Err(AppError::Unauthorized(
+                ErrorCode::AuthTokenExpired,
+                "Token has expired".to_string(),
+            ));
+        }
+        if !is_session_active(&state.db, &claims.sid).await? {
+            return Err(session_revoked());
+        }
+        return Ok((claims, false));
+    }
+
+    Err(AppError::Unauthorized(
+        ErrorCode::AuthTokenMissing,
+        "Missing bearer token or ticket".to_string(),
+    ))
+}
+
+/// The entry following `bearer` in the offered subprotocols.
+fn protocol_token(req: &HttpRequest) -> Option<&str> {
+    let offered = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()?;
+    let mut protocols = offered.split(',').map(str::trim);
+    protocols.find(|protocol| *protocol == BEARER_PROTOCOL)?;
+    protocols.next().filter(|token| !token.is_empty())
+}
+
+pub fn seconds_until(exp: usize) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Duration::from_secs((exp as u64).saturating_sub(now))
+}
+
+/// Close frame carrying an error code as its reason.
+fn close(code: CloseCode, reason: ErrorCode) -> Option<CloseReason> {
+    Some(CloseReason {
+        code,
+        description: Some(reason.as_str().to_string()),
+    })
+}
+
+/// Token bucket for inbound messages: refills at `rate` per second and holds
+/// at most `rate` tokens, so a short burst is fine but a flood is not.
+struct RateLimit {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
 
-    rt::spawn(async move {
-        while let Some(Ok(msg)) = msg_stream.next().await {
-            match msg {
-                Message::Text(text) => {
-                    session.text(text).await.ok();
+impl RateLimit {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: rate.into(),
+            tokens: rate.into(),
+            refilled: Instant::now(),
+        }
+    }
+
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Per-socket state kept by the session task.
+struct Connection {
+    id: ConnectionId,
+    claims: JwtClaims,
+    /// Deliveries on acked subscriptions, by message id, with when they were
+    /// last sent.
+    unacked: HashMap<Arc<str>, (Arc<str>, Instant)>,
+}
+
+/// Result of a client request: frames to send first, then the ack payload.
+#[derive(Default)]
+struct Reply {
+    frames: Vec<String>,
+    payload: Value,
+}
+
+impl Connection {
+    /// Applies one client frame and returns the frames to send back, in
+    /// order. Acks from the client are not acked back.
+    async fn handle(&mut self, state: &AppState, text: &str) -> Vec<String> {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                let error = WsError::new(ErrorCode::InvalidMessage, e.to_string());
+                return vec![ServerMessage::error(None, &error).to_frame()];
+            }
+        };
+        let id = message.id.as_deref();
+        match self.apply(state, &message).await {
+            Ok(_) if matches!(message.kind, ClientMessageType::Ack) => Vec::new(),
+            Ok(mut reply) => {
+                reply.frames.push(
+                    ServerMessage::ack(id)
+                        .with_payload(reply.payload)
+                        .to_frame(),
+                );
+                reply.frames
+            }
+            Err(error) => vec![ServerMessage::error(id, &error).to_frame()],
+        }
+    }
+
+    async fn apply(&mut self, state: &AppState, message: &ClientMessage) -> Result<Reply, WsError> {
+        if message.v != PROTOCOL_VERSION {
+            return Err(WsError::new(
+                ErrorCode::UnsupportedVersion,
+                format!("Protocol version {PROTOCOL_VERSION} is the only one supported"),
+            ));
+        }
+
+        let hub = &state.hub;
+        let topic = || {
+            message
+                .topic
+                .as_deref()
+                .ok_or_else(|| WsError::new(ErrorCode::InvalidMessage, "topic is required"))
+        };
+        let invalid = |e: serde_json::Error| WsError::new(ErrorCode::InvalidMessage, e.to_string());
+        let mut reply = Reply::default();
+        match message.kind {
+            ClientMessageType::Subscribe => {
+                let topic = topic()?;
+                let options: Option<SubscribeOptions> =
+                    serde_json::from_value(message.payload.clone()).map_err(invalid)?;
+                hub.authorize(&self.claims, topic, TopicAccess::Subscribe)?;
+                hub.subscribe(self.id, topic, options.unwrap_or_default().ack);
+            }
+            ClientMessageType::Unsubscribe => hub.unsubscribe(self.id, topic()?),
+            ClientMessageType::Publish => {
+                let topic = topic()?;
+                hub.authorize(&self.claims, topic, TopicAccess::Publish)?;
+                hub.publish(topic, message.payload.clone());
+            }
+            ClientMessageType::Ack => {
+                let id = message
+                    .id
+                    .as_deref()
+                    .ok_or_else(|| WsError::new(ErrorCode::InvalidMessage, "ack requires an id"))?;
+                self.unacked.remove(id);
+            }
+            ClientMessageType::Send => {
+                let send: SendMessage =
+                    serde_json::from_value(message.payload.clone()).map_err(invalid)?;
+                let stored = chat::send_message(
+                    &state.db,
+                    hub,
+                    self.claims.user_id,
+                    send.conversation_id,
+                    &send.body,
+                )
+                .await?;
+                reply.payload = serde_json::to_value(&stored).expect("message serializes to JSON");
+            }
+            ClientMessageType::Replay => {
+                let options: Option<ReplayOptions> =
+                    serde_json::from_value(message.payload.clone()).map_err(invalid)?;
+                let options = options.unwrap_or_default();
+                let limit = options
+                    .limit
+                    .unwrap_or(chat::DEFAULT_REPLAY_LIMIT)
+                    .clamp(1, chat::MAX_REPLAY_LIMIT);
+                let mut messages =
+                    chat::replay(&state.db, self.claims.user_id, options.after_id, limit + 1)
+                        .await
+                        .map_err(AppError::from)?;
+                let more = messages.len() as u64 > limit;
+                messages.truncate(limit as usize);
+
+                // Same frames as live delivery on the user's own topic.
+                let topic = user_topic(self.claims.user_id);
+                reply.frames = messages
+                    .iter()
+                    .map(|message| {
+                        let id = Uuid::new_v4().to_string();
+                        ServerMessage::message(&topic, message_event(message), &id).to_frame()
+                    })
+                    .collect();
+                reply.payload = serde_json::json!({
+                    "replayed": messages.len(),
+                    "last_id": messages.last().map_or(options.after_id, |message| message.id),
+                    "more": more,
+                });
+            }
+        }
+        Ok(reply)
+    }
+}
+
+async fn run_session(
+    state: web::Data<AppState>,
+    claims: JwtClaims,
+    registration: Registration,
+    mut session: Session,
+    mut msg_stream: MessageStream,
+) {
+    let hub = state.hub.clone();
+    let config = &state.config.websocket;
+    let Registration {
+        id,
+        mut deliveries,
+        mut shutdown,
+        joined,
+    } = registration;
+    let user_id = claims.user_id;
+    if joined {
+        publish_presence_event(&hub, PresenceEventKind::Join, user_id);
+    }
+    let mut connection = Connection {
+        id,
+        claims,
+        unacked: HashMap::new(),
+    };
+
+    let expiry = tokio::time::sleep(seconds_until(connection.claims.exp));
+    tokio::pin!(expiry);
+
+    let period = Duration::from_secs(config.session_check_seconds);
+    let mut session_check = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+
+    let ack_timeout = Duration::from_secs(config.ack_timeout_seconds);
+    let mut redelivery =
+        tokio::time::interval_at(tokio::time::Instant::now() + ack_timeout, ack_timeout);
+
+    let heartbeat_interval = Duration::from_secs(config.heartbeat_interval_seconds);
+    let mut heartbeat = tokio::time::interval_at(
+        tokio::time::Instant::now() + heartbeat_interval,
+        heartbeat_interval,
+    );
+    let client_timeout = Duration::from_secs(config.client_timeout_seconds);
+    let idle_timeout = Duration::from_secs(config.idle_timeout_seconds);
+    // Any frame, including a pong, proves the peer is there; only messages
+    // count as activity.
+    let mut last_heard = Instant::now();
+    let mut last_message = Instant::now();
+    let mut rate_limit = RateLimit::new(config.messages_per_second);
+
+    let reason = 'session: loop {
+        tokio::select! {
+            _ = &mut expiry => break close(CloseCode::Policy, ErrorCode::AuthTokenExpired),
+            _ = shutdown.changed() => break close(CloseCode::Away, ErrorCode::ServerShuttingDown),
+            _ = session_check.tick() => match is_session_active(&state.db, &connection.claims.sid).await {
+                Ok(true) => {}
+                Ok(false) => break close(CloseCode::Policy, ErrorCode::AuthSessionRevoked),
+                // Keep the connection on a transient database error; the next
+                // check will catch a revocation.
+                Err(e) => log::warn!("websocket session check failed: {e}"),
+            },
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() >= client_timeout {
+                    break close(CloseCode::Policy, ErrorCode::HeartbeatTimeout);
+                }
+                if last_message.elapsed() >= idle_timeout && hub.subscription_count(connection.id) == 0 {
+                    break close(CloseCode::Policy, ErrorCode::IdleTimeout);
                 }
-                Message::Binary(bin) => {
-                    session.binary(bin).await.ok();
+                if session.ping(b"").await.is_err() {
+                    break None;
                 }
-                Message::Ping(msg) => {
-                    session.pong(&msg).await.ok();
+            }
+            delivery = deliveries.recv() => match delivery {
+                Some(delivery) => {
+                    if delivery.ack {
+                        if connection.unacked.len() >= hub.buffer() {
+                            break close(CloseCode::Again, ErrorCode::SlowConsumer);
+                        }
+                        connection
+                            .unacked
+                            .insert(delivery.id, (delivery.frame.clone(), Instant::now()));
+                    }
+                    if session.text(&*delivery.frame).await.is_err() {
+                        break None;
+                    }
+                }
+                // The hub dropped this connection because its queue filled up.
+                None => break close(CloseCode::Again, ErrorCode::SlowConsumer),
+            },
+            _ = redelivery.tick() => {
+                let now = Instant::now();
+                for (frame, sent_at) in connection.unacked.values_mut() {
+                    if now.duration_since(*sent_at) >= ack_timeout {
+                        *sent_at = now;
+                        session.text(&**frame).await.ok();
+                    }
                 }
-                Message::Close(_) => {
-                    break;
+            }
+            msg = msg_stream.next() => {
+                last_heard = Instant::now();
+                match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        last_message = last_heard;
+                        if text.len() > config.max_message_bytes {
+                            break close(CloseCode::Size, ErrorCode::PayloadTooLarge);
+                        }
+                        if !rate_limit.allow() {
+                            break close(CloseCode::Policy, ErrorCode::RateLimited);
+                        }
+                        for reply in connection.handle(&state, &text).await {
+                            // A failed send means the socket is gone; stop serving it.
+                            if session.text(reply).await.is_err() {
+                                break 'session None;
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Binary(_))) => {
+                        last_message = last_heard;
+                        if !rate_limit.allow() {
+                            break close(CloseCode::Policy, ErrorCode::RateLimited);
+                        }
+                        let error = WsError::new(
+                            ErrorCode::InvalidMessage,
+                            "Binary frames are not supported; send JSON text",
+                        );
+                        session.text(ServerMessage::error(None, &error).to_frame()).await.ok();
+                    }
+                    Some(Ok(Message::Ping(msg))) => {
+                        session.pong(&msg).await.ok();
+                    }
+                    Some(Ok(Message::Close(reason))) => break reason,
+                    Some(Ok(_)) => {}
+                    Some(Err(ProtocolError::Overflow)) => {
+                        break close(CloseCode::Size, ErrorCode::PayloadTooLarge);
+                    }
+                    Some(Err(_)) | None => break None,
                 }
-                _ => {}
             }
         }
-    });
+    };
 
-    Ok(res)
+    if hub.disconnect(connection.id) {
+        publish_presence_event(&hub, PresenceEventKind::Leave, user_id);
+    }
+    session.close(reason).await.ok();
 }
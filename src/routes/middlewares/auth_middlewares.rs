@@ -1,38 +1,64 @@
-use crate::utils::jwt::decode_jwt;
+use crate::utils::{
+    app_error::{AppError, ErrorCode},
+    app_state::AppState,
+    jwt::{JwtClaims, decode_jwt},
+    session::is_session_active,
+};
 use actix_web::{
     Error, HttpMessage,
     body::MessageBody,
     dev::{ServiceRequest, ServiceResponse},
-    error::ErrorInternalServerError,
     middleware::Next,
+    web,
 };
 
+/// Decodes an access token and checks the session it belongs to is still live.
+/// Shared by [`auth_middleware`] and the WebSocket handshake.
+pub async fn verify_access_token(state: &AppState, token: &str) -> Result<JwtClaims, AppError> {
+    let claims = decode_jwt(&state.config.auth, token.to_string()).map_err(AppError::from)?;
+
+    // Access tokens outlive a logout or a detected refresh token reuse, so check
+    // the session they belong to is still live.
+    let active = is_session_active(&state.db, &claims.sid)
+        .await
+        .map_err(AppError::from)?;
+    if !active {
+        return Err(session_revoked());
+    }
+
+    Ok(claims)
+}
+
+pub fn session_revoked() -> AppError {
+    AppError::Unauthorized(
+        ErrorCode::AuthSessionRevoked,
+        "Session has been revoked".to_string(),
+    )
+}
+
 pub async fn auth_middleware(
     req: ServiceRequest,
     next: Next<impl MessageBody>,
 ) -> Result<ServiceResponse<impl MessageBody>, Error> {
-    let auth_header = req.headers().get("Authorization");
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or_else(|| {
+            AppError::Unauthorized(
+                ErrorCode::AuthTokenMissing,
+                "Missing bearer token".to_string(),
+            )
+        })?;
 
-    if auth_header.is_none() {
-        return Err(ErrorInternalServerError("Unauthorized".to_string()));
-    }
+    let state = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or_else(|| AppError::Internal("AppState is not configured".to_string()))?;
 
-    let token = auth_header
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .trim_start_matches("Bearer ");
+    let claims = verify_access_token(state, token).await?;
 
-    let token = decode_jwt(token.to_string());
+    req.extensions_mut().insert(claims);
 
-    if token.is_err() {
-        return Err(ErrorInternalServerError("Unauthorized".to_string()));
-    }
-
-    req.extensions_mut().insert(token.unwrap());
-
-    // let user = User::find_by_id(token.unwrap().claims.sub).await.unwrap();
-    next.call(req)
-        .await
-        .map_err(|_| ErrorInternalServerError("Error".to_string()))
+    next.call(req).await
 }
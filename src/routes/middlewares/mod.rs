@@ -1 +1,2 @@
 pub mod auth_middlewares;
+pub mod request_id_middlewares;
@@ -0,0 +1,54 @@
+use crate::utils::request_id::{self, REQUEST_ID_HEADER};
+use actix_web::{
+    Error,
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+};
+use uuid::Uuid;
+
+/// Tags every request with an id, taken from `X-Request-Id` when the client sends a
+/// sane one, and echoes it back. Errors are rendered inside the request scope so
+/// their bodies can include the id.
+pub async fn request_id_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            !value.is_empty()
+                && value.len() <= 128
+                && value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let header = HeaderValue::from_str(&id).ok();
+    match request_id::scope(id.clone(), next.call(req)).await {
+        Ok(mut res) => {
+            if let Some(header) = header {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), header);
+            }
+            Ok(res.map_into_boxed_body())
+        }
+        Err(err) => {
+            // The request has been consumed by now, so render the error here and
+            // hand the finished response back through an `InternalError`.
+            let mut response = request_id::sync_scope(id, || err.error_response());
+            if let Some(header) = header {
+                response
+                    .headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), header);
+            }
+            Err(InternalError::from_response(err.to_string(), response).into())
+        }
+    }
+}
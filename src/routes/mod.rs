@@ -1,7 +1,10 @@
 pub mod auth_routes;
+pub mod chat_routes;
 pub mod file_routes;
 pub mod handlers;
 pub mod middlewares;
 pub mod post_routes;
+pub mod sse_routes;
+pub mod storage_routes;
 pub mod user_routes;
 pub mod websocket_routes;
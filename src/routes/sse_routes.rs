@@ -0,0 +1,8 @@
+use actix_web::web;
+
+use super::handlers::sse_handler::event_stream;
+
+/// Configure the Server-Sent Events stream, a fallback for `/ws`
+pub fn sse_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/events").route(web::get().to(event_stream)));
+}
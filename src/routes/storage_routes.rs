@@ -0,0 +1,12 @@
+use actix_web::web;
+
+use super::handlers::storage_handler::{serve_object, upload_object};
+
+/// Configure signed downloads and uploads for the `local` and `memory` storage backends
+pub fn storage_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/storage/{key:.+}")
+            .route(web::get().to(serve_object))
+            .route(web::put().to(upload_object)),
+    );
+}
@@ -1,21 +1,38 @@
-use actix_web::{HttpResponse, Result, web};
+use actix_web::{HttpResponse, Result, middleware::from_fn, web};
 
-use super::handlers::websocket_handler::websocket_handler;
+use super::{
+    handlers::websocket_handler::{presence, websocket_handler},
+    middlewares::auth_middlewares,
+};
+use crate::utils::app_state::AppState;
 
 /// Configure websocket routes
 pub fn websocket_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/ws").route(web::get().to(websocket_handler)));
 }
 
-/// Health check endpoint for websocket service
-pub async fn websocket_health() -> Result<HttpResponse> {
+/// Health check endpoint for websocket service, with live counts for this
+/// instance
+pub async fn websocket_health(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let stats = state.hub.stats();
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "status": "WebSocket service is running",
-        "endpoint": "/ws"
+        "endpoint": "/ws",
+        "connections": stats.connections,
+        "users": stats.users,
+        "topics": stats.topics,
     })))
 }
 
-/// Configure websocket API routes with health check
+/// Configure websocket API routes with health check and presence
 pub fn websocket_api_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::scope("/api/websocket").route("/health", web::get().to(websocket_health)));
+    cfg.service(
+        web::scope("/api/websocket")
+            .route("/health", web::get().to(websocket_health))
+            .service(
+                web::resource("/presence")
+                    .wrap(from_fn(auth_middlewares::auth_middleware))
+                    .route(web::get().to(presence)),
+            ),
+    );
 }
@@ -1,6 +1,4 @@
-use actix_web::{
-    HttpRequest, HttpResponse, Responder, ResponseError, body::BoxBody, http::StatusCode,
-};
+use actix_web::{HttpRequest, HttpResponse, Responder, body::BoxBody, http::StatusCode};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -30,28 +28,6 @@ impl<T> std::fmt::Debug for ApiResponse<T> {
     }
 }
 
-impl<T> std::fmt::Display for ApiResponse<T>
-where
-    T: Serialize,
-{
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
-    }
-}
-
-impl<T> ResponseError for ApiResponse<T>
-where
-    T: Serialize,
-{
-    fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(StatusCode::from_u16(self.status).unwrap())
-            .content_type("application/json")
-            .body(serde_json::to_string(&self).unwrap_or_else(|_| {
-                r#"{"status": 500, "message": "Serialization error", "data": null}"#.to_string()
-            }))
-    }
-}
-
 impl<T> Responder for ApiResponse<T>
 where
     T: Serialize,
@@ -0,0 +1,331 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode};
+use sea_orm::{DbErr, SqlErr};
+use serde::Serialize;
+
+use crate::utils::request_id;
+
+/// Stable, machine-readable error codes. Clients should branch on these rather
+/// than on message text, so existing codes must never be renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    AuthTokenMissing,
+    AuthTokenInvalid,
+    AuthTokenExpired,
+    AuthSessionRevoked,
+    AuthInvalidCredentials,
+    AuthRefreshTokenInvalid,
+    AuthRefreshTokenReused,
+    Forbidden,
+    InvalidRequest,
+    InvalidId,
+    InvalidCursor,
+    ValidationFailed,
+    UserNotFound,
+    PostNotFound,
+    ConversationNotFound,
+    MessageNotFound,
+    FileNotFound,
+    ObjectNotFound,
+    ResourceConflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    FileTypeNotAllowed,
+    FileTypeMismatch,
+    MultipartInvalid,
+    FileNameRequired,
+    StorageError,
+    DatabaseError,
+    InternalError,
+    InvalidMessage,
+    UnsupportedVersion,
+    UnknownTopic,
+    SlowConsumer,
+    TooManyConnections,
+    ServerShuttingDown,
+    RateLimited,
+    HeartbeatTimeout,
+    IdleTimeout,
+    EventsMissed,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::AuthTokenMissing => "AUTH_TOKEN_MISSING",
+            ErrorCode::AuthTokenInvalid => "AUTH_TOKEN_INVALID",
+            ErrorCode::AuthTokenExpired => "AUTH_TOKEN_EXPIRED",
+            ErrorCode::AuthSessionRevoked => "AUTH_SESSION_REVOKED",
+            ErrorCode::AuthInvalidCredentials => "AUTH_INVALID_CREDENTIALS",
+            ErrorCode::AuthRefreshTokenInvalid => "AUTH_REFRESH_TOKEN_INVALID",
+            ErrorCode::AuthRefreshTokenReused => "AUTH_REFRESH_TOKEN_REUSED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::InvalidId => "INVALID_ID",
+            ErrorCode::InvalidCursor => "INVALID_CURSOR",
+            ErrorCode::ValidationFailed => "VALIDATION_FAILED",
+            ErrorCode::UserNotFound => "USER_NOT_FOUND",
+            ErrorCode::PostNotFound => "POST_NOT_FOUND",
+            ErrorCode::ConversationNotFound => "CONVERSATION_NOT_FOUND",
+            ErrorCode::MessageNotFound => "MESSAGE_NOT_FOUND",
+            ErrorCode::FileNotFound => "FILE_NOT_FOUND",
+            ErrorCode::ObjectNotFound => "OBJECT_NOT_FOUND",
+            ErrorCode::ResourceConflict => "RESOURCE_CONFLICT",
+            ErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ErrorCode::UnsupportedMediaType => "UNSUPPORTED_MEDIA_TYPE",
+            ErrorCode::FileTypeNotAllowed => "FILE_TYPE_NOT_ALLOWED",
+            ErrorCode::FileTypeMismatch => "FILE_TYPE_MISMATCH",
+            ErrorCode::MultipartInvalid => "MULTIPART_INVALID",
+            ErrorCode::FileNameRequired => "FILE_NAME_REQUIRED",
+            ErrorCode::StorageError => "STORAGE_ERROR",
+            ErrorCode::DatabaseError => "DATABASE_ERROR",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+            ErrorCode::InvalidMessage => "INVALID_MESSAGE",
+            ErrorCode::UnsupportedVersion => "UNSUPPORTED_VERSION",
+            ErrorCode::UnknownTopic => "UNKNOWN_TOPIC",
+            ErrorCode::SlowConsumer => "SLOW_CONSUMER",
+            ErrorCode::TooManyConnections => "TOO_MANY_CONNECTIONS",
+            ErrorCode::ServerShuttingDown => "SERVER_SHUTTING_DOWN",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::HeartbeatTimeout => "HEARTBEAT_TIMEOUT",
+            ErrorCode::IdleTimeout => "IDLE_TIMEOUT",
+            ErrorCode::EventsMissed => "EVENTS_MISSED",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// A problem with a single input field.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Error type returned by every handler and middleware.
+///
+/// Server-side failures keep their cause for logging but only expose a generic
+/// message to the client.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(ErrorCode, String),
+    Unauthorized(ErrorCode, String),
+    Forbidden(String),
+    NotFound(ErrorCode, String),
+    Conflict(ErrorCode, String),
+    PayloadTooLarge(ErrorCode, String),
+    UnsupportedMediaType(ErrorCode, String),
+    TooManyRequests(ErrorCode, String),
+    ServiceUnavailable(ErrorCode, String),
+    Validation(Vec<FieldError>),
+    Database(DbErr),
+    Storage(String),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::BadRequest(code, _)
+            | AppError::Unauthorized(code, _)
+            | AppError::NotFound(code, _)
+            | AppError::Conflict(code, _)
+            | AppError::PayloadTooLarge(code, _)
+            | AppError::UnsupportedMediaType(code, _)
+            | AppError::TooManyRequests(code, _)
+            | AppError::ServiceUnavailable(code, _) => *code,
+            AppError::Forbidden(_) => ErrorCode::Forbidden,
+            AppError::Validation(_) => ErrorCode::ValidationFailed,
+            AppError::Database(_) => ErrorCode::DatabaseError,
+            AppError::Storage(_) => ErrorCode::StorageError,
+            AppError::Internal(_) => ErrorCode::InternalError,
+        }
+    }
+
+    pub fn public_message(&self) -> String {
+        match self {
+            AppError::BadRequest(_, message)
+            | AppError::Unauthorized(_, message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(_, message)
+            | AppError::Conflict(_, message)
+            | AppError::PayloadTooLarge(_, message)
+            | AppError::UnsupportedMediaType(_, message)
+            | AppError::TooManyRequests(_, message)
+            | AppError::ServiceUnavailable(_, message) => message.clone(),
+            AppError::Validation(_) => "Request validation failed".to_string(),
+            AppError::Database(_) => "Database error".to_string(),
+            AppError::Storage(_) => "Storage service error".to_string(),
+            AppError::Internal(_) => "Internal server error".to_string(),
+        }
+    }
+
+    pub fn details(&self) -> Option<&[FieldError]> {
+        match self {
+            AppError::Validation(details) => Some(details),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Database(e) => write!(f, "{}: {e}", self.code().as_str()),
+            AppError::Storage(e) | AppError::Internal(e) => {
+                write!(f, "{}: {e}", self.code().as_str())
+            }
+            _ => write!(f, "{}: {}", self.code().as_str(), self.public_message()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: ErrorCode,
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a [FieldError]>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse<'a> {
+    status: u16,
+    message: String,
+    data: Option<()>,
+    error: ErrorBody<'a>,
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(..) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(..) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(..) => StatusCode::NOT_FOUND,
+            AppError::Conflict(..) => StatusCode::CONFLICT,
+            AppError::PayloadTooLarge(..) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(..) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ServiceUnavailable(..) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Storage(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let request_id = request_id::current();
+
+        if status.is_server_error() {
+            log::error!(
+                "request {} failed: {self}",
+                request_id.as_deref().unwrap_or("-")
+            );
+        }
+
+        let body = ErrorResponse {
+            status: status.as_u16(),
+            message: self.public_message(),
+            data: None,
+            error: ErrorBody {
+                code: self.code(),
+                request_id,
+                details: self.details(),
+            },
+        };
+
+        HttpResponse::build(status).json(body)
+    }
+}
+
+impl From<DbErr> for AppError {
+    fn from(e: DbErr) -> Self {
+        match e.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(_)) => AppError::Conflict(
+                ErrorCode::ResourceConflict,
+                "Resource already exists".to_string(),
+            ),
+            _ => AppError::Database(e),
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        match e.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => {
+                AppError::Unauthorized(ErrorCode::AuthTokenExpired, "Token has expired".to_string())
+            }
+            _ => AppError::Unauthorized(ErrorCode::AuthTokenInvalid, "Invalid token".to_string()),
+        }
+    }
+}
+
+impl<E, R> From<aws_sdk_s3::error::SdkError<E, R>> for AppError
+where
+    E: std::error::Error + 'static,
+    R: std::fmt::Debug,
+{
+    fn from(e: aws_sdk_s3::error::SdkError<E, R>) -> Self {
+        AppError::Storage(aws_sdk_s3::error::DisplayErrorContext(e).to_string())
+    }
+}
+
+impl From<actix_multipart::MultipartError> for AppError {
+    fn from(e: actix_multipart::MultipartError) -> Self {
+        AppError::from(&e)
+    }
+}
+
+impl From<&actix_multipart::MultipartError> for AppError {
+    fn from(e: &actix_multipart::MultipartError) -> Self {
+        use actix_multipart::MultipartError;
+        match e {
+            MultipartError::ContentTypeMissing | MultipartError::ContentTypeIncompatible => {
+                AppError::UnsupportedMediaType(
+                    ErrorCode::UnsupportedMediaType,
+                    "Content-Type must be multipart/form-data".to_string(),
+                )
+            }
+            MultipartError::Payload(actix_web::error::PayloadError::Overflow) => {
+                AppError::PayloadTooLarge(
+                    ErrorCode::PayloadTooLarge,
+                    "Multipart body is too large".to_string(),
+                )
+            }
+            MultipartError::MissingField(name) => AppError::BadRequest(
+                ErrorCode::MultipartInvalid,
+                format!("Multipart field `{name}` is required"),
+            ),
+            e => AppError::BadRequest(ErrorCode::MultipartInvalid, e.to_string()),
+        }
+    }
+}
+
+impl From<actix_web::error::BlockingError> for AppError {
+    fn from(e: actix_web::error::BlockingError) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
+
+/// Parses a numeric path segment such as `/post/{id}`.
+pub fn parse_id(raw: &str, what: &str) -> Result<i32, AppError> {
+    raw.parse::<i32>().map_err(|_| {
+        AppError::BadRequest(ErrorCode::InvalidId, format!("Invalid {what} ID format"))
+    })
+}
@@ -1,6 +1,19 @@
+use std::sync::Arc;
+
 use sea_orm::DatabaseConnection;
 
+use crate::utils::{
+    config::Config, password::PasswordService, storage::ObjectStore, ws_hub::Hub,
+    ws_ticket::WsTickets,
+};
+
 pub struct AppState {
     pub db: DatabaseConnection,
-    pub s3_client: aws_sdk_s3::Client,
+    /// Where uploaded files are kept, chosen by `STORAGE_BACKEND`.
+    pub storage: Arc<dyn ObjectStore>,
+    pub config: Arc<Config>,
+    pub passwords: Arc<PasswordService>,
+    pub ws_tickets: Arc<WsTickets>,
+    /// Publishes to WebSocket topics.
+    pub hub: Hub,
 }
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use entity::{conversation, conversation_member, message};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseBackend, DatabaseConnection,
+    DbErr, EntityTrait, FromQueryResult, QueryFilter, QueryOrder, QuerySelect, Set, Statement,
+    TransactionTrait,
+    sea_query::{Expr, Query},
+};
+
+use crate::utils::{
+    app_error::{AppError, ErrorCode, FieldError},
+    events::{publish_message_event, publish_read_event},
+    ws_hub::Hub,
+};
+
+/// Longest message body accepted, in characters.
+pub const MAX_BODY_CHARS: usize = 4000;
+pub const DEFAULT_REPLAY_LIMIT: u64 = 100;
+pub const MAX_REPLAY_LIMIT: u64 = 500;
+
+pub fn conversation_not_found() -> AppError {
+    AppError::NotFound(
+        ErrorCode::ConversationNotFound,
+        "Conversation not found".to_string(),
+    )
+}
+
+/// The caller's membership. Conversations they are not in are reported as
+/// missing, so ids cannot be probed.
+pub async fn membership<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: i32,
+    user_id: i32,
+) -> Result<conversation_member::Model, AppError> {
+    conversation_member::Entity::find_by_id((conversation_id, user_id))
+        .one(db)
+        .await?
+        .ok_or_else(conversation_not_found)
+}
+
+pub async fn member_ids<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: i32,
+) -> Result<Vec<i32>, DbErr> {
+    conversation_member::Entity::find()
+        .select_only()
+        .column(conversation_member::Column::UserId)
+        .filter(conversation_member::Column::ConversationId.eq(conversation_id))
+        .into_tuple()
+        .all(db)
+        .await
+}
+
+/// Ids of the conversations `user_id` belongs to, as a subquery.
+pub fn conversations_of(user_id: i32) -> sea_orm::sea_query::SelectStatement {
+    Query::select()
+        .column(conversation_member::Column::ConversationId)
+        .from(conversation_member::Entity)
+        .and_where(conversation_member::Column::UserId.eq(user_id))
+        .to_owned()
+}
+
+/// Stores a message and publishes it to every member once committed. The
+/// sender's read receipt moves to it, so their own messages never count as
+/// unread.
+pub async fn send_message(
+    db: &DatabaseConnection,
+    hub: &Hub,
+    sender_id: i32,
+    conversation_id: i32,
+    body: &str,
+) -> Result<message::Model, AppError> {
+    if body.trim().is_empty() {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "body",
+            "must not be blank",
+        )]));
+    }
+    if body.chars().count() > MAX_BODY_CHARS {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "body",
+            format!("must be at most {MAX_BODY_CHARS} characters"),
+        )]));
+    }
+    membership(db, conversation_id, sender_id).await?;
+
+    let txn = db.begin().await?;
+    let message = message::ActiveModel {
+        conversation_id: Set(conversation_id),
+        sender_id: Set(sender_id),
+        body: Set(body.to_string()),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await?;
+    conversation::Entity::update_many()
+        .col_expr(conversation::Column::LastMessageId, Expr::value(message.id))
+        .filter(conversation::Column::Id.eq(conversation_id))
+        .exec(&txn)
+        .await?;
+    conversation_member::Entity::update_many()
+        .col_expr(
+            conversation_member::Column::LastReadMessageId,
+            Expr::value(message.id),
+        )
+        .filter(conversation_member::Column::ConversationId.eq(conversation_id))
+        .filter(conversation_member::Column::UserId.eq(sender_id))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+
+    let members = member_ids(db, conversation_id).await?;
+    publish_message_event(hub, &members, &message);
+    Ok(message)
+}
+
+/// Moves the caller's read receipt up to `message_id`. Receipts never move
+/// back; members are only told when it actually moved.
+pub async fn mark_read(
+    db: &DatabaseConnection,
+    hub: &Hub,
+    user_id: i32,
+    conversation_id: i32,
+    message_id: i32,
+) -> Result<conversation_member::Model, AppError> {
+    let member = membership(db, conversation_id, user_id).await?;
+    message::Entity::find_by_id(message_id)
+        .filter(message::Column::ConversationId.eq(conversation_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(ErrorCode::MessageNotFound, "Message not found".to_string())
+        })?;
+
+    // Conditional so a concurrent, older receipt cannot move it back.
+    let moved = conversation_member::Entity::update_many()
+        .col_expr(
+            conversation_member::Column::LastReadMessageId,
+            Expr::value(message_id),
+        )
+        .filter(conversation_member::Column::ConversationId.eq(conversation_id))
+        .filter(conversation_member::Column::UserId.eq(user_id))
+        .filter(
+            Condition::any()
+                .add(conversation_member::Column::LastReadMessageId.is_null())
+                .add(conversation_member::Column::LastReadMessageId.lt(message_id)),
+        )
+        .exec(db)
+        .await?
+        .rows_affected
+        > 0;
+    if !moved {
+        return Ok(member);
+    }
+
+    let members = member_ids(db, conversation_id).await?;
+    publish_read_event(hub, &members, conversation_id, user_id, message_id);
+    membership(db, conversation_id, user_id).await
+}
+
+/// Messages above `after_id` in the user's conversations, oldest first.
+pub async fn replay(
+    db: &DatabaseConnection,
+    user_id: i32,
+    after_id: i32,
+    limit: u64,
+) -> Result<Vec<message::Model>, DbErr> {
+    message::Entity::find()
+        .filter(message::Column::Id.gt(after_id))
+        .filter(message::Column::ConversationId.in_subquery(conversations_of(user_id)))
+        .order_by_asc(message::Column::Id)
+        .limit(limit)
+        .all(db)
+        .await
+}
+
+#[derive(FromQueryResult)]
+struct UnreadRow {
+    conversation_id: i32,
+    unread: i64,
+}
+
+/// Unread messages per conversation: those above the user's read receipt
+/// and sent by someone else. Conversations with none are left out.
+pub async fn unread_counts(
+    db: &DatabaseConnection,
+    user_id: i32,
+) -> Result<HashMap<i32, u64>, DbErr> {
+    let rows = UnreadRow::find_by_statement(Statement::from_sql_and_values(
+        DatabaseBackend::Postgres,
+        r#"SELECT cm.conversation_id, COUNT(*) AS unread
+           FROM conversation_member cm
+           JOIN message m ON m.conversation_id = cm.conversation_id
+               AND m.id > COALESCE(cm.last_read_message_id, 0)
+               AND m.sender_id <> cm.user_id
+           WHERE cm.user_id = $1
+           GROUP BY cm.conversation_id"#,
+        [user_id.into()],
+    ))
+    .all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.conversation_id, row.unread as u64))
+        .collect())
+}
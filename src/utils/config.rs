@@ -0,0 +1,984 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::utils::password::HashParams;
+
+const DEV_JWT_SECRET: &str = "secret";
+const MIN_PROD_JWT_SECRET_LEN: usize = 32;
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const MAX_WS_FRAME_BYTES: usize = 65_536;
+/// S3 rejects presigned requests valid for longer than a week.
+const MAX_PRESIGN_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+/// Deployment profile, selected with `APP_PROFILE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Dev,
+    Test,
+    Prod,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "dev" | "development" => Ok(Profile::Dev),
+            "test" => Ok(Profile::Test),
+            "prod" | "production" => Ok(Profile::Prod),
+            other => Err(format!(
+                "APP_PROFILE must be one of dev, test, prod (got \"{other}\")"
+            )),
+        }
+    }
+}
+
+/// How WebSocket broadcasts reach other instances, selected with `WS_BROADCAST`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastBackend {
+    /// Only sockets on this instance receive a broadcast.
+    Local,
+    /// Broadcasts are also relayed through Postgres `LISTEN`/`NOTIFY`.
+    Postgres,
+}
+
+impl FromStr for BroadcastBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "local" => Ok(BroadcastBackend::Local),
+            "postgres" => Ok(BroadcastBackend::Postgres),
+            other => Err(format!(
+                "WS_BROADCAST must be one of local, postgres (got \"{other}\")"
+            )),
+        }
+    }
+}
+
+/// Where uploaded files are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    S3,
+    /// A directory on this instance's disk.
+    Local,
+    /// Process memory; lost on restart and not shared between instances.
+    Memory,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "s3" => Ok(StorageBackend::S3),
+            "local" => Ok(StorageBackend::Local),
+            "memory" => Ok(StorageBackend::Memory),
+            other => Err(format!(
+                "STORAGE_BACKEND must be one of s3, local, memory (got \"{other}\")"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub url: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub jwt_secret: String,
+    pub access_token_ttl_minutes: u32,
+    pub refresh_token_ttl_days: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// Only required with the `s3` storage backend.
+    pub bucket: String,
+    pub region: String,
+    /// Endpoint of an S3-compatible service such as MinIO, LocalStack or R2.
+    /// AWS itself when unset.
+    pub endpoint_url: Option<String>,
+    /// Address buckets as `{endpoint}/{bucket}` rather than `{bucket}.{endpoint}`.
+    pub force_path_style: bool,
+    /// Used instead of the default AWS credential chain when set.
+    pub credentials: Option<S3Credentials>,
+    /// Base of the object URLs handed out, such as a CDN or a public bucket
+    /// domain. Derived from the endpoint when unset.
+    pub public_url: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl fmt::Debug for S3Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Root directory of the `local` backend.
+    pub local_dir: String,
+    /// Base of the signed URLs the `local` and `memory` backends hand out.
+    pub public_url: String,
+    /// Largest attachment accepted, by `/file/upload` while it streams and by
+    /// `/file/presign-upload` up front.
+    pub max_upload_bytes: u64,
+    /// Largest avatar image accepted.
+    pub max_avatar_bytes: u64,
+    /// Largest banner image accepted.
+    pub max_banner_bytes: u64,
+    /// Lifetime of presigned upload requests.
+    pub upload_url_ttl_seconds: u64,
+    /// Lifetime of presigned download URLs.
+    pub download_url_ttl_seconds: u64,
+    /// Direct uploads not confirmed this long after they were presigned are
+    /// deleted, object and row.
+    pub pending_upload_ttl_seconds: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    /// Lifetime of a `/auth/ws-ticket` ticket.
+    pub ticket_ttl_seconds: u64,
+    /// How often a live socket re-checks that its session was not revoked.
+    pub session_check_seconds: u64,
+    /// Messages a connection may have queued or awaiting an ack before it is
+    /// dropped as a slow consumer.
+    pub outbound_buffer: usize,
+    /// How long an acked subscription waits before redelivering a message.
+    pub ack_timeout_seconds: u64,
+    pub broadcast: BroadcastBackend,
+    /// How often the server pings each socket.
+    pub heartbeat_interval_seconds: u64,
+    /// A socket that sends nothing, not even a pong, for this long is closed.
+    pub client_timeout_seconds: u64,
+    /// A socket with no subscriptions that sends no message for this long is
+    /// closed.
+    pub idle_timeout_seconds: u64,
+    pub max_connections: usize,
+    pub max_connections_per_user: usize,
+    /// Largest text message a client may send.
+    pub max_message_bytes: usize,
+    /// Sustained inbound messages per second per connection; bursts of the
+    /// same size are allowed.
+    pub messages_per_second: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct SseConfig {
+    /// Idle time after which `/events` sends a keep-alive comment.
+    pub keep_alive_seconds: u64,
+    /// Recent messages kept so `/events` can resume from `Last-Event-ID`.
+    /// Zero disables resuming.
+    pub replay_buffer: usize,
+}
+
+/// Application configuration, built once at startup and shared through `AppState`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub profile: Profile,
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub password: HashParams,
+    pub s3: S3Config,
+    pub storage: StorageConfig,
+    pub websocket: WebSocketConfig,
+    pub sse: SseConfig,
+}
+
+/// Every problem found while loading the configuration.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Optional TOML file layer. Every key may be omitted; environment variables
+/// take precedence over the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub server: FileServer,
+    #[serde(default)]
+    pub database: FileDatabase,
+    #[serde(default)]
+    pub auth: FileAuth,
+    #[serde(default)]
+    pub password: FilePassword,
+    #[serde(default)]
+    pub s3: FileS3,
+    #[serde(default)]
+    pub storage: FileStorage,
+    #[serde(default)]
+    pub websocket: FileWebSocket,
+    #[serde(default)]
+    pub sse: FileSse,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileServer {
+    pub address: Option<String>,
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileDatabase {
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileAuth {
+    pub jwt_secret: Option<String>,
+    pub access_token_ttl_minutes: Option<u32>,
+    pub refresh_token_ttl_days: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilePassword {
+    pub memory_kib: Option<u32>,
+    pub iterations: Option<u32>,
+    pub parallelism: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileS3 {
+    pub bucket: Option<String>,
+    pub region: Option<String>,
+    pub endpoint_url: Option<String>,
+    pub force_path_style: Option<bool>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub public_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileStorage {
+    pub backend: Option<String>,
+    pub local_dir: Option<String>,
+    pub public_url: Option<String>,
+    pub max_upload_bytes: Option<u64>,
+    pub max_avatar_bytes: Option<u64>,
+    pub max_banner_bytes: Option<u64>,
+    pub upload_url_ttl_seconds: Option<u64>,
+    pub download_url_ttl_seconds: Option<u64>,
+    pub pending_upload_ttl_seconds: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileWebSocket {
+    pub ticket_ttl_seconds: Option<u64>,
+    pub session_check_seconds: Option<u64>,
+    pub outbound_buffer: Option<usize>,
+    pub ack_timeout_seconds: Option<u64>,
+    pub broadcast: Option<String>,
+    pub heartbeat_interval_seconds: Option<u64>,
+    pub client_timeout_seconds: Option<u64>,
+    pub idle_timeout_seconds: Option<u64>,
+    pub max_connections: Option<usize>,
+    pub max_connections_per_user: Option<usize>,
+    pub max_message_bytes: Option<usize>,
+    pub messages_per_second: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileSse {
+    pub keep_alive_seconds: Option<u64>,
+    pub replay_buffer: Option<usize>,
+}
+
+/// Resolves one setting from the environment, then the file, then a default,
+/// recording parse failures instead of stopping at the first one.
+struct Resolver<'a, E> {
+    env: &'a E,
+    problems: Vec<String>,
+}
+
+impl<E> Resolver<'_, E>
+where
+    E: Fn(&str) -> Option<String>,
+{
+    fn string(&mut self, key: &str, file: Option<String>) -> Option<String> {
+        (self.env)(key).filter(|value| !value.is_empty()).or(file)
+    }
+
+    fn parsed<T: FromStr>(&mut self, key: &str, file: Option<T>, default: T) -> T {
+        match (self.env)(key).filter(|value| !value.is_empty()) {
+            Some(raw) => match raw.parse::<T>() {
+                Ok(value) => value,
+                Err(_) => {
+                    self.problems
+                        .push(format!("{key} must be a valid number (got \"{raw}\")"));
+                    default
+                }
+            },
+            None => file.unwrap_or(default),
+        }
+    }
+
+    fn flag(&mut self, key: &str, file: Option<bool>, default: bool) -> bool {
+        match (self.env)(key).filter(|value| !value.is_empty()) {
+            Some(raw) => match raw.to_ascii_lowercase().as_str() {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => {
+                    self.problems
+                        .push(format!("{key} must be true or false (got \"{raw}\")"));
+                    default
+                }
+            },
+            None => file.unwrap_or(default),
+        }
+    }
+
+    fn required(&mut self, key: &str, file: Option<String>) -> String {
+        self.string(key, file).unwrap_or_else(|| {
+            self.problems.push(format!("{key} must be set"));
+            String::new()
+        })
+    }
+}
+
+impl Config {
+    /// Loads `.env`, the optional TOML file and the process environment.
+    ///
+    /// The file is read from `APP_CONFIG_FILE`, or `config.toml` when present.
+    pub fn load() -> Result<Self, ConfigError> {
+        dotenv::dotenv().ok();
+
+        let explicit = std::env::var("APP_CONFIG_FILE").ok();
+        let path = explicit.as_deref().unwrap_or(DEFAULT_CONFIG_FILE);
+        let file = if explicit.is_some() || Path::new(path).exists() {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| ConfigError(vec![format!("failed to read {path}: {e}")]))?;
+            toml::from_str::<FileConfig>(&contents)
+                .map_err(|e| ConfigError(vec![format!("failed to parse {path}: {e}")]))?
+        } else {
+            FileConfig::default()
+        };
+
+        Self::from_sources(|key| std::env::var(key).ok(), file)
+    }
+
+    /// Builds a configuration from an environment lookup and a parsed file.
+    /// Kept separate from [`Config::load`] so tests can supply both directly.
+    pub fn from_sources<E>(env: E, file: FileConfig) -> Result<Self, ConfigError>
+    where
+        E: Fn(&str) -> Option<String>,
+    {
+        let mut resolver = Resolver {
+            env: &env,
+            problems: Vec::new(),
+        };
+
+        let profile = match resolver.string("APP_PROFILE", file.profile) {
+            Some(raw) => raw.parse::<Profile>().unwrap_or_else(|problem| {
+                resolver.problems.push(problem);
+                Profile::Dev
+            }),
+            None => Profile::Dev,
+        };
+
+        let server = ServerConfig {
+            address: resolver
+                .string("ADDRESS", file.server.address)
+                .unwrap_or_else(|| "0.0.0.0".to_string()),
+            port: resolver.parsed("PORT", file.server.port, 3000),
+        };
+
+        let database = DatabaseConfig {
+            url: resolver.required("DATABASE_URL", file.database.url),
+        };
+
+        let jwt_secret = resolver.string("JWT_SECRET", file.auth.jwt_secret);
+        let auth = AuthConfig {
+            access_token_ttl_minutes: resolver.parsed(
+                "ACCESS_TOKEN_TTL_MINUTES",
+                file.auth.access_token_ttl_minutes,
+                15,
+            ),
+            refresh_token_ttl_days: resolver.parsed(
+                "REFRESH_TOKEN_TTL_DAYS",
+                file.auth.refresh_token_ttl_days,
+                30,
+            ),
+            jwt_secret: match jwt_secret {
+                Some(secret) => secret,
+                None if profile == Profile::Prod => String::new(),
+                None => {
+                    log::warn!("JWT_SECRET is not set, using an insecure development secret");
+                    DEV_JWT_SECRET.to_string()
+                }
+            },
+        };
+
+        let password = HashParams {
+            memory_kib: resolver.parsed("ARGON2_MEMORY_KIB", file.password.memory_kib, 19456),
+            iterations: resolver.parsed("ARGON2_ITERATIONS", file.password.iterations, 2),
+            parallelism: resolver.parsed("ARGON2_PARALLELISM", file.password.parallelism, 1),
+        };
+
+        let storage = StorageConfig {
+            backend: match resolver.string("STORAGE_BACKEND", file.storage.backend) {
+                Some(raw) => raw.parse::<StorageBackend>().unwrap_or_else(|problem| {
+                    resolver.problems.push(problem);
+                    StorageBackend::S3
+                }),
+                None => StorageBackend::S3,
+            },
+            local_dir: resolver
+                .string("STORAGE_LOCAL_DIR", file.storage.local_dir)
+                .unwrap_or_else(|| "./storage".to_string()),
+            public_url: resolver
+                .string("STORAGE_PUBLIC_URL", file.storage.public_url)
+                .unwrap_or_else(|| format!("http://localhost:{}", server.port))
+                .trim_end_matches('/')
+                .to_string(),
+            max_upload_bytes: resolver.parsed(
+                "STORAGE_MAX_UPLOAD_BYTES",
+                file.storage.max_upload_bytes,
+                50 * 1024 * 1024,
+            ),
+            max_avatar_bytes: resolver.parsed(
+                "STORAGE_MAX_AVATAR_BYTES",
+                file.storage.max_avatar_bytes,
+                5 * 1024 * 1024,
+            ),
+            max_banner_bytes: resolver.parsed(
+                "STORAGE_MAX_BANNER_BYTES",
+                file.storage.max_banner_bytes,
+                10 * 1024 * 1024,
+            ),
+            upload_url_ttl_seconds: resolver.parsed(
+                "STORAGE_UPLOAD_URL_TTL_SECONDS",
+                file.storage.upload_url_ttl_seconds,
+                900,
+            ),
+            download_url_ttl_seconds: resolver.parsed(
+                "STORAGE_DOWNLOAD_URL_TTL_SECONDS",
+                file.storage.download_url_ttl_seconds,
+                900,
+            ),
+            pending_upload_ttl_seconds: resolver.parsed(
+                "STORAGE_PENDING_UPLOAD_TTL_SECONDS",
+                file.storage.pending_upload_ttl_seconds,
+                24 * 60 * 60,
+            ),
+        };
+
+        let endpoint_url = resolver
+            .string("S3_ENDPOINT_URL", file.s3.endpoint_url)
+            .map(|url| url.trim_end_matches('/').to_string());
+        let credentials = match (
+            resolver.string("S3_ACCESS_KEY_ID", file.s3.access_key_id),
+            resolver.string("S3_SECRET_ACCESS_KEY", file.s3.secret_access_key),
+        ) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(S3Credentials {
+                access_key_id,
+                secret_access_key,
+            }),
+            (None, None) => None,
+            _ => {
+                resolver.problems.push(
+                    "S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY must be set together".to_string(),
+                );
+                None
+            }
+        };
+        let s3 = S3Config {
+            bucket: if storage.backend == StorageBackend::S3 {
+                resolver.required("S3_BUCKET_NAME", file.s3.bucket)
+            } else {
+                resolver
+                    .string("S3_BUCKET_NAME", file.s3.bucket)
+                    .unwrap_or_default()
+            },
+            region: resolver
+                .string("AWS_REGION", file.s3.region)
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            // Self-hosted services rarely have per-bucket DNS names.
+            force_path_style: resolver.flag(
+                "S3_FORCE_PATH_STYLE",
+                file.s3.force_path_style,
+                endpoint_url.is_some(),
+            ),
+            endpoint_url,
+            credentials,
+            public_url: resolver
+                .string("S3_PUBLIC_URL", file.s3.public_url)
+                .map(|url| url.trim_end_matches('/').to_string()),
+        };
+
+        let websocket = WebSocketConfig {
+            ticket_ttl_seconds: resolver.parsed(
+                "WS_TICKET_TTL_SECONDS",
+                file.websocket.ticket_ttl_seconds,
+                30,
+            ),
+            session_check_seconds: resolver.parsed(
+                "WS_SESSION_CHECK_SECONDS",
+                file.websocket.session_check_seconds,
+                30,
+            ),
+            outbound_buffer: resolver.parsed(
+                "WS_OUTBOUND_BUFFER",
+                file.websocket.outbound_buffer,
+                64,
+            ),
+            ack_timeout_seconds: resolver.parsed(
+                "WS_ACK_TIMEOUT_SECONDS",
+                file.websocket.ack_timeout_seconds,
+                10,
+            ),
+            broadcast: match resolver.string("WS_BROADCAST", file.websocket.broadcast) {
+                Some(raw) => raw.parse::<BroadcastBackend>().unwrap_or_else(|problem| {
+                    resolver.problems.push(problem);
+                    BroadcastBackend::Local
+                }),
+                None => BroadcastBackend::Local,
+            },
+            heartbeat_interval_seconds: resolver.parsed(
+                "WS_HEARTBEAT_INTERVAL_SECONDS",
+                file.websocket.heartbeat_interval_seconds,
+                15,
+            ),
+            client_timeout_seconds: resolver.parsed(
+                "WS_CLIENT_TIMEOUT_SECONDS",
+                file.websocket.client_timeout_seconds,
+                45,
+            ),
+            idle_timeout_seconds: resolver.parsed(
+                "WS_IDLE_TIMEOUT_SECONDS",
+                file.websocket.idle_timeout_seconds,
+                300,
+            ),
+            max_connections: resolver.parsed(
+                "WS_MAX_CONNECTIONS",
+                file.websocket.max_connections,
+                10_000,
+            ),
+            max_connections_per_user: resolver.parsed(
+                "WS_MAX_CONNECTIONS_PER_USER",
+                file.websocket.max_connections_per_user,
+                10,
+            ),
+            max_message_bytes: resolver.parsed(
+                "WS_MAX_MESSAGE_BYTES",
+                file.websocket.max_message_bytes,
+                16_384,
+            ),
+            messages_per_second: resolver.parsed(
+                "WS_MESSAGES_PER_SECOND",
+                file.websocket.messages_per_second,
+                20,
+            ),
+        };
+
+        let sse = SseConfig {
+            keep_alive_seconds: resolver.parsed(
+                "SSE_KEEP_ALIVE_SECONDS",
+                file.sse.keep_alive_seconds,
+                15,
+            ),
+            replay_buffer: resolver.parsed("SSE_REPLAY_BUFFER", file.sse.replay_buffer, 1000),
+        };
+
+        let mut problems = resolver.problems;
+
+        if profile == Profile::Prod {
+            if auth.jwt_secret.is_empty() || auth.jwt_secret == DEV_JWT_SECRET {
+                problems.push("JWT_SECRET must be set to a non-default value in prod".to_string());
+            } else if auth.jwt_secret.len() < MIN_PROD_JWT_SECRET_LEN {
+                problems.push(format!(
+                    "JWT_SECRET must be at least {MIN_PROD_JWT_SECRET_LEN} characters in prod"
+                ));
+            }
+        }
+        if auth.access_token_ttl_minutes == 0 {
+            problems.push("ACCESS_TOKEN_TTL_MINUTES must be greater than 0".to_string());
+        }
+        if auth.refresh_token_ttl_days == 0 {
+            problems.push("REFRESH_TOKEN_TTL_DAYS must be greater than 0".to_string());
+        }
+        for (key, value) in [
+            ("WS_TICKET_TTL_SECONDS", websocket.ticket_ttl_seconds),
+            ("WS_SESSION_CHECK_SECONDS", websocket.session_check_seconds),
+            ("WS_OUTBOUND_BUFFER", websocket.outbound_buffer as u64),
+            ("WS_ACK_TIMEOUT_SECONDS", websocket.ack_timeout_seconds),
+            (
+                "WS_HEARTBEAT_INTERVAL_SECONDS",
+                websocket.heartbeat_interval_seconds,
+            ),
+            ("WS_IDLE_TIMEOUT_SECONDS", websocket.idle_timeout_seconds),
+            ("WS_MAX_CONNECTIONS", websocket.max_connections as u64),
+            (
+                "WS_MAX_CONNECTIONS_PER_USER",
+                websocket.max_connections_per_user as u64,
+            ),
+            (
+                "WS_MESSAGES_PER_SECOND",
+                websocket.messages_per_second.into(),
+            ),
+            ("SSE_KEEP_ALIVE_SECONDS", sse.keep_alive_seconds),
+            ("STORAGE_MAX_UPLOAD_BYTES", storage.max_upload_bytes),
+            ("STORAGE_MAX_AVATAR_BYTES", storage.max_avatar_bytes),
+            ("STORAGE_MAX_BANNER_BYTES", storage.max_banner_bytes),
+        ] {
+            if value == 0 {
+                problems.push(format!("{key} must be greater than 0"));
+            }
+        }
+        if websocket.client_timeout_seconds <= websocket.heartbeat_interval_seconds {
+            problems.push(
+                "WS_CLIENT_TIMEOUT_SECONDS must be greater than WS_HEARTBEAT_INTERVAL_SECONDS"
+                    .to_string(),
+            );
+        }
+        // actix-http rejects larger frames before they reach the handler.
+        if !(1..=MAX_WS_FRAME_BYTES).contains(&websocket.max_message_bytes) {
+            problems.push(format!(
+                "WS_MAX_MESSAGE_BYTES must be between 1 and {MAX_WS_FRAME_BYTES}"
+            ));
+        }
+        for (key, value) in [
+            (
+                "STORAGE_UPLOAD_URL_TTL_SECONDS",
+                storage.upload_url_ttl_seconds,
+            ),
+            (
+                "STORAGE_DOWNLOAD_URL_TTL_SECONDS",
+                storage.download_url_ttl_seconds,
+            ),
+        ] {
+            if !(1..=MAX_PRESIGN_TTL_SECONDS).contains(&value) {
+                problems.push(format!(
+                    "{key} must be between 1 and {MAX_PRESIGN_TTL_SECONDS}"
+                ));
+            }
+        }
+        // Clients need the whole lifetime of the upload URL to use it.
+        if storage.pending_upload_ttl_seconds < storage.upload_url_ttl_seconds {
+            problems.push(
+                "STORAGE_PENDING_UPLOAD_TTL_SECONDS must be at least STORAGE_UPLOAD_URL_TTL_SECONDS"
+                    .to_string(),
+            );
+        }
+        for (key, url) in [
+            ("S3_ENDPOINT_URL", &s3.endpoint_url),
+            ("S3_PUBLIC_URL", &s3.public_url),
+        ] {
+            if let Some(url) = url
+                && !url.starts_with("http://")
+                && !url.starts_with("https://")
+            {
+                problems.push(format!("{key} must start with http:// or https://"));
+            }
+        }
+        if let Err(e) = password.validate() {
+            problems.push(format!("invalid Argon2 parameters: {e}"));
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError(problems));
+        }
+
+        Ok(Config {
+            profile,
+            server,
+            database,
+            auth,
+            password,
+            s3,
+            storage,
+            websocket,
+            sse,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const PROD_SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    fn problems(vars: &[(&str, &str)]) -> Vec<String> {
+        Config::from_sources(env(vars), FileConfig::default())
+            .expect_err("configuration should be rejected")
+            .0
+    }
+
+    #[test]
+    fn minimal_dev_config_loads() {
+        let config = Config::from_sources(
+            env(&[("DATABASE_URL", "postgres://localhost/app")]),
+            FileConfig {
+                s3: FileS3 {
+                    bucket: Some("uploads".to_string()),
+                    ..FileS3::default()
+                },
+                ..FileConfig::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(config.profile, Profile::Dev);
+        assert_eq!(config.server.port, 3000);
+        assert_eq!(config.auth.jwt_secret, DEV_JWT_SECRET);
+        assert_eq!(config.s3.bucket, "uploads");
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let config = Config::from_sources(
+            env(&[
+                ("DATABASE_URL", "postgres://localhost/app"),
+                ("STORAGE_BACKEND", "memory"),
+                ("PORT", "8080"),
+            ]),
+            FileConfig {
+                server: FileServer {
+                    address: Some("127.0.0.1".to_string()),
+                    port: Some(4000),
+                },
+                ..FileConfig::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(config.server.address, "127.0.0.1");
+        assert_eq!(config.server.port, 8080);
+    }
+
+    #[test]
+    fn file_sections_are_optional() {
+        let file: FileConfig = toml::from_str(
+            r#"
+            profile = "dev"
+
+            [server]
+            port = 8080
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(file.server.port, Some(8080));
+        assert_eq!(file.storage.backend, None);
+        assert_eq!(file.s3.bucket, None);
+    }
+
+    #[test]
+    fn s3_section_parses() {
+        let file: FileConfig = toml::from_str(
+            r#"
+            [storage]
+            backend = "s3"
+
+            [s3]
+            bucket = "uploads"
+            endpoint_url = "http://localhost:9000/"
+            access_key_id = "minio"
+            secret_access_key = "minio-secret"
+            public_url = "https://cdn.example.com/"
+            "#,
+        )
+        .unwrap();
+        let config =
+            Config::from_sources(env(&[("DATABASE_URL", "postgres://localhost/app")]), file)
+                .unwrap();
+
+        assert_eq!(config.storage.backend, StorageBackend::S3);
+        assert_eq!(config.s3.bucket, "uploads");
+        assert_eq!(config.s3.region, "us-east-1");
+        assert_eq!(
+            config.s3.endpoint_url.as_deref(),
+            Some("http://localhost:9000")
+        );
+        assert!(config.s3.force_path_style);
+        let credentials = config.s3.credentials.unwrap();
+        assert_eq!(credentials.access_key_id, "minio");
+        assert_eq!(credentials.secret_access_key, "minio-secret");
+        assert_eq!(
+            config.s3.public_url.as_deref(),
+            Some("https://cdn.example.com")
+        );
+    }
+
+    #[test]
+    fn s3_defaults_to_aws() {
+        let config = Config::from_sources(
+            env(&[
+                ("DATABASE_URL", "postgres://localhost/app"),
+                ("S3_BUCKET_NAME", "uploads"),
+            ]),
+            FileConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(config.s3.endpoint_url, None);
+        assert!(!config.s3.force_path_style);
+        assert!(config.s3.credentials.is_none());
+        assert_eq!(config.s3.public_url, None);
+    }
+
+    #[test]
+    fn s3_path_style_can_be_turned_off() {
+        let config = Config::from_sources(
+            env(&[
+                ("DATABASE_URL", "postgres://localhost/app"),
+                ("S3_BUCKET_NAME", "uploads"),
+                ("S3_ENDPOINT_URL", "https://storage.example.com"),
+                ("S3_FORCE_PATH_STYLE", "false"),
+            ]),
+            FileConfig::default(),
+        )
+        .unwrap();
+
+        assert!(!config.s3.force_path_style);
+    }
+
+    #[test]
+    fn s3_rejects_bad_settings() {
+        let problems = problems(&[
+            ("DATABASE_URL", "postgres://localhost/app"),
+            ("S3_ENDPOINT_URL", "localhost:9000"),
+            ("S3_PUBLIC_URL", "cdn.example.com"),
+            ("S3_FORCE_PATH_STYLE", "yes"),
+            ("S3_SECRET_ACCESS_KEY", "minio-secret"),
+        ]);
+
+        assert_eq!(
+            problems,
+            [
+                "S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY must be set together",
+                "S3_BUCKET_NAME must be set",
+                "S3_FORCE_PATH_STYLE must be true or false (got \"yes\")",
+                "S3_ENDPOINT_URL must start with http:// or https://",
+                "S3_PUBLIC_URL must start with http:// or https://",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let problems = problems(&[
+            ("APP_PROFILE", "staging"),
+            ("PORT", "eighty"),
+            ("ACCESS_TOKEN_TTL_MINUTES", "0"),
+            ("STORAGE_BACKEND", "memory"),
+            ("WS_MAX_MESSAGE_BYTES", "1000000"),
+            ("S3_ACCESS_KEY_ID", "minio"),
+        ]);
+
+        for expected in [
+            "APP_PROFILE must be one of dev, test, prod (got \"staging\")",
+            "PORT must be a valid number (got \"eighty\")",
+            "DATABASE_URL must be set",
+            "ACCESS_TOKEN_TTL_MINUTES must be greater than 0",
+            "WS_MAX_MESSAGE_BYTES must be between 1 and 65536",
+            "S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY must be set together",
+        ] {
+            assert!(
+                problems.iter().any(|problem| problem == expected),
+                "missing {expected:?} in {problems:?}"
+            );
+        }
+        assert_eq!(problems.len(), 6, "{problems:?}");
+    }
+
+    #[test]
+    fn prod_rejects_missing_or_default_jwt_secret() {
+        let base = [
+            ("APP_PROFILE", "prod"),
+            ("DATABASE_URL", "postgres://localhost/app"),
+            ("STORAGE_BACKEND", "memory"),
+        ];
+        let expected = "JWT_SECRET must be set to a non-default value in prod";
+
+        assert_eq!(problems(&base), [expected]);
+        let default_secret = [&base[..], &[("JWT_SECRET", DEV_JWT_SECRET)]].concat();
+        assert_eq!(problems(&default_secret), [expected]);
+    }
+
+    #[test]
+    fn prod_rejects_short_jwt_secret() {
+        let problems = problems(&[
+            ("APP_PROFILE", "prod"),
+            ("DATABASE_URL", "postgres://localhost/app"),
+            ("STORAGE_BACKEND", "memory"),
+            ("JWT_SECRET", &PROD_SECRET[1..]),
+        ]);
+
+        assert_eq!(
+            problems,
+            [format!(
+                "JWT_SECRET must be at least {MIN_PROD_JWT_SECRET_LEN} characters in prod"
+            )]
+        );
+    }
+
+    #[test]
+    fn prod_accepts_strong_jwt_secret() {
+        let config = Config::from_sources(
+            env(&[
+                ("APP_PROFILE", "production"),
+                ("DATABASE_URL", "postgres://localhost/app"),
+                ("STORAGE_BACKEND", "memory"),
+                ("JWT_SECRET", PROD_SECRET),
+            ]),
+            FileConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(config.profile, Profile::Prod);
+        assert_eq!(config.auth.jwt_secret, PROD_SECRET);
+    }
+}
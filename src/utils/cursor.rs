@@ -0,0 +1,468 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sea_orm::{
+    ColumnTrait, Condition, QueryFilter, QueryOrder, Value, prelude::DateTimeWithTimeZone,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::utils::app_error::{AppError, ErrorCode, FieldError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Keeps cursor signatures distinct from anything else signed with the same secret.
+const CURSOR_CONTEXT: &[u8] = b"list-cursor.v1.";
+
+/// Which way a cursor moves from the row it was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "n")]
+    Next,
+    #[serde(rename = "p")]
+    Prev,
+}
+
+/// A sort column value of the row a cursor points at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CursorValue {
+    #[serde(rename = "i")]
+    Integer(i64),
+    #[serde(rename = "s")]
+    Text(String),
+    #[serde(rename = "t")]
+    Timestamp(DateTimeWithTimeZone),
+}
+
+impl From<CursorValue> for Value {
+    fn from(value: CursorValue) -> Self {
+        match value {
+            CursorValue::Integer(integer) => integer.into(),
+            CursorValue::Text(text) => text.into(),
+            CursorValue::Timestamp(timestamp) => timestamp.into(),
+        }
+    }
+}
+
+impl TryFrom<Value> for CursorValue {
+    type Error = AppError;
+
+    /// Only non-null integer, text and timestamp columns can key a cursor.
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::SmallInt(Some(integer)) => Ok(CursorValue::Integer(integer.into())),
+            Value::Int(Some(integer)) => Ok(CursorValue::Integer(integer.into())),
+            Value::BigInt(Some(integer)) => Ok(CursorValue::Integer(integer)),
+            Value::String(Some(text)) => Ok(CursorValue::Text(*text)),
+            Value::ChronoDateTimeWithTimeZone(Some(timestamp)) => {
+                Ok(CursorValue::Timestamp(*timestamp))
+            }
+            other => Err(AppError::Internal(format!(
+                "cannot build a cursor from {other:?}"
+            ))),
+        }
+    }
+}
+
+/// Position in a keyset-paginated listing: the sort key values of a row and
+/// the direction to read from it. Short field names keep tokens small.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Canonical sort the cursor was issued for, such as `-created_at,-id`.
+    #[serde(rename = "s")]
+    pub sort: String,
+    #[serde(rename = "v")]
+    pub values: Vec<CursorValue>,
+    #[serde(rename = "d")]
+    pub direction: Direction,
+}
+
+fn invalid_cursor(message: &str) -> AppError {
+    AppError::BadRequest(ErrorCode::InvalidCursor, message.to_string())
+}
+
+fn mac(secret: &str, payload: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(CURSOR_CONTEXT);
+    mac.update(payload.as_bytes());
+    mac
+}
+
+impl Cursor {
+    /// Encodes the cursor as `<payload>.<signature>`, both base64url.
+    pub fn encode(&self, secret: &str) -> String {
+        let json = serde_json::to_vec(self).expect("cursor serializes to JSON");
+        let payload = URL_SAFE_NO_PAD.encode(json);
+        let signature = URL_SAFE_NO_PAD.encode(mac(secret, &payload).finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    /// Verifies the signature before trusting anything in the payload.
+    pub fn decode(raw: &str, secret: &str) -> Result<Self, AppError> {
+        let (payload, signature) = raw
+            .split_once('.')
+            .ok_or_else(|| invalid_cursor("Invalid cursor"))?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| invalid_cursor("Invalid cursor"))?;
+        mac(secret, payload)
+            .verify_slice(&signature)
+            .map_err(|_| invalid_cursor("Invalid cursor"))?;
+
+        let json = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| invalid_cursor("Invalid cursor"))?;
+        serde_json::from_slice(&json).map_err(|_| invalid_cursor("Invalid cursor"))
+    }
+}
+
+/// One page read with a cursor, plus cursors for its neighbours.
+#[derive(Debug)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+/// Keyset pagination over `(column, ascending)` sort keys. The last key must
+/// be unique (usually the primary key) so the order is total. Unlike OFFSET,
+/// rows inserted between requests never shift later pages, and deep pages
+/// cost the same as the first.
+pub struct Keyset<'a, C> {
+    pub sort: &'a str,
+    pub keys: &'a [(C, bool)],
+    pub secret: &'a str,
+}
+
+impl<C: ColumnTrait> Keyset<'_, C> {
+    /// Verifies a client-supplied cursor and checks it was issued for this sort.
+    pub fn decode(&self, raw: Option<&str>) -> Result<Option<Cursor>, AppError> {
+        let Some(raw) = raw.filter(|raw| !raw.is_empty()) else {
+            return Ok(None);
+        };
+        let cursor = Cursor::decode(raw, self.secret)?;
+        if cursor.sort != self.sort || cursor.values.len() != self.keys.len() {
+            return Err(invalid_cursor("Cursor was issued for a different sort"));
+        }
+        Ok(Some(cursor))
+    }
+
+    /// Adds the seek condition and ordering. Callers fetch `limit + 1` rows so
+    /// [`Keyset::page`] can tell whether another page follows.
+    pub fn apply<Q>(&self, query: Q, cursor: Option<&Cursor>) -> Q
+    where
+        Q: QueryFilter + QueryOrder,
+    {
+        // Reading backwards scans in the opposite order; `page` restores it.
+        let backwards = cursor.is_some_and(|cursor| cursor.direction == Direction::Prev);
+        let scan_ascending = |ascending: bool| ascending != backwards;
+
+        let query = match cursor {
+            // (k1 > v1) OR (k1 = v1 AND k2 > v2) OR ..., flipping the
+            // comparison for keys scanned in descending order.
+            Some(cursor) => {
+                let mut seek = Condition::any();
+                for (i, ((column, ascending), value)) in
+                    self.keys.iter().zip(&cursor.values).enumerate()
+                {
+                    let mut step = Condition::all();
+                    for ((previous, _), previous_value) in self.keys[..i].iter().zip(&cursor.values)
+                    {
+                        step = step.add(previous.eq(Value::from(previous_value.clone())));
+                    }
+                    let value = Value::from(value.clone());
+                    step = step.add(if scan_ascending(*ascending) {
+                        column.gt(value)
+                    } else {
+                        column.lt(value)
+                    });
+                    seek = seek.add(step);
+                }
+                query.filter(seek)
+            }
+            None => query,
+        };
+
+        self.keys.iter().fold(query, |query, (column, ascending)| {
+            if scan_ascending(*ascending) {
+                query.order_by_asc(*column)
+            } else {
+                query.order_by_desc(*column)
+            }
+        })
+    }
+
+    /// Trims the look-ahead row, restores display order and issues cursors.
+    /// `key` returns the sort key values of a row, in key order.
+    pub fn page<T>(
+        &self,
+        mut rows: Vec<T>,
+        limit: u64,
+        cursor: Option<&Cursor>,
+        key: impl Fn(&T) -> Result<Vec<CursorValue>, AppError>,
+    ) -> Result<CursorPage<T>, AppError> {
+        let has_more = rows.len() as u64 > limit;
+        rows.truncate(limit as usize);
+
+        let backwards = cursor.is_some_and(|cursor| cursor.direction == Direction::Prev);
+        if backwards {
+            rows.reverse();
+        }
+
+        // Going back from a page means that page still follows, and vice versa.
+        let (has_next, has_prev) = if backwards {
+            (true, has_more)
+        } else {
+            (has_more, cursor.is_some())
+        };
+
+        let make = |row: &T, direction| -> Result<String, AppError> {
+            Ok(Cursor {
+                sort: self.sort.to_string(),
+                values: key(row)?,
+                direction,
+            }
+            .encode(self.secret))
+        };
+
+        Ok(CursorPage {
+            next_cursor: rows
+                .last()
+                .filter(|_| has_next)
+                .map(|row| make(row, Direction::Next))
+                .transpose()?,
+            prev_cursor: rows
+                .first()
+                .filter(|_| has_prev)
+                .map(|row| make(row, Direction::Prev))
+                .transpose()?,
+            items: rows,
+        })
+    }
+}
+
+/// How a listing is paginated, chosen with `?pagination=`. Passing a cursor
+/// implies cursor mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaginationMode {
+    Page,
+    Cursor,
+}
+
+impl PaginationMode {
+    pub fn parse(raw: Option<&str>, cursor: Option<&str>) -> Result<Self, AppError> {
+        match raw {
+            None if cursor.is_some_and(|cursor| !cursor.is_empty()) => Ok(PaginationMode::Cursor),
+            None | Some("page") => Ok(PaginationMode::Page),
+            Some("cursor") => Ok(PaginationMode::Cursor),
+            Some(other) => Err(AppError::Validation(vec![FieldError::new(
+                "pagination",
+                format!("Unknown pagination mode \"{other}\". Supported: page, cursor"),
+            )])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::post;
+    use sea_orm::{
+        DbBackend, EntityTrait, QueryTrait,
+        sea_query::{PostgresQueryBuilder, QueryBuilder},
+    };
+
+    use super::*;
+
+    const SECRET: &str = "cursor-test-secret";
+    const KEYS: &[(post::Column, bool)] =
+        &[(post::Column::CreatedAt, false), (post::Column::Id, false)];
+
+    fn keyset() -> Keyset<'static, post::Column> {
+        Keyset {
+            sort: "-created_at,-id",
+            keys: KEYS,
+            secret: SECRET,
+        }
+    }
+
+    fn cursor(sort: &str, direction: Direction) -> Cursor {
+        Cursor {
+            sort: sort.to_string(),
+            values: vec![
+                CursorValue::Timestamp("2026-01-02T03:04:05Z".parse().unwrap()),
+                CursorValue::Integer(42),
+            ],
+            direction,
+        }
+    }
+
+    /// `raw` as it appears in Postgres SQL; releases of sea-query differ in
+    /// how many fractional digits they print.
+    fn literal(raw: &str) -> String {
+        let timestamp: DateTimeWithTimeZone = raw.parse().unwrap();
+        PostgresQueryBuilder.value_to_string(&Value::from(timestamp))
+    }
+
+    fn assert_invalid(result: Result<impl std::fmt::Debug, AppError>) {
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidCursor, "{error:?}");
+    }
+
+    #[test]
+    fn round_trip() {
+        let raw = cursor("-created_at,-id", Direction::Prev).encode(SECRET);
+        let decoded = Cursor::decode(&raw, SECRET).unwrap();
+
+        assert_eq!(decoded.sort, "-created_at,-id");
+        assert_eq!(decoded.direction, Direction::Prev);
+        assert!(matches!(
+            decoded.values.as_slice(),
+            [CursorValue::Timestamp(_), CursorValue::Integer(42)]
+        ));
+    }
+
+    #[test]
+    fn tampering_is_rejected() {
+        let raw = cursor("-created_at,-id", Direction::Next).encode(SECRET);
+        let (payload, signature) = raw.split_once('.').unwrap();
+
+        // A payload changed to point elsewhere, keeping the old signature.
+        let mut forged = cursor("-created_at,-id", Direction::Next);
+        forged.values[1] = CursorValue::Integer(1);
+        let forged_payload = forged.encode(SECRET).split_once('.').unwrap().0.to_string();
+        assert_invalid(Cursor::decode(
+            &format!("{forged_payload}.{signature}"),
+            SECRET,
+        ));
+
+        let mut bad_signature = signature.to_string();
+        let last = if bad_signature.ends_with('A') {
+            "B"
+        } else {
+            "A"
+        };
+        bad_signature.replace_range(bad_signature.len() - 1.., last);
+        assert_invalid(Cursor::decode(
+            &format!("{payload}.{bad_signature}"),
+            SECRET,
+        ));
+
+        assert_invalid(Cursor::decode(&raw, "another-secret"));
+        assert_invalid(Cursor::decode(payload, SECRET));
+        assert_invalid(Cursor::decode("not a cursor.at all", SECRET));
+        assert_invalid(Cursor::decode("", SECRET));
+    }
+
+    #[test]
+    fn cursors_are_bound_to_their_sort() {
+        let keyset = keyset();
+        assert!(keyset.decode(None).unwrap().is_none());
+        assert!(keyset.decode(Some("")).unwrap().is_none());
+        assert!(
+            keyset
+                .decode(Some(
+                    &cursor("-created_at,-id", Direction::Next).encode(SECRET)
+                ))
+                .unwrap()
+                .is_some()
+        );
+
+        assert_invalid(keyset.decode(Some(
+            &cursor("created_at,id", Direction::Next).encode(SECRET),
+        )));
+        let mut short = cursor("-created_at,-id", Direction::Next);
+        short.values.pop();
+        assert_invalid(keyset.decode(Some(&short.encode(SECRET))));
+    }
+
+    #[test]
+    fn seek_condition_follows_the_direction() {
+        let keyset = keyset();
+        let sql = |direction| {
+            let cursor = cursor("-created_at,-id", direction);
+            keyset
+                .apply(post::Entity::find(), Some(&cursor))
+                .build(DbBackend::Postgres)
+                .to_string()
+        };
+
+        let at = literal("2026-01-02T03:04:05Z");
+        let next = sql(Direction::Next);
+        assert!(
+            next.contains(&format!(
+                r#""post"."created_at" < {at} OR ("post"."created_at" = {at} AND "post"."id" < 42)"#
+            )),
+            "{next}"
+        );
+        assert!(
+            next.ends_with(r#"ORDER BY "post"."created_at" DESC, "post"."id" DESC"#),
+            "{next}"
+        );
+
+        let prev = sql(Direction::Prev);
+        assert!(
+            prev.contains(&format!(
+                r#""post"."created_at" > {at} OR ("post"."created_at" = {at} AND "post"."id" > 42)"#
+            )),
+            "{prev}"
+        );
+        assert!(
+            prev.ends_with(r#"ORDER BY "post"."created_at" ASC, "post"."id" ASC"#),
+            "{prev}"
+        );
+    }
+
+    #[test]
+    fn pages_link_to_their_neighbours() {
+        let keyset = Keyset {
+            sort: "id",
+            keys: &[(post::Column::Id, true)],
+            secret: SECRET,
+        };
+        let key = |row: &i64| Ok(vec![CursorValue::Integer(*row)]);
+        let value = |raw: Option<String>| {
+            let cursor = Cursor::decode(&raw.expect("cursor is issued"), SECRET).unwrap();
+            match (cursor.values.as_slice(), cursor.direction) {
+                ([CursorValue::Integer(value)], direction) => (*value, direction),
+                other => panic!("unexpected cursor {other:?}"),
+            }
+        };
+
+        // First page, with the look-ahead row.
+        let first = keyset.page(vec![1, 2, 3], 2, None, key).unwrap();
+        assert_eq!(first.items, [1, 2]);
+        assert!(first.prev_cursor.is_none());
+        assert_eq!(value(first.next_cursor), (2, Direction::Next));
+
+        // Last page going forwards.
+        let next = cursor("id", Direction::Next);
+        let last = keyset.page(vec![3], 2, Some(&next), key).unwrap();
+        assert_eq!(last.items, [3]);
+        assert!(last.next_cursor.is_none());
+        assert_eq!(value(last.prev_cursor), (3, Direction::Prev));
+
+        // Going back, rows arrive in reverse and more may precede them.
+        let prev = cursor("id", Direction::Prev);
+        let back = keyset.page(vec![4, 3, 2], 2, Some(&prev), key).unwrap();
+        assert_eq!(back.items, [3, 4]);
+        assert_eq!(value(back.prev_cursor), (3, Direction::Prev));
+        assert_eq!(value(back.next_cursor), (4, Direction::Next));
+    }
+
+    #[test]
+    fn pagination_mode() {
+        let parse = PaginationMode::parse;
+        assert_eq!(parse(None, None).unwrap(), PaginationMode::Page);
+        assert_eq!(parse(None, Some("")).unwrap(), PaginationMode::Page);
+        assert_eq!(parse(None, Some("abc")).unwrap(), PaginationMode::Cursor);
+        assert_eq!(
+            parse(Some("page"), Some("abc")).unwrap(),
+            PaginationMode::Page
+        );
+        assert_eq!(parse(Some("cursor"), None).unwrap(), PaginationMode::Cursor);
+        assert!(matches!(
+            parse(Some("offset"), None),
+            Err(AppError::Validation(details)) if details[0].field == "pagination"
+        ));
+    }
+}
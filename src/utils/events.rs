@@ -0,0 +1,311 @@
+use chrono::Utc;
+use entity::{message, post};
+use sea_orm::prelude::DateTimeWithTimeZone;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::utils::ws_hub::Hub;
+
+/// Topic every post event is published on.
+pub const POSTS_TOPIC: &str = "posts";
+
+/// Topic for the events of one author's posts.
+pub fn user_posts_topic(user_id: i32) -> String {
+    format!("user:{user_id}:posts")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PostEventKind {
+    #[serde(rename = "post.created")]
+    Created,
+    #[serde(rename = "post.updated")]
+    Updated,
+    #[serde(rename = "post.deleted")]
+    Deleted,
+}
+
+/// Payload of a post event, as delivered in a `message` frame.
+#[derive(Debug, Serialize)]
+pub struct PostEvent<'a> {
+    pub event: PostEventKind,
+    /// Same on every topic the event is published to, so clients subscribed
+    /// to several can drop duplicates.
+    pub event_id: Uuid,
+    /// The user whose request caused the event.
+    pub actor_id: i32,
+    pub occurred_at: DateTimeWithTimeZone,
+    /// The post after the change; for `post.deleted`, as it was before.
+    pub post: &'a post::Model,
+}
+
+/// Publishes a post event once the change has been committed. Goes to
+/// [`POSTS_TOPIC`] and to the author's [`user_posts_topic`].
+pub fn publish_post_event(hub: &Hub, event: PostEventKind, actor_id: i32, post: &post::Model) {
+    let payload = serde_json::to_value(PostEvent {
+        event,
+        event_id: Uuid::new_v4(),
+        actor_id,
+        occurred_at: Utc::now().fixed_offset(),
+        post,
+    })
+    .expect("post event serializes to JSON");
+
+    hub.publish(&user_posts_topic(post.user_id), payload.clone());
+    hub.publish(POSTS_TOPIC, payload);
+}
+
+/// Topic for users coming online and going offline.
+pub const PRESENCE_TOPIC: &str = "presence";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PresenceEventKind {
+    /// The user opened their first connection.
+    #[serde(rename = "presence.join")]
+    Join,
+    /// The user closed their last connection.
+    #[serde(rename = "presence.leave")]
+    Leave,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PresenceEvent {
+    pub event: PresenceEventKind,
+    pub user_id: i32,
+    pub occurred_at: DateTimeWithTimeZone,
+}
+
+/// Publishes a presence change to [`PRESENCE_TOPIC`]. Further tabs of an
+/// already online user do not produce events. Presence is counted per
+/// instance, so the event is not relayed: elsewhere the user may still be
+/// online, or not yet.
+pub fn publish_presence_event(hub: &Hub, event: PresenceEventKind, user_id: i32) {
+    let payload = serde_json::to_value(PresenceEvent {
+        event,
+        user_id,
+        occurred_at: Utc::now().fixed_offset(),
+    })
+    .expect("presence event serializes to JSON");
+
+    hub.publish_local(PRESENCE_TOPIC, payload);
+}
+
+/// A user's private topic. Chat events reach every member of a conversation
+/// here, so no per-conversation subscription is needed.
+pub fn user_topic(user_id: i32) -> String {
+    format!("user:{user_id}")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ChatEventKind {
+    #[serde(rename = "message.created")]
+    MessageCreated,
+    /// A member's read receipt moved forward.
+    #[serde(rename = "message.read")]
+    MessageRead,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageEvent<'a> {
+    pub event: ChatEventKind,
+    pub message: &'a message::Model,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadEvent {
+    pub event: ChatEventKind,
+    pub conversation_id: i32,
+    pub user_id: i32,
+    pub last_read_message_id: i32,
+    pub occurred_at: DateTimeWithTimeZone,
+}
+
+/// Payload of a `message.created` event. Replayed messages use it too, so
+/// clients handle live and missed messages alike.
+pub fn message_event(message: &message::Model) -> Value {
+    serde_json::to_value(MessageEvent {
+        event: ChatEventKind::MessageCreated,
+        message,
+    })
+    .expect("message event serializes to JSON")
+}
+
+/// Publishes a new chat message to the [`user_topic`] of every member,
+/// including the sender's other connections.
+pub fn publish_message_event(hub: &Hub, member_ids: &[i32], message: &message::Model) {
+    let payload = message_event(message);
+    for member_id in member_ids {
+        hub.publish(&user_topic(*member_id), payload.clone());
+    }
+}
+
+/// Publishes a read receipt to the [`user_topic`] of every member.
+pub fn publish_read_event(
+    hub: &Hub,
+    member_ids: &[i32],
+    conversation_id: i32,
+    user_id: i32,
+    last_read_message_id: i32,
+) {
+    let payload = serde_json::to_value(ReadEvent {
+        event: ChatEventKind::MessageRead,
+        conversation_id,
+        user_id,
+        last_read_message_id,
+        occurred_at: Utc::now().fixed_offset(),
+    })
+    .expect("read event serializes to JSON");
+
+    for member_id in member_ids {
+        hub.publish(&user_topic(*member_id), payload.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::sea_orm_active_enums::Role;
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::{
+        config::{BroadcastBackend, WebSocketConfig},
+        jwt::JwtClaims,
+        ws_hub::Topics,
+    };
+
+    fn post() -> post::Model {
+        let created_at = "2026-10-17T12:00:00Z".parse().unwrap();
+        post::Model {
+            id: 3,
+            user_id: 7,
+            title: "Hello".to_string(),
+            text: "World".to_string(),
+            created_at,
+            updated_at: created_at,
+            banner: None,
+        }
+    }
+
+    fn hub() -> Hub {
+        let config = WebSocketConfig {
+            ticket_ttl_seconds: 30,
+            session_check_seconds: 30,
+            outbound_buffer: 8,
+            ack_timeout_seconds: 10,
+            broadcast: BroadcastBackend::Local,
+            heartbeat_interval_seconds: 15,
+            client_timeout_seconds: 45,
+            idle_timeout_seconds: 300,
+            max_connections: 10,
+            max_connections_per_user: 10,
+            max_message_bytes: 16_384,
+            messages_per_second: 20,
+        };
+        Hub::new(Topics::new(), &config, 0)
+    }
+
+    fn claims(user_id: i32) -> JwtClaims {
+        JwtClaims {
+            user_id,
+            exp: 0,
+            iat: 0,
+            email: format!("user{user_id}@example.com"),
+            role: Role::User,
+            sid: String::new(),
+        }
+    }
+
+    #[test]
+    fn post_event_shape() {
+        let post = post();
+        for (kind, name) in [
+            (PostEventKind::Created, "post.created"),
+            (PostEventKind::Updated, "post.updated"),
+            (PostEventKind::Deleted, "post.deleted"),
+        ] {
+            let event_id = Uuid::new_v4();
+            let value = serde_json::to_value(PostEvent {
+                event: kind,
+                event_id,
+                actor_id: 9,
+                occurred_at: post.created_at,
+                post: &post,
+            })
+            .unwrap();
+
+            assert_eq!(
+                value,
+                json!({
+                    "event": name,
+                    "event_id": event_id,
+                    "actor_id": 9,
+                    "occurred_at": "2026-10-17T12:00:00Z",
+                    "post": {
+                        "id": 3,
+                        "user_id": 7,
+                        "title": "Hello",
+                        "text": "World",
+                        "created_at": "2026-10-17T12:00:00Z",
+                        "updated_at": "2026-10-17T12:00:00Z",
+                        "banner": null,
+                    },
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn post_event_reaches_both_topics() {
+        let hub = hub();
+        let mut all_posts = hub.connect(&claims(1)).unwrap();
+        let mut author_posts = hub.connect(&claims(2)).unwrap();
+        let mut other_author = hub.connect(&claims(3)).unwrap();
+        hub.subscribe(all_posts.id, POSTS_TOPIC, false);
+        hub.subscribe(author_posts.id, &user_posts_topic(7), false);
+        hub.subscribe(other_author.id, &user_posts_topic(8), false);
+
+        publish_post_event(&hub, PostEventKind::Created, 7, &post());
+
+        let mut frames = Vec::new();
+        for (registration, topic) in [
+            (&mut all_posts, "posts"),
+            (&mut author_posts, "user:7:posts"),
+        ] {
+            let delivery = registration.deliveries.try_recv().unwrap();
+            let frame: Value = serde_json::from_str(&delivery.frame).unwrap();
+            assert_eq!(frame["type"], "message");
+            assert_eq!(frame["topic"], topic);
+            assert_eq!(frame["payload"]["event"], "post.created");
+            assert_eq!(frame["payload"]["actor_id"], 7);
+            assert_eq!(frame["payload"]["post"]["id"], 3);
+            assert!(registration.deliveries.try_recv().is_err());
+            frames.push(frame);
+        }
+        assert_eq!(
+            frames[0]["payload"]["event_id"],
+            frames[1]["payload"]["event_id"]
+        );
+        assert!(other_author.deliveries.try_recv().is_err());
+    }
+
+    #[test]
+    fn presence_events_stay_on_this_instance() {
+        let hub = hub();
+        let (relay, mut relayed) = tokio::sync::mpsc::channel(8);
+        hub.set_relay(relay);
+        let mut watcher = hub.connect(&claims(1)).unwrap();
+        hub.subscribe(watcher.id, PRESENCE_TOPIC, false);
+
+        publish_presence_event(&hub, PresenceEventKind::Join, 7);
+
+        let delivery = watcher.deliveries.try_recv().unwrap();
+        let frame: Value = serde_json::from_str(&delivery.frame).unwrap();
+        assert_eq!(frame["payload"]["event"], "presence.join");
+        assert_eq!(frame["payload"]["user_id"], 7);
+        assert!(relayed.try_recv().is_err());
+
+        // Other events still reach the other instances.
+        publish_post_event(&hub, PostEventKind::Created, 7, &post());
+        assert!(relayed.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,299 @@
+use entity::sea_orm_active_enums::FilePurpose;
+
+use crate::utils::{
+    app_error::{AppError, ErrorCode},
+    config::StorageConfig,
+};
+
+/// Bytes read from the start of a file to tell its type.
+pub const SNIFF_LEN: usize = 512;
+
+/// A type told apart by [`sniff`], with the extension its keys get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileType {
+    pub mime: &'static str,
+    pub extension: &'static str,
+}
+
+pub const PNG: FileType = FileType {
+    mime: "image/png",
+    extension: "png",
+};
+pub const JPEG: FileType = FileType {
+    mime: "image/jpeg",
+    extension: "jpg",
+};
+pub const GIF: FileType = FileType {
+    mime: "image/gif",
+    extension: "gif",
+};
+pub const WEBP: FileType = FileType {
+    mime: "image/webp",
+    extension: "webp",
+};
+pub const PDF: FileType = FileType {
+    mime: "application/pdf",
+    extension: "pdf",
+};
+pub const ZIP: FileType = FileType {
+    mime: "application/zip",
+    extension: "zip",
+};
+pub const TEXT: FileType = FileType {
+    mime: "text/plain",
+    extension: "txt",
+};
+/// HTML, SVG, XML and anything else opening with a tag. Browsers may run
+/// scripts in these, so no purpose allows them.
+pub const MARKUP: FileType = FileType {
+    mime: "text/html",
+    extension: "html",
+};
+pub const UNKNOWN: FileType = FileType {
+    mime: "application/octet-stream",
+    extension: "bin",
+};
+
+const IMAGES: &[FileType] = &[PNG, JPEG, GIF, WEBP];
+const ATTACHMENTS: &[FileType] = &[PNG, JPEG, GIF, WEBP, PDF, ZIP, TEXT];
+
+/// The type of a file starting with `head`, from its signature rather than
+/// anything the client says. `head` should hold the first [`SNIFF_LEN`]
+/// bytes, or the whole file if shorter.
+pub fn sniff(head: &[u8]) -> FileType {
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        PNG
+    } else if head.starts_with(b"\xff\xd8\xff") {
+        JPEG
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        GIF
+    } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(&b"WEBP"[..]) {
+        WEBP
+    } else if head.starts_with(b"%PDF-") {
+        PDF
+    } else if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        ZIP
+    } else {
+        sniff_text(head)
+    }
+}
+
+/// UTF-8 without control characters is text, or markup if it opens with `<`.
+fn sniff_text(head: &[u8]) -> FileType {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // The sample may end partway through a character.
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).expect("valid up to this point")
+        }
+        Err(_) => return UNKNOWN,
+    };
+    let text = text.trim_start_matches('\u{feff}');
+    if text.trim_start().starts_with('<') {
+        MARKUP
+    } else if text.is_empty()
+        || text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+    {
+        UNKNOWN
+    } else {
+        TEXT
+    }
+}
+
+fn purpose_name(purpose: FilePurpose) -> &'static str {
+    match purpose {
+        FilePurpose::Avatar => "avatar",
+        FilePurpose::Banner => "banner",
+        FilePurpose::Attachment => "attachment",
+    }
+}
+
+/// Types a file uploaded for `purpose` may have.
+pub fn allowed_types(purpose: FilePurpose) -> &'static [FileType] {
+    match purpose {
+        FilePurpose::Avatar | FilePurpose::Banner => IMAGES,
+        FilePurpose::Attachment => ATTACHMENTS,
+    }
+}
+
+pub fn max_bytes(purpose: FilePurpose, config: &StorageConfig) -> u64 {
+    match purpose {
+        FilePurpose::Avatar => config.max_avatar_bytes,
+        FilePurpose::Banner => config.max_banner_bytes,
+        FilePurpose::Attachment => config.max_upload_bytes,
+    }
+}
+
+pub fn too_large(purpose: FilePurpose, max_bytes: u64) -> AppError {
+    AppError::PayloadTooLarge(
+        ErrorCode::PayloadTooLarge,
+        format!(
+            "Files uploaded as {} must not exceed {max_bytes} bytes",
+            purpose_name(purpose)
+        ),
+    )
+}
+
+/// The allowed type a client names for a `purpose` upload, ignoring
+/// parameters and case. `image/jpg` is taken for `image/jpeg`.
+pub fn allowed_type(purpose: FilePurpose, declared: &str) -> Result<FileType, AppError> {
+    let essence = declared
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let essence = match essence.as_str() {
+        "image/jpg" => JPEG.mime,
+        other => other,
+    };
+    allowed_types(purpose)
+        .iter()
+        .find(|file_type| file_type.mime == essence)
+        .copied()
+        .ok_or_else(|| not_allowed(purpose, essence))
+}
+
+/// Accepts a file whose content is `detected` for `purpose`. The client's
+/// `declared` type must match it, unless it declared none or
+/// `application/octet-stream`.
+pub fn check(
+    purpose: FilePurpose,
+    declared: Option<&str>,
+    detected: FileType,
+) -> Result<(), AppError> {
+    if !allowed_types(purpose).contains(&detected) {
+        return Err(not_allowed(purpose, detected.mime));
+    }
+    let Some(declared) = declared.filter(|declared| {
+        !declared
+            .trim()
+            .to_ascii_lowercase()
+            .starts_with(UNKNOWN.mime)
+    }) else {
+        return Ok(());
+    };
+    match allowed_type(purpose, declared) {
+        Ok(declared) if declared == detected => Ok(()),
+        _ => Err(AppError::UnsupportedMediaType(
+            ErrorCode::FileTypeMismatch,
+            format!(
+                "File content is {} but was sent as {declared}",
+                detected.mime
+            ),
+        )),
+    }
+}
+
+fn not_allowed(purpose: FilePurpose, mime: &str) -> AppError {
+    let allowed: Vec<_> = allowed_types(purpose)
+        .iter()
+        .map(|file_type| file_type.mime)
+        .collect();
+    AppError::UnsupportedMediaType(
+        ErrorCode::FileTypeNotAllowed,
+        format!(
+            "{mime} is not allowed for {} uploads. Allowed: {}",
+            purpose_name(purpose),
+            allowed.join(", ")
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{ResponseError, http::StatusCode};
+
+    use super::*;
+
+    #[test]
+    fn sniffs_signatures() {
+        let cases: &[(&[u8], FileType)] = &[
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", PNG),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", JPEG),
+            (b"GIF87a\x01\0", GIF),
+            (b"GIF89a\x01\0", GIF),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", WEBP),
+            (b"%PDF-1.7\n", PDF),
+            (b"PK\x03\x04\x14\0", ZIP),
+            (b"PK\x05\x06\0\0", ZIP),
+            (b"plain notes\r\n\tindented\n", TEXT),
+        ];
+        for (head, expected) in cases {
+            assert_eq!(sniff(head), *expected, "{head:?}");
+        }
+        // Near misses are not taken for the real thing.
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WAVEfmt "), UNKNOWN);
+        assert_eq!(sniff(b"\x89PNG"), UNKNOWN);
+        assert_eq!(sniff(b"\0\x01\x02\x03"), UNKNOWN);
+    }
+
+    #[test]
+    fn markup_is_found_behind_a_bom_or_whitespace() {
+        for head in [
+            &b"<html><body>hi</body></html>"[..],
+            b"\xef\xbb\xbf<!DOCTYPE html>",
+            b"\n\n   <svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            b"\xef\xbb\xbf \t<?xml version=\"1.0\"?><svg/>",
+        ] {
+            assert_eq!(sniff(head), MARKUP, "{head:?}");
+            let error = check(FilePurpose::Attachment, None, MARKUP).unwrap_err();
+            assert_eq!(error.code(), ErrorCode::FileTypeNotAllowed);
+            assert_eq!(error.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+    }
+
+    #[test]
+    fn text_may_be_cut_mid_character() {
+        let text = "naïve café ✓".as_bytes();
+        // Cut inside the final three-byte character.
+        assert_eq!(sniff(&text[..text.len() - 1]), TEXT);
+        assert_eq!(sniff(&text[..text.len() - 2]), TEXT);
+        // An invalid byte before the end is not text.
+        assert_eq!(sniff(b"caf\xe9 au lait"), UNKNOWN);
+    }
+
+    #[test]
+    fn empty_files_are_unknown() {
+        assert_eq!(sniff(b""), UNKNOWN);
+        assert_eq!(sniff(b"\xef\xbb\xbf"), UNKNOWN);
+        let error = check(FilePurpose::Attachment, None, UNKNOWN).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::FileTypeNotAllowed);
+    }
+
+    #[test]
+    fn declared_type_must_match_the_content() {
+        let error = check(FilePurpose::Avatar, Some("image/png"), JPEG).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::FileTypeMismatch);
+        assert_eq!(error.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        // Declaring nothing in particular leaves it to the content.
+        assert!(check(FilePurpose::Avatar, None, PNG).is_ok());
+        assert!(check(FilePurpose::Avatar, Some("application/octet-stream"), PNG).is_ok());
+        assert!(check(FilePurpose::Avatar, Some("IMAGE/PNG; charset=binary"), PNG).is_ok());
+        // Allowed content is still refused for a purpose that does not take it.
+        let error = check(FilePurpose::Avatar, Some("application/pdf"), PDF).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::FileTypeNotAllowed);
+    }
+
+    #[test]
+    fn image_jpg_is_taken_for_image_jpeg() {
+        assert_eq!(
+            allowed_type(FilePurpose::Avatar, "image/jpg").unwrap(),
+            JPEG
+        );
+        assert_eq!(
+            allowed_type(FilePurpose::Banner, "Image/JPG").unwrap(),
+            JPEG
+        );
+        assert!(check(FilePurpose::Avatar, Some("image/jpg"), JPEG).is_ok());
+        assert_eq!(
+            allowed_type(FilePurpose::Avatar, "text/html")
+                .unwrap_err()
+                .code(),
+            ErrorCode::FileTypeNotAllowed
+        );
+    }
+}
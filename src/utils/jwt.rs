@@ -1,10 +1,12 @@
 use std::future::{self, Ready};
 
-use crate::utils::constants::JWT_SECRET;
-use actix_web::{
-    Error, FromRequest, HttpMessage, HttpRequest, dev::Payload, error::ErrorBadRequest,
+use crate::utils::{
+    app_error::{AppError, ErrorCode},
+    config::AuthConfig,
 };
+use actix_web::{FromRequest, HttpMessage, HttpRequest, dev::Payload};
 use chrono::{Duration, Utc};
+use entity::sea_orm_active_enums::Role;
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use serde::{Deserialize, Serialize};
 
@@ -14,23 +16,38 @@ pub struct JwtClaims {
     pub exp: usize,
     pub iat: usize,
     pub email: String,
+    pub role: Role,
+    /// Refresh token family this access token was issued for.
+    pub sid: String,
 }
 
 impl FromRequest for JwtClaims {
-    type Error = Error;
+    type Error = AppError;
     type Future = Ready<Result<Self, Self::Error>>;
 
-    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Ready<Result<JwtClaims, Error>> {
+    fn from_request(
+        req: &HttpRequest,
+        _payload: &mut Payload,
+    ) -> Ready<Result<JwtClaims, AppError>> {
         match req.extensions().get::<JwtClaims>() {
             Some(claims) => future::ready(Ok(claims.clone())),
-            None => future::ready(Err(ErrorBadRequest("Unauthorized".to_string()))),
+            None => future::ready(Err(AppError::Unauthorized(
+                ErrorCode::AuthTokenMissing,
+                "Unauthorized".to_string(),
+            ))),
         }
     }
 }
 
-pub fn generate_jwt(user_id: i32, email: String) -> Result<String, jsonwebtoken::errors::Error> {
+pub fn generate_jwt(
+    auth: &AuthConfig,
+    user_id: i32,
+    email: String,
+    role: Role,
+    sid: String,
+) -> Result<String, jsonwebtoken::errors::Error> {
     let now = Utc::now();
-    let exp = Duration::hours(24);
+    let exp = Duration::minutes(i64::from(auth.access_token_ttl_minutes));
     let iat = now.timestamp() as usize;
 
     let claims = JwtClaims {
@@ -38,9 +55,11 @@ pub fn generate_jwt(user_id: i32, email: String) -> Result<String, jsonwebtoken:
         exp: (now + exp).timestamp() as usize,
         iat,
         email,
+        role,
+        sid,
     };
 
-    let secret = JWT_SECRET.as_bytes();
+    let secret = auth.jwt_secret.as_bytes();
 
     encode(
         &Header::default(),
@@ -49,8 +68,11 @@ pub fn generate_jwt(user_id: i32, email: String) -> Result<String, jsonwebtoken:
     )
 }
 
-pub fn decode_jwt(token: String) -> Result<JwtClaims, jsonwebtoken::errors::Error> {
-    let secret = JWT_SECRET.as_bytes();
+pub fn decode_jwt(
+    auth: &AuthConfig,
+    token: String,
+) -> Result<JwtClaims, jsonwebtoken::errors::Error> {
+    let secret = auth.jwt_secret.as_bytes();
     let token = decode::<JwtClaims>(
         &token,
         &DecodingKey::from_secret(secret),
@@ -0,0 +1,956 @@
+use std::future::{Ready, ready};
+
+use actix_web::{FromRequest, HttpRequest, dev::Payload, web};
+use chrono::{DateTime, Days, NaiveDate};
+use sea_orm::{
+    ColumnTrait, Condition, ConnectionTrait, EntityTrait, ModelTrait, Order, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect, Select, Value, prelude::DateTimeWithTimeZone,
+    sea_query::SimpleExpr,
+};
+use serde::Serialize;
+
+use crate::utils::{
+    app_error::{AppError, ErrorCode, FieldError},
+    cursor::{Cursor, CursorValue, Keyset, PaginationMode},
+};
+
+pub const DEFAULT_PER_PAGE: u64 = 10;
+pub const MAX_PER_PAGE: u64 = 100;
+const MAX_IN_VALUES: usize = 100;
+
+/// Comparison requested by `filter[field][op]=value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    In,
+    Gte,
+    Lte,
+    IsNull,
+}
+
+impl FilterOp {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "eq" => Some(FilterOp::Eq),
+            "in" => Some(FilterOp::In),
+            "gte" => Some(FilterOp::Gte),
+            "lte" => Some(FilterOp::Lte),
+            "is_null" => Some(FilterOp::IsNull),
+            _ => None,
+        }
+    }
+}
+
+/// One `filter[...]` query parameter, not yet checked against a [`ListSpec`].
+#[derive(Debug, Clone)]
+pub struct FilterParam {
+    pub field: String,
+    pub op: String,
+    pub value: String,
+}
+
+/// Query parameters shared by every list endpoint. Endpoint-specific
+/// parameters such as `include` are left for the handler's own extractor.
+///
+/// `sort=-created_at,title` sorts by several fields, `-` meaning descending.
+/// The older `sort_by` and `sort_order` pair is still accepted.
+#[derive(Debug, Default)]
+pub struct ListParams {
+    pub page: u64,
+    pub limit: u64,
+    pub search: Option<String>,
+    pub sort: Option<String>,
+    pub sort_by: Option<String>,
+    pub sort_order: Option<String>,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    pub pagination: Option<String>,
+    pub cursor: Option<String>,
+    pub include_total: Option<bool>,
+    pub filters: Vec<FilterParam>,
+}
+
+impl ListParams {
+    pub fn from_query(query: &str) -> Result<Self, AppError> {
+        let pairs = web::Query::<Vec<(String, String)>>::from_query(query)
+            .map_err(|e| {
+                AppError::BadRequest(
+                    ErrorCode::InvalidRequest,
+                    format!("Invalid query string: {e}"),
+                )
+            })?
+            .into_inner();
+
+        let mut params = ListParams::default();
+        let mut problems = Vec::new();
+        let mut page = None;
+        let mut limit = None;
+
+        for (key, value) in pairs {
+            let value = Some(value).filter(|value| !value.is_empty());
+            match key.as_str() {
+                "page" => page = value,
+                "limit" => limit = value,
+                "search" => params.search = value,
+                "sort" => params.sort = value,
+                "sort_by" => params.sort_by = value,
+                "sort_order" => params.sort_order = value,
+                "start_date" => params.start_date = value,
+                "end_date" => params.end_date = value,
+                "pagination" => params.pagination = value,
+                "cursor" => params.cursor = value,
+                "include_total" => match value.as_deref() {
+                    None => {}
+                    Some("true") => params.include_total = Some(true),
+                    Some("false") => params.include_total = Some(false),
+                    Some(_) => {
+                        problems.push(FieldError::new("include_total", "must be true or false"))
+                    }
+                },
+                _ if key.starts_with("filter[") => match parse_filter_key(&key) {
+                    Some((field, op)) => params.filters.push(FilterParam {
+                        field: field.to_string(),
+                        op: op.to_string(),
+                        value: value.unwrap_or_default(),
+                    }),
+                    None => problems.push(FieldError::new(
+                        key.clone(),
+                        "must look like filter[field] or filter[field][op]",
+                    )),
+                },
+                _ => {}
+            }
+        }
+
+        // Out-of-range values are clamped as before; only non-numbers are rejected.
+        params.page = match page.map(|raw| raw.parse::<u64>()) {
+            None => 1,
+            Some(Ok(page)) => page.max(1),
+            Some(Err(_)) => {
+                problems.push(FieldError::new("page", "must be a positive integer"));
+                1
+            }
+        };
+        params.limit = match limit.map(|raw| raw.parse::<u64>()) {
+            None | Some(Ok(0)) => DEFAULT_PER_PAGE,
+            Some(Ok(limit)) => limit.min(MAX_PER_PAGE),
+            Some(Err(_)) => {
+                problems.push(FieldError::new("limit", "must be a positive integer"));
+                DEFAULT_PER_PAGE
+            }
+        };
+
+        if !problems.is_empty() {
+            return Err(AppError::Validation(problems));
+        }
+        Ok(params)
+    }
+
+    /// The requested sort in `sort=` syntax, translating `sort_by`/`sort_order`.
+    fn requested_sort(&self, default_field: &str) -> Option<String> {
+        if self.sort.is_some() {
+            return self.sort.clone();
+        }
+        if self.sort_by.is_none() && self.sort_order.is_none() {
+            return None;
+        }
+        let field = self.sort_by.as_deref().unwrap_or(default_field);
+        Some(match self.sort_order.as_deref() {
+            Some("asc") => field.to_string(),
+            _ => format!("-{field}"),
+        })
+    }
+}
+
+/// `filter[field]` or `filter[field][op]`; a bare field means `eq`.
+fn parse_filter_key(key: &str) -> Option<(&str, &str)> {
+    let rest = key.strip_prefix("filter[")?;
+    let (field, rest) = rest.split_once(']')?;
+    let op = match rest {
+        "" => "eq",
+        _ => rest.strip_prefix('[')?.strip_suffix(']')?,
+    };
+    (!field.is_empty()).then_some((field, op))
+}
+
+impl FromRequest for ListParams {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Self::from_query(req.query_string()).map_err(Into::into))
+    }
+}
+
+/// How a filterable column's values are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Integer,
+    Text,
+    /// RFC 3339 timestamps, or `YYYY-MM-DD` meaning the whole UTC day.
+    Timestamp,
+}
+
+/// Start of a UTC day and start of the next one.
+fn day_bounds(date: NaiveDate) -> (DateTimeWithTimeZone, DateTimeWithTimeZone) {
+    let start = date.and_time(Default::default()).and_utc().fixed_offset();
+    let end = date
+        .checked_add_days(Days::new(1))
+        .unwrap_or(date)
+        .and_time(Default::default())
+        .and_utc()
+        .fixed_offset();
+    (start, end)
+}
+
+fn parse_date(raw: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+        .map_err(|_| "Invalid date format. Use YYYY-MM-DD".to_string())
+}
+
+fn parse_value(kind: FieldKind, raw: &str) -> Result<Value, String> {
+    match kind {
+        FieldKind::Integer => raw
+            .parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| format!("\"{raw}\" is not an integer")),
+        FieldKind::Text => Ok(Value::from(raw.to_string())),
+        FieldKind::Timestamp => DateTime::parse_from_rfc3339(raw)
+            .map(Value::from)
+            .map_err(|_| format!("\"{raw}\" is not an RFC 3339 timestamp or YYYY-MM-DD date")),
+    }
+}
+
+fn filter_condition<C: ColumnTrait>(
+    column: C,
+    kind: FieldKind,
+    op: FilterOp,
+    raw: &str,
+) -> Result<SimpleExpr, String> {
+    // Dates cover the whole day: `eq` matches any time that day, `lte` includes it.
+    if kind == FieldKind::Timestamp
+        && op != FilterOp::In
+        && op != FilterOp::IsNull
+        && let Ok(date) = NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+    {
+        let (start, end) = day_bounds(date);
+        return Ok(match op {
+            FilterOp::Gte => column.gte(start),
+            FilterOp::Lte => column.lt(end),
+            _ => column.gte(start).and(column.lt(end)),
+        });
+    }
+
+    match op {
+        FilterOp::Eq => Ok(column.eq(parse_value(kind, raw)?)),
+        FilterOp::Gte => Ok(column.gte(parse_value(kind, raw)?)),
+        FilterOp::Lte => Ok(column.lte(parse_value(kind, raw)?)),
+        FilterOp::In => {
+            let values = raw
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| parse_value(kind, value))
+                .collect::<Result<Vec<_>, _>>()?;
+            if values.is_empty() || values.len() > MAX_IN_VALUES {
+                return Err(format!(
+                    "must list between 1 and {MAX_IN_VALUES} comma-separated values"
+                ));
+            }
+            Ok(column.is_in(values))
+        }
+        FilterOp::IsNull => match raw {
+            "true" => Ok(column.is_null()),
+            "false" => Ok(column.is_not_null()),
+            _ => Err("must be true or false".to_string()),
+        },
+    }
+}
+
+/// One requested sort field, resolved against a [`ListSpec`].
+struct SortKey<C> {
+    name: &'static str,
+    column: C,
+    ascending: bool,
+}
+
+/// Which sorts, filters and searches a list endpoint allows, for one entity.
+///
+/// ```ignore
+/// let spec = ListSpec::new(user::Column::Id)
+///     .sortable("name", user::Column::Name)
+///     .filterable("role", user::Column::Role, FieldKind::Text)
+///     .searchable(user::Column::Name)
+///     .date_range(user::Column::CreatedAt)
+///     .default_sort("-created_at");
+/// let page = spec.plan(&params, secret)?.fetch(db, user::Entity::find()).await?;
+/// ```
+pub struct ListSpec<E: EntityTrait> {
+    id: E::Column,
+    sortable: Vec<(&'static str, E::Column)>,
+    filterable: Vec<(&'static str, E::Column, FieldKind)>,
+    searchable: Vec<E::Column>,
+    date_range: Option<E::Column>,
+    default_sort: &'static str,
+    relevance: bool,
+}
+
+impl<E: EntityTrait> ListSpec<E> {
+    /// `id` is sortable as `id` and breaks ties so the order is always total.
+    pub fn new(id: E::Column) -> Self {
+        Self {
+            id,
+            sortable: vec![("id", id)],
+            filterable: Vec::new(),
+            searchable: Vec::new(),
+            date_range: None,
+            default_sort: "-id",
+            relevance: false,
+        }
+    }
+
+    pub fn sortable(mut self, name: &'static str, column: E::Column) -> Self {
+        self.sortable.push((name, column));
+        self
+    }
+
+    pub fn filterable(mut self, name: &'static str, column: E::Column, kind: FieldKind) -> Self {
+        self.filterable.push((name, column, kind));
+        self
+    }
+
+    /// Columns matched by `search` with a substring match. Endpoints with their
+    /// own search, such as full-text, leave this empty and read
+    /// [`ListPlan::search`].
+    pub fn searchable(mut self, column: E::Column) -> Self {
+        self.searchable.push(column);
+        self
+    }
+
+    /// Column filtered by `start_date` and `end_date`.
+    pub fn date_range(mut self, column: E::Column) -> Self {
+        self.date_range = Some(column);
+        self
+    }
+
+    /// Sort used when the request gives none, in `sort=` syntax. Must only name
+    /// sortable fields.
+    pub fn default_sort(mut self, sort: &'static str) -> Self {
+        self.default_sort = sort;
+        self
+    }
+
+    /// Accepts `relevance` as the leading sort key, ordered by the expression
+    /// the handler passes to [`ListPlan::rank_by`]. Searches in page mode sort
+    /// by relevance unless another sort is requested.
+    pub fn relevance(mut self) -> Self {
+        self.relevance = true;
+        self
+    }
+
+    fn sortable_names(&self) -> String {
+        let mut names: Vec<_> = self.sortable.iter().map(|(name, _)| *name).collect();
+        if self.relevance {
+            names.insert(0, "relevance");
+        }
+        names.join(", ")
+    }
+
+    /// Parses `raw` into sort keys, returning whether relevance leads them.
+    fn parse_sort(
+        &self,
+        raw: &str,
+        mode: PaginationMode,
+        problems: &mut Vec<FieldError>,
+    ) -> (bool, Vec<SortKey<E::Column>>) {
+        let mut relevance = false;
+        let mut keys: Vec<SortKey<E::Column>> = Vec::new();
+
+        for (i, part) in raw.split(',').map(str::trim).enumerate() {
+            let (name, ascending) = match part.strip_prefix('-') {
+                Some(name) => (name, false),
+                None => (part.strip_prefix('+').unwrap_or(part), true),
+            };
+            if name.is_empty() {
+                continue;
+            }
+
+            if name == "relevance" && self.relevance {
+                if i != 0 {
+                    problems.push(FieldError::new(
+                        "sort",
+                        "relevance must be the first sort key",
+                    ));
+                } else if mode == PaginationMode::Cursor {
+                    problems.push(FieldError::new(
+                        "sort",
+                        "relevance cannot be combined with cursor pagination",
+                    ));
+                }
+                relevance = true;
+                continue;
+            }
+
+            match self.sortable.iter().find(|(sortable, _)| *sortable == name) {
+                Some(_) if keys.iter().any(|key| key.name == name) => {}
+                Some((name, column)) => keys.push(SortKey {
+                    name,
+                    column: *column,
+                    ascending,
+                }),
+                None => problems.push(FieldError::new(
+                    "sort",
+                    format!(
+                        "Unknown sort field \"{name}\". Supported: {}",
+                        self.sortable_names()
+                    ),
+                )),
+            }
+        }
+
+        (relevance, keys)
+    }
+
+    /// Validates the request against this spec. Every problem is reported at once.
+    pub fn plan(&self, params: &ListParams, secret: &str) -> Result<ListPlan<E>, AppError> {
+        let mut problems = Vec::new();
+
+        let mode = PaginationMode::parse(params.pagination.as_deref(), params.cursor.as_deref())
+            .unwrap_or_else(|e| {
+                if let AppError::Validation(details) = e {
+                    problems.extend(details);
+                }
+                PaginationMode::Page
+            });
+
+        let search = params
+            .search
+            .as_deref()
+            .map(str::trim)
+            .filter(|search| !search.is_empty())
+            .map(str::to_string);
+
+        // Sorting
+        let default_field = self.default_sort.trim_start_matches(['-', '+']);
+        let (relevance, mut keys) = match params.requested_sort(default_field) {
+            Some(raw) => self.parse_sort(&raw, mode, &mut problems),
+            None => (
+                self.relevance && search.is_some() && mode == PaginationMode::Page,
+                Vec::new(),
+            ),
+        };
+        if keys.is_empty() {
+            keys = self.parse_sort(self.default_sort, mode, &mut problems).1;
+        }
+        if !keys.iter().any(|key| key.name == "id") {
+            let ascending = keys.last().is_some_and(|key| key.ascending);
+            keys.push(SortKey {
+                name: "id",
+                column: self.id,
+                ascending,
+            });
+        }
+
+        // Filtering
+        let mut condition = Condition::all();
+
+        if let Some(search) = &search
+            && !self.searchable.is_empty()
+        {
+            condition = condition.add(
+                self.searchable
+                    .iter()
+                    .fold(Condition::any(), |any, column| {
+                        any.add(column.contains(search))
+                    }),
+            );
+        }
+
+        if let Some(column) = self.date_range {
+            for (field, raw, op) in [
+                ("start_date", &params.start_date, FilterOp::Gte),
+                ("end_date", &params.end_date, FilterOp::Lte),
+            ] {
+                let Some(raw) = raw else { continue };
+                match parse_date(raw) {
+                    Ok(_) => {
+                        let expr = filter_condition(column, FieldKind::Timestamp, op, raw)
+                            .expect("a valid date always builds a condition");
+                        condition = condition.add(expr);
+                    }
+                    Err(message) => problems.push(FieldError::new(field, message)),
+                }
+            }
+        }
+
+        for filter in &params.filters {
+            let field = format!("filter[{}]", filter.field);
+            let Some((_, column, kind)) = self
+                .filterable
+                .iter()
+                .find(|(name, _, _)| *name == filter.field)
+            else {
+                let supported: Vec<_> = self.filterable.iter().map(|(name, _, _)| *name).collect();
+                problems.push(FieldError::new(
+                    field,
+                    format!("Unknown filter field. Supported: {}", supported.join(", ")),
+                ));
+                continue;
+            };
+            let Some(op) = FilterOp::parse(&filter.op) else {
+                problems.push(FieldError::new(
+                    field,
+                    format!(
+                        "Unknown filter operator \"{}\". Supported: eq, in, gte, lte, is_null",
+                        filter.op
+                    ),
+                ));
+                continue;
+            };
+            match filter_condition(*column, *kind, op, &filter.value) {
+                Ok(expr) => condition = condition.add(expr),
+                Err(message) => problems.push(FieldError::new(field, message)),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(AppError::Validation(problems));
+        }
+
+        let sort = keys
+            .iter()
+            .map(|key| {
+                if key.ascending {
+                    key.name.to_string()
+                } else {
+                    format!("-{}", key.name)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let keys: Vec<_> = keys
+            .into_iter()
+            .map(|key| (key.column, key.ascending))
+            .collect();
+
+        let cursor = Keyset {
+            sort: &sort,
+            keys: &keys,
+            secret,
+        }
+        .decode(params.cursor.as_deref())?;
+
+        Ok(ListPlan {
+            mode,
+            page: params.page,
+            per_page: params.limit,
+            include_total: params.include_total.unwrap_or(mode == PaginationMode::Page),
+            relevance,
+            search,
+            condition,
+            keys,
+            sort,
+            cursor,
+            rank: None,
+            secret: secret.to_string(),
+        })
+    }
+}
+
+/// A validated listing request, ready to run against a base query.
+pub struct ListPlan<E: EntityTrait> {
+    mode: PaginationMode,
+    page: u64,
+    per_page: u64,
+    include_total: bool,
+    relevance: bool,
+    search: Option<String>,
+    condition: Condition,
+    keys: Vec<(E::Column, bool)>,
+    sort: String,
+    cursor: Option<Cursor>,
+    rank: Option<SimpleExpr>,
+    secret: String,
+}
+
+impl<E: EntityTrait> ListPlan<E> {
+    /// The trimmed, non-empty `search` parameter.
+    pub fn search(&self) -> Option<&str> {
+        self.search.as_deref()
+    }
+
+    /// True when results should lead with the handler's relevance ranking.
+    pub fn sorts_by_relevance(&self) -> bool {
+        self.relevance
+    }
+
+    /// Orders by `rank` (descending) ahead of the other sort keys.
+    pub fn rank_by(&mut self, rank: SimpleExpr) {
+        if self.relevance {
+            self.rank = Some(rank);
+        }
+    }
+
+    /// Applies filters, sorting and pagination to `select` and runs it.
+    pub async fn fetch<C>(self, db: &C, select: Select<E>) -> Result<Page<E::Model>, AppError>
+    where
+        C: ConnectionTrait,
+        E::Model: Send + Sync,
+    {
+        let select = select.filter(self.condition);
+
+        if self.mode == PaginationMode::Cursor {
+            // Totals ignore the cursor; they describe the whole filtered listing.
+            let total_items = if self.include_total {
+                Some(select.clone().count(db).await?)
+            } else {
+                None
+            };
+
+            let keyset = Keyset {
+                sort: &self.sort,
+                keys: &self.keys,
+                secret: &self.secret,
+            };
+            let rows = keyset
+                .apply(select, self.cursor.as_ref())
+                .limit(self.per_page + 1)
+                .all(db)
+                .await?;
+            let page = keyset.page(rows, self.per_page, self.cursor.as_ref(), |row| {
+                self.keys
+                    .iter()
+                    .map(|(column, _)| CursorValue::try_from(row.get(*column)))
+                    .collect()
+            })?;
+
+            return Ok(Page {
+                items: page.items,
+                pagination: PaginationMeta {
+                    current_page: None,
+                    per_page: self.per_page,
+                    total_items,
+                    total_pages: total_items.map(|total| total.div_ceil(self.per_page)),
+                    next_cursor: page.next_cursor,
+                    prev_cursor: page.prev_cursor,
+                },
+            });
+        }
+
+        let mut select = select;
+        if let Some(rank) = self.rank {
+            select = select.order_by(rank, Order::Desc);
+        }
+        for (column, ascending) in &self.keys {
+            select = if *ascending {
+                select.order_by_asc(*column)
+            } else {
+                select.order_by_desc(*column)
+            };
+        }
+
+        let paginator = select.paginate(db, self.per_page);
+        let totals = if self.include_total {
+            Some(paginator.num_items_and_pages().await?)
+        } else {
+            None
+        };
+        let items = paginator.fetch_page(self.page - 1).await?;
+
+        Ok(Page {
+            items,
+            pagination: PaginationMeta {
+                current_page: Some(self.page),
+                per_page: self.per_page,
+                total_items: totals.as_ref().map(|totals| totals.number_of_items),
+                total_pages: totals.map(|totals| totals.number_of_pages),
+                next_cursor: None,
+                prev_cursor: None,
+            },
+        })
+    }
+}
+
+/// Page-number mode fills `current_page` and the totals; cursor mode fills
+/// the cursors. Totals are only present when counted.
+#[derive(Debug, Serialize)]
+pub struct PaginationMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_page: Option<u64>,
+    pub per_page: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_pages: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_cursor: Option<String>,
+}
+
+/// Response envelope shared by every list endpoint.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub pagination: PaginationMeta,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            pagination: self.pagination,
+        }
+    }
+
+    /// Response message such as "Posts found: 12 (page 1 of 2)".
+    pub fn summary(&self, noun: &str) -> String {
+        let pagination = &self.pagination;
+        match (
+            pagination.current_page,
+            pagination.total_items,
+            pagination.total_pages,
+        ) {
+            (Some(page), Some(total_items), Some(total_pages)) => {
+                format!("{noun} found: {total_items} (page {page} of {total_pages})")
+            }
+            (Some(page), _, _) => format!("{noun} found: {} (page {page})", self.items.len()),
+            _ => format!("{noun} found: {}", self.items.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::post;
+    use sea_orm::{
+        DbBackend, QueryTrait,
+        sea_query::{PostgresQueryBuilder, QueryBuilder},
+    };
+
+    use super::*;
+    use crate::utils::cursor::Direction;
+
+    const SECRET: &str = "list-test-secret";
+
+    fn spec() -> ListSpec<post::Entity> {
+        ListSpec::new(post::Column::Id)
+            .sortable("created_at", post::Column::CreatedAt)
+            .sortable("title", post::Column::Title)
+            .filterable("user_id", post::Column::UserId, FieldKind::Integer)
+            .filterable("created_at", post::Column::CreatedAt, FieldKind::Timestamp)
+            .date_range(post::Column::CreatedAt)
+            .relevance()
+            .default_sort("-created_at")
+    }
+
+    fn plan(query: &str) -> Result<ListPlan<post::Entity>, AppError> {
+        spec().plan(&ListParams::from_query(query)?, SECRET)
+    }
+
+    /// Fields of the validation problems, in the order they were found.
+    fn problems(query: &str) -> Vec<String> {
+        match plan(query) {
+            Err(AppError::Validation(details)) => {
+                details.into_iter().map(|detail| detail.field).collect()
+            }
+            Err(other) => panic!("{query}: unexpected error {other:?}"),
+            Ok(_) => panic!("{query}: expected validation problems"),
+        }
+    }
+
+    /// `raw` as it appears in Postgres SQL; releases of sea-query differ in
+    /// how many fractional digits they print.
+    fn literal(raw: &str) -> String {
+        let timestamp: DateTimeWithTimeZone = raw.parse().unwrap();
+        PostgresQueryBuilder.value_to_string(&Value::from(timestamp))
+    }
+
+    fn where_clause(plan: ListPlan<post::Entity>) -> String {
+        let sql = post::Entity::find()
+            .filter(plan.condition)
+            .build(DbBackend::Postgres)
+            .to_string();
+        sql.split_once(" WHERE ")
+            .map(|(_, condition)| condition.to_string())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn parses_filter_keys() {
+        let params = ListParams::from_query(
+            "filter[user_id]=7&filter[created_at][gte]=2026-01-01&filter[title][is_null]=",
+        )
+        .unwrap();
+        let filters: Vec<_> = params
+            .filters
+            .iter()
+            .map(|filter| {
+                (
+                    filter.field.as_str(),
+                    filter.op.as_str(),
+                    filter.value.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            filters,
+            [
+                ("user_id", "eq", "7"),
+                ("created_at", "gte", "2026-01-01"),
+                ("title", "is_null", ""),
+            ]
+        );
+
+        for malformed in [
+            "filter[]=1",
+            "filter[user_id=1",
+            "filter[user_id]x=1",
+            "filter[user_id][eq=1",
+        ] {
+            assert!(
+                matches!(
+                    ListParams::from_query(malformed),
+                    Err(AppError::Validation(_))
+                ),
+                "{malformed}"
+            );
+        }
+    }
+
+    #[test]
+    fn clamps_paging_and_rejects_non_numbers() {
+        let params = ListParams::from_query("page=0&limit=1000").unwrap();
+        assert_eq!((params.page, params.limit), (1, MAX_PER_PAGE));
+        let params = ListParams::from_query("limit=0").unwrap();
+        assert_eq!((params.page, params.limit), (1, DEFAULT_PER_PAGE));
+
+        let Err(AppError::Validation(details)) =
+            ListParams::from_query("page=x&limit=-1&include_total=yes")
+        else {
+            panic!("expected validation problems");
+        };
+        let fields: Vec<_> = details.iter().map(|detail| detail.field.as_str()).collect();
+        assert_eq!(fields, ["include_total", "page", "limit"]);
+    }
+
+    #[test]
+    fn sorts_by_several_fields_and_breaks_ties_by_id() {
+        assert_eq!(plan("").unwrap().sort, "-created_at,-id");
+        assert_eq!(
+            plan("sort=title,-created_at").unwrap().sort,
+            "title,-created_at,-id"
+        );
+        assert_eq!(plan("sort=+title,title").unwrap().sort, "title,id");
+        assert_eq!(plan("sort=-id").unwrap().sort, "-id");
+        assert_eq!(problems("sort=views,-likes"), ["sort", "sort"]);
+    }
+
+    #[test]
+    fn legacy_sort_parameters() {
+        assert_eq!(
+            plan("sort_by=title&sort_order=asc").unwrap().sort,
+            "title,id"
+        );
+        assert_eq!(plan("sort_by=title").unwrap().sort, "-title,-id");
+        assert_eq!(plan("sort_order=asc").unwrap().sort, "created_at,id");
+        // `sort` wins over the older pair.
+        assert_eq!(
+            plan("sort=title&sort_by=created_at&sort_order=desc")
+                .unwrap()
+                .sort,
+            "title,id"
+        );
+    }
+
+    #[test]
+    fn relevance_sort() {
+        let searched = plan("search=rust").unwrap();
+        assert!(searched.sorts_by_relevance());
+        assert_eq!(searched.sort, "-created_at,-id");
+        assert!(plan("sort=relevance,title").unwrap().sorts_by_relevance());
+        assert!(!plan("search=rust&sort=title").unwrap().sorts_by_relevance());
+        // Cursors cannot encode a rank, so cursor mode never defaults to it.
+        assert!(
+            !plan("search=rust&pagination=cursor")
+                .unwrap()
+                .sorts_by_relevance()
+        );
+
+        assert_eq!(problems("sort=title,relevance"), ["sort"]);
+        assert_eq!(problems("sort=relevance&pagination=cursor"), ["sort"]);
+    }
+
+    #[test]
+    fn cursors_must_match_the_sort() {
+        let cursor = Cursor {
+            sort: "-created_at,-id".to_string(),
+            values: vec![
+                CursorValue::Timestamp("2026-01-02T03:04:05Z".parse().unwrap()),
+                CursorValue::Integer(42),
+            ],
+            direction: Direction::Next,
+        }
+        .encode(SECRET);
+
+        let same = plan(&format!("cursor={cursor}")).unwrap();
+        assert_eq!(same.mode, PaginationMode::Cursor);
+        assert!(same.cursor.is_some());
+        assert!(!same.include_total);
+
+        let error = plan(&format!("cursor={cursor}&sort=title")).err().unwrap();
+        assert_eq!(error.code(), ErrorCode::InvalidCursor);
+    }
+
+    #[test]
+    fn builds_filter_conditions() {
+        assert_eq!(
+            where_clause(plan("filter[user_id][in]=1, 2,3").unwrap()),
+            r#""post"."user_id" IN (1, 2, 3)"#
+        );
+        // A date covers the whole UTC day.
+        let day = format!(
+            r#""post"."created_at" >= {} AND "post"."created_at" < {}"#,
+            literal("2026-01-02T00:00:00Z"),
+            literal("2026-01-03T00:00:00Z"),
+        );
+        assert_eq!(
+            where_clause(plan("filter[created_at]=2026-01-02").unwrap()),
+            day
+        );
+        assert_eq!(
+            where_clause(plan("start_date=2026-01-02&end_date=2026-01-02").unwrap()),
+            day
+        );
+        assert_eq!(
+            where_clause(plan("filter[created_at][lte]=2026-01-02T10:00:00Z").unwrap()),
+            format!(
+                r#""post"."created_at" <= {}"#,
+                literal("2026-01-02T10:00:00Z")
+            )
+        );
+    }
+
+    #[test]
+    fn reports_every_filter_problem() {
+        assert_eq!(
+            problems(
+                "filter[views]=1&filter[user_id][like]=1&filter[user_id]=x\
+                 &filter[created_at][is_null]=maybe&start_date=yesterday&pagination=offset"
+            ),
+            [
+                "pagination",
+                "start_date",
+                "filter[views]",
+                "filter[user_id]",
+                "filter[user_id]",
+                "filter[created_at]",
+            ]
+        );
+        let too_many = vec!["1"; MAX_IN_VALUES + 1].join(",");
+        assert_eq!(
+            problems(&format!("filter[user_id][in]={too_many}")),
+            ["filter[user_id]"]
+        );
+    }
+}
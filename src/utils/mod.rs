@@ -1,4 +1,23 @@
 pub mod api_response;
+pub mod app_error;
 pub mod app_state;
-pub mod constants;
+pub mod chat;
+pub mod config;
+pub mod cursor;
+pub mod events;
+pub mod file_type;
 pub mod jwt;
+pub mod list_query;
+pub mod password;
+pub mod policy;
+pub mod request_id;
+pub mod schema_check;
+pub mod search;
+pub mod session;
+pub mod storage;
+pub mod upload_sweep;
+pub mod validation;
+pub mod ws_broadcast;
+pub mod ws_hub;
+pub mod ws_protocol;
+pub mod ws_ticket;
@@ -0,0 +1,292 @@
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+
+/// Tunable Argon2id cost parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl HashParams {
+    /// Checks the parameters are within the ranges Argon2 accepts.
+    pub fn validate(self) -> Result<(), argon2::Error> {
+        self.to_argon2().map(|_| ())
+    }
+
+    fn to_argon2(self) -> Result<Params, argon2::Error> {
+        Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+    }
+}
+
+/// Outcome of checking a password against a stored hash.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// The password does not match.
+    Invalid,
+    /// The password matches. `needs_rehash` is set when the stored hash uses a
+    /// legacy scheme or outdated parameters and should be replaced.
+    Valid { needs_rehash: bool },
+}
+
+/// A password storage scheme that can produce and check stored hashes.
+pub trait PasswordScheme {
+    /// Returns true if `stored` was produced by this scheme.
+    fn recognizes(&self, stored: &str) -> bool;
+
+    /// Checks `password` against a hash this scheme recognizes.
+    fn verify(&self, password: &str, stored: &str) -> Verification;
+}
+
+/// Argon2id hashes stored in PHC string format.
+pub struct Argon2idScheme {
+    params: HashParams,
+}
+
+impl Argon2idScheme {
+    pub fn new(params: HashParams) -> Self {
+        Self { params }
+    }
+
+    fn hasher(&self) -> Result<Argon2<'static>, argon2::Error> {
+        Ok(Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            self.params.to_argon2()?,
+        ))
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, argon2::password_hash::Error> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = self
+            .hasher()?
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string();
+        Ok(hash)
+    }
+
+    fn is_current(&self, hash: &PasswordHash) -> bool {
+        if hash.algorithm != Algorithm::Argon2id.ident() {
+            return false;
+        }
+        match Params::try_from(hash) {
+            Ok(params) => {
+                params.m_cost() == self.params.memory_kib
+                    && params.t_cost() == self.params.iterations
+                    && params.p_cost() == self.params.parallelism
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl PasswordScheme for Argon2idScheme {
+    fn recognizes(&self, stored: &str) -> bool {
+        stored.starts_with("$argon2")
+    }
+
+    fn verify(&self, password: &str, stored: &str) -> Verification {
+        let Ok(parsed) = PasswordHash::new(stored) else {
+            return Verification::Invalid;
+        };
+        // Verification reads the parameters from the stored hash itself, so
+        // hashes created with older settings keep working.
+        match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+            Ok(()) => Verification::Valid {
+                needs_rehash: !self.is_current(&parsed),
+            },
+            Err(_) => Verification::Invalid,
+        }
+    }
+}
+
+/// Unsalted hex-encoded SHA-256 digests written by earlier releases.
+pub struct LegacySha256Scheme;
+
+impl PasswordScheme for LegacySha256Scheme {
+    fn recognizes(&self, stored: &str) -> bool {
+        stored.len() == 64 && stored.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    fn verify(&self, password: &str, stored: &str) -> Verification {
+        let digest = sha256::digest(password);
+        if constant_time_eq(digest.as_bytes(), stored.to_ascii_lowercase().as_bytes()) {
+            Verification::Valid { needs_rehash: true }
+        } else {
+            Verification::Invalid
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Hashes new passwords with Argon2id and verifies against every supported scheme.
+pub struct PasswordService {
+    current: Argon2idScheme,
+    legacy: Vec<Box<dyn PasswordScheme + Send + Sync>>,
+    /// Hash with the current parameters that no password is checked against
+    /// successfully; see [`PasswordService::verify_unknown`].
+    dummy: String,
+}
+
+impl PasswordService {
+    pub fn new(params: HashParams) -> Self {
+        let current = Argon2idScheme::new(params);
+        let dummy = current.hash("not a real password").unwrap_or_default();
+        Self {
+            current,
+            legacy: vec![Box::new(LegacySha256Scheme)],
+            dummy,
+        }
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, argon2::password_hash::Error> {
+        self.current.hash(password)
+    }
+
+    pub fn verify(&self, password: &str, stored: &str) -> Verification {
+        if self.current.recognizes(stored) {
+            return self.current.verify(password, stored);
+        }
+        self.legacy
+            .iter()
+            .find(|scheme| scheme.recognizes(stored))
+            .map(|scheme| scheme.verify(password, stored))
+            .unwrap_or(Verification::Invalid)
+    }
+
+    /// Does the work of [`PasswordService::verify`] for an account that does
+    /// not exist, so the response time does not tell whether it does.
+    pub fn verify_unknown(&self, password: &str) -> Verification {
+        self.current.verify(password, &self.dummy);
+        Verification::Invalid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small costs keep the tests fast; they are still valid Argon2 parameters.
+    const PARAMS: HashParams = HashParams {
+        memory_kib: 8,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn argon2id_round_trip() {
+        let service = PasswordService::new(PARAMS);
+        let stored = service.hash("hunter22").unwrap();
+
+        assert!(stored.starts_with("$argon2id$"));
+        assert_eq!(
+            service.verify("hunter22", &stored),
+            Verification::Valid {
+                needs_rehash: false
+            }
+        );
+        assert_eq!(service.verify("hunter23", &stored), Verification::Invalid);
+    }
+
+    #[test]
+    fn legacy_sha256_verifies_and_needs_rehash() {
+        let service = PasswordService::new(PARAMS);
+        let stored = sha256::digest("hunter22");
+
+        assert_eq!(
+            service.verify("hunter22", &stored),
+            Verification::Valid { needs_rehash: true }
+        );
+        assert_eq!(
+            service.verify("hunter22", &stored.to_ascii_uppercase()),
+            Verification::Valid { needs_rehash: true }
+        );
+        assert_eq!(service.verify("hunter23", &stored), Verification::Invalid);
+    }
+
+    #[test]
+    fn outdated_parameters_need_rehash() {
+        let stored = PasswordService::new(PARAMS).hash("hunter22").unwrap();
+        for outdated in [
+            HashParams {
+                memory_kib: 16,
+                ..PARAMS
+            },
+            HashParams {
+                iterations: 2,
+                ..PARAMS
+            },
+            HashParams {
+                parallelism: 2,
+                ..PARAMS
+            },
+        ] {
+            assert_eq!(
+                PasswordService::new(outdated).verify("hunter22", &stored),
+                Verification::Valid { needs_rehash: true },
+                "{outdated:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unrecognized_hash_is_invalid() {
+        let service = PasswordService::new(PARAMS);
+        assert_eq!(service.verify("hunter22", ""), Verification::Invalid);
+        assert_eq!(
+            service.verify("hunter22", "$argon2id$garbage"),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn unknown_account_pays_for_a_current_hash() {
+        let service = PasswordService::new(PARAMS);
+        assert!(service.current.recognizes(&service.dummy));
+        assert!(
+            service
+                .current
+                .is_current(&PasswordHash::new(&service.dummy).unwrap())
+        );
+        assert_eq!(
+            service.verify_unknown("not a real password"),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_params() {
+        assert!(PARAMS.validate().is_ok());
+        for invalid in [
+            HashParams {
+                memory_kib: 0,
+                ..PARAMS
+            },
+            HashParams {
+                iterations: 0,
+                ..PARAMS
+            },
+            HashParams {
+                parallelism: 0,
+                ..PARAMS
+            },
+            // Argon2 needs at least 8 KiB per lane.
+            HashParams {
+                memory_kib: 8,
+                iterations: 1,
+                parallelism: 4,
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use entity::{file, post, sea_orm_active_enums::Role};
+
+use crate::utils::{app_error::AppError, jwt::JwtClaims};
+
+/// Something a caller wants to do to a resource owned by a user.
+pub enum Action<'a> {
+    UpdatePost(&'a post::Model),
+    DeletePost(&'a post::Model),
+    UpdateUser(i32),
+    ReadFile(&'a file::Model),
+    DeleteFile(&'a file::Model),
+}
+
+fn rank(role: Role) -> u8 {
+    match role {
+        Role::User => 0,
+        Role::Moderator => 1,
+        Role::Admin => 2,
+    }
+}
+
+/// Returns true if `role` is `required` or a more privileged role.
+pub fn has_role(role: Role, required: Role) -> bool {
+    rank(role) >= rank(required)
+}
+
+/// Decides whether the caller may perform `action`.
+///
+/// - Posts can only be edited by their author.
+/// - Posts can be deleted by their author, moderators and admins.
+/// - Users can edit themselves; admins can edit anyone.
+/// - Files can be read and deleted by their owner and admins.
+pub fn is_allowed(claims: &JwtClaims, action: &Action) -> bool {
+    match action {
+        Action::UpdatePost(post) => post.user_id == claims.user_id,
+        Action::DeletePost(post) => {
+            post.user_id == claims.user_id || has_role(claims.role, Role::Moderator)
+        }
+        Action::UpdateUser(user_id) => {
+            *user_id == claims.user_id || has_role(claims.role, Role::Admin)
+        }
+        Action::ReadFile(file) | Action::DeleteFile(file) => {
+            file.owner_id == claims.user_id || has_role(claims.role, Role::Admin)
+        }
+    }
+}
+
+/// Like [`is_allowed`], but returns a 403 error for handlers to propagate.
+pub fn authorize(claims: &JwtClaims, action: Action) -> Result<(), AppError> {
+    if is_allowed(claims, &action) {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden(
+            "You are not allowed to perform this action".to_string(),
+        ))
+    }
+}
+
+/// What a WebSocket client wants to do with a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicAccess {
+    Subscribe,
+    Publish,
+}
+
+/// Per-topic authorization hook. Receives the caller, the topic segments
+/// matched by `*` in the rule's pattern, and the access requested. Messages
+/// published by the server itself are not checked.
+pub type TopicHook = fn(&JwtClaims, &[&str], TopicAccess) -> bool;
+
+/// Any signed-in user may subscribe and publish, as in `room:*`.
+pub fn open_topic(_claims: &JwtClaims, _wildcards: &[&str], _access: TopicAccess) -> bool {
+    true
+}
+
+/// Any signed-in user may subscribe; only the server publishes, as for the
+/// post event topics.
+pub fn read_only_topic(_claims: &JwtClaims, _wildcards: &[&str], access: TopicAccess) -> bool {
+    access == TopicAccess::Subscribe
+}
+
+/// `user:{id}`: only that user, or an admin, may subscribe; only the server
+/// publishes.
+pub fn own_user_topic(claims: &JwtClaims, wildcards: &[&str], access: TopicAccess) -> bool {
+    access == TopicAccess::Subscribe
+        && (wildcards.first() == Some(&claims.user_id.to_string().as_str())
+            || has_role(claims.role, Role::Admin))
+}
@@ -0,0 +1,21 @@
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Header used to accept and echo request ids.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Runs `f` with `id` as the current request id.
+pub async fn scope<F: std::future::Future>(id: String, f: F) -> F::Output {
+    REQUEST_ID.scope(id, f).await
+}
+
+/// Runs `f` synchronously with `id` as the current request id.
+pub fn sync_scope<R>(id: String, f: impl FnOnce() -> R) -> R {
+    REQUEST_ID.sync_scope(id, f)
+}
+
+/// The id of the request being handled, if called from within a request scope.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
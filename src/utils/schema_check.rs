@@ -0,0 +1,106 @@
+use sea_orm::{
+    ColumnTrait, ColumnType, DatabaseBackend, DatabaseConnection, DbErr, EntityTrait,
+    FromQueryResult, Iden, Iterable, Statement,
+};
+
+#[derive(FromQueryResult)]
+struct DbColumn {
+    column_name: String,
+    data_type: String,
+    is_nullable: String,
+}
+
+/// Compares every entity column with `information_schema` and returns a line
+/// per mismatch. Catches migrations and entities drifting apart, such as a
+/// `NOT NULL` column modelled as `Option<T>`.
+pub async fn check(db: &DatabaseConnection) -> Result<Vec<String>, DbErr> {
+    let mut problems = Vec::new();
+    problems.extend(check_entity(db, entity::user::Entity).await?);
+    problems.extend(check_entity(db, entity::post::Entity).await?);
+    problems.extend(check_entity(db, entity::refresh_token::Entity).await?);
+    problems.extend(check_entity(db, entity::ws_ticket::Entity).await?);
+    problems.extend(check_entity(db, entity::conversation::Entity).await?);
+    problems.extend(check_entity(db, entity::conversation_member::Entity).await?);
+    problems.extend(check_entity(db, entity::message::Entity).await?);
+    problems.extend(check_entity(db, entity::file::Entity).await?);
+    Ok(problems)
+}
+
+async fn check_entity<E: EntityTrait>(
+    db: &DatabaseConnection,
+    entity: E,
+) -> Result<Vec<String>, DbErr> {
+    let table = entity.table_name();
+    let columns = DbColumn::find_by_statement(Statement::from_sql_and_values(
+        DatabaseBackend::Postgres,
+        "SELECT column_name, data_type, is_nullable FROM information_schema.columns \
+         WHERE table_schema = current_schema() AND table_name = $1",
+        [table.into()],
+    ))
+    .all(db)
+    .await?;
+
+    let mut problems = Vec::new();
+    for column in E::Column::iter() {
+        let name = column.to_string();
+        let def = column.def();
+        let Some(actual) = columns.iter().find(|c| c.column_name == name) else {
+            problems.push(format!("{table}.{name} is missing from the database"));
+            continue;
+        };
+
+        let nullable = actual.is_nullable == "YES";
+        if nullable != def.is_null() {
+            problems.push(format!(
+                "{table}.{name} is {} in the database but {} in the entity",
+                if nullable { "nullable" } else { "NOT NULL" },
+                if def.is_null() {
+                    "optional"
+                } else {
+                    "required"
+                },
+            ));
+        }
+
+        if let Some(expected) = expected_data_type(def.get_column_type())
+            && actual.data_type != expected
+        {
+            problems.push(format!(
+                "{table}.{name} is {} in the database but {expected} in the entity",
+                actual.data_type
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// Postgres `data_type` for the column types whose drift matters to us.
+fn expected_data_type(column_type: &ColumnType) -> Option<&'static str> {
+    match column_type {
+        ColumnType::TimestampWithTimeZone => Some("timestamp with time zone"),
+        ColumnType::DateTime | ColumnType::Timestamp => Some("timestamp without time zone"),
+        ColumnType::Integer => Some("integer"),
+        ColumnType::BigInteger => Some("bigint"),
+        ColumnType::Text => Some("text"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::Database;
+
+    /// Runs against the database in `DATABASE_URL`, as CI provides.
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn migrated_schema_matches_entities() {
+        dotenv::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let db = Database::connect(url).await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let problems = super::check(&db).await.unwrap();
+        assert!(problems.is_empty(), "schema drift: {problems:#?}");
+    }
+}
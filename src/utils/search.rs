@@ -0,0 +1,174 @@
+use sea_orm::{
+    ConnectionTrait, DatabaseBackend, DbErr, FromQueryResult, Statement, Value,
+    sea_query::{Expr, SimpleExpr},
+};
+
+/// Text search configuration used for both the stored `tsvector` and queries.
+pub const SEARCH_CONFIG: &str = "english";
+
+#[derive(Debug)]
+enum TermKind {
+    Word,
+    Phrase,
+    Prefix,
+}
+
+#[derive(Debug)]
+struct Term {
+    kind: TermKind,
+    text: String,
+    negated: bool,
+}
+
+/// A user search string compiled into a Postgres `tsquery` expression.
+///
+/// Supported syntax, with terms ANDed together:
+/// - `rust actix`: both words, stemmed
+/// - `"web framework"`: the words next to each other, in order
+/// - `act*`: any word starting with `act`
+/// - `-draft` or `-"first look"`: exclude matches
+///
+/// Every term is bound as a parameter, so no input can produce a `tsquery`
+/// syntax error.
+#[derive(Debug, Clone)]
+pub struct TsQuery {
+    sql: String,
+    values: Vec<Value>,
+}
+
+impl TsQuery {
+    /// Returns `None` when the input holds no searchable term.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let terms = tokenize(raw);
+        if terms.is_empty() {
+            return None;
+        }
+
+        let mut parts = Vec::with_capacity(terms.len());
+        let mut values = Vec::with_capacity(terms.len());
+        for (i, term) in terms.into_iter().enumerate() {
+            let n = i + 1;
+            let (function, value) = match term.kind {
+                TermKind::Word => ("plainto_tsquery", term.text),
+                TermKind::Phrase => ("phraseto_tsquery", term.text),
+                TermKind::Prefix => ("to_tsquery", format!("{}:*", term.text)),
+            };
+            let query = format!("{function}('{SEARCH_CONFIG}', ${n})");
+            parts.push(if term.negated {
+                format!("!!{query}")
+            } else {
+                query
+            });
+            values.push(value.into());
+        }
+
+        Some(Self {
+            sql: format!("({})", parts.join(" && ")),
+            values,
+        })
+    }
+
+    /// `true` when the query keeps at least one lexeme. Queries made only of
+    /// stop words ("the", "and") compile to an empty `tsquery` that matches
+    /// nothing, so callers should fall back to a substring search.
+    pub async fn has_lexemes<C: ConnectionTrait>(&self, db: &C) -> Result<bool, DbErr> {
+        #[derive(FromQueryResult)]
+        struct Nodes {
+            nodes: i32,
+        }
+
+        let nodes = Nodes::find_by_statement(Statement::from_sql_and_values(
+            DatabaseBackend::Postgres,
+            format!("SELECT numnode({}) AS nodes", self.sql),
+            self.values.clone(),
+        ))
+        .one(db)
+        .await?;
+        Ok(nodes.is_some_and(|row| row.nodes > 0))
+    }
+
+    /// `<vector> @@ <query>`
+    pub fn matches(&self, vector: &str) -> SimpleExpr {
+        self.expr(format!("{vector} @@ {}", self.sql))
+    }
+
+    /// `ts_rank(<vector>, <query>)`
+    pub fn rank(&self, vector: &str) -> SimpleExpr {
+        self.expr(format!("ts_rank({vector}, {})", self.sql))
+    }
+
+    /// `ts_headline` over `document`, wrapping matches in `<mark>` tags.
+    /// `options` are appended to the default selection markers.
+    pub fn headline(&self, document: &str, options: &str) -> SimpleExpr {
+        self.expr(format!(
+            "ts_headline('{SEARCH_CONFIG}', {document}, {}, 'StartSel=<mark>, StopSel=</mark>{options}')",
+            self.sql
+        ))
+    }
+
+    fn expr(&self, sql: String) -> SimpleExpr {
+        Expr::cust_with_values(sql, self.values.clone())
+    }
+}
+
+/// Splits a search string into words, quoted phrases and prefixes. Characters
+/// that have meaning in `tsquery` syntax are dropped from prefix terms.
+fn tokenize(raw: &str) -> Vec<Term> {
+    let mut terms = Vec::new();
+    let mut chars = raw.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let negated = c == '-';
+        if negated {
+            chars.next();
+        }
+
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            if !phrase.trim().is_empty() {
+                terms.push(Term {
+                    kind: TermKind::Phrase,
+                    text: phrase.trim().to_string(),
+                    negated,
+                });
+            }
+            continue;
+        }
+
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '"' {
+                break;
+            }
+            word.push(c);
+            chars.next();
+        }
+
+        let term = match word.strip_suffix('*') {
+            Some(prefix) => {
+                let prefix: String = prefix.chars().filter(|c| c.is_alphanumeric()).collect();
+                Term {
+                    kind: TermKind::Prefix,
+                    text: prefix,
+                    negated,
+                }
+            }
+            None => Term {
+                kind: TermKind::Word,
+                text: word,
+                negated,
+            },
+        };
+        if !term.text.is_empty() {
+            terms.push(term);
+        }
+    }
+
+    terms
+}
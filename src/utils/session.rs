@@ -0,0 +1,342 @@
+use chrono::{Duration, Utc};
+use entity::{refresh_token, user};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, ConnectionTrait,
+    DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter, TransactionTrait,
+    sea_query::Expr,
+};
+use uuid::Uuid;
+
+use crate::utils::{
+    app_error::{AppError, ErrorCode},
+    config::AuthConfig,
+    jwt::generate_jwt,
+};
+
+/// Access and refresh token pair handed out on login and refresh.
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    /// The refresh token is unknown, expired or revoked.
+    InvalidToken,
+    /// An already rotated refresh token was presented again; its family is now revoked.
+    TokenReuse,
+    Database(DbErr),
+    Jwt(jsonwebtoken::errors::Error),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::InvalidToken => write!(f, "Invalid refresh token"),
+            SessionError::TokenReuse => write!(f, "Refresh token reuse detected"),
+            SessionError::Database(e) => write!(f, "{e}"),
+            SessionError::Jwt(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<SessionError> for AppError {
+    fn from(e: SessionError) -> Self {
+        match e {
+            SessionError::InvalidToken => AppError::Unauthorized(
+                ErrorCode::AuthRefreshTokenInvalid,
+                "Invalid refresh token".to_string(),
+            ),
+            SessionError::TokenReuse => AppError::Unauthorized(
+                ErrorCode::AuthRefreshTokenReused,
+                "Refresh token reuse detected, session revoked".to_string(),
+            ),
+            SessionError::Database(e) => AppError::Database(e),
+            SessionError::Jwt(e) => AppError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for SessionError {
+    fn from(e: DbErr) -> Self {
+        SessionError::Database(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for SessionError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        SessionError::Jwt(e)
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    // Refresh tokens are long random values, so a fast unsalted digest is enough
+    // to keep them out of the database in plain text.
+    sha256::digest(token)
+}
+
+fn new_refresh_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+async fn insert_refresh_token(
+    db: &impl ConnectionTrait,
+    auth: &AuthConfig,
+    user_id: i32,
+    family_id: &str,
+) -> Result<String, DbErr> {
+    let token = new_refresh_token();
+    let now = Utc::now().fixed_offset();
+
+    refresh_token::ActiveModel {
+        user_id: Set(user_id),
+        token_hash: Set(hash_token(&token)),
+        family_id: Set(family_id.to_string()),
+        expires_at: Set(now + Duration::days(i64::from(auth.refresh_token_ttl_days))),
+        revoked_at: Set(None),
+        created_at: Set(now),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(token)
+}
+
+/// Starts a new session (refresh token family) for a user.
+pub async fn start_session(
+    db: &DatabaseConnection,
+    auth: &AuthConfig,
+    user: &user::Model,
+) -> Result<TokenPair, SessionError> {
+    let family_id = Uuid::new_v4().to_string();
+    let refresh_token = insert_refresh_token(db, auth, user.id, &family_id).await?;
+    let access_token = generate_jwt(auth, user.id, user.email.clone(), user.role, family_id)?;
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+    })
+}
+
+/// Exchanges a refresh token for a new pair, rotating the refresh token.
+///
+/// Presenting a token that was already rotated revokes the whole family, since
+/// it means the token has leaked to someone else. The rotation runs in one
+/// transaction, so a failure part way leaves the presented token live rather
+/// than the family without one.
+pub async fn rotate_session(
+    db: &DatabaseConnection,
+    auth: &AuthConfig,
+    presented: &str,
+) -> Result<TokenPair, SessionError> {
+    let txn = db.begin().await?;
+    let current = refresh_token::Entity::find()
+        .filter(refresh_token::Column::TokenHash.eq(hash_token(presented)))
+        .one(&txn)
+        .await?
+        .ok_or(SessionError::InvalidToken)?;
+
+    if current.revoked_at.is_some() {
+        revoke_family(&txn, &current.family_id).await?;
+        txn.commit().await?;
+        return Err(SessionError::TokenReuse);
+    }
+
+    let now = Utc::now().fixed_offset();
+    if current.expires_at <= now {
+        return Err(SessionError::InvalidToken);
+    }
+
+    // Only one concurrent refresh may win the rotation; the loser is treated as reuse.
+    let rotated = refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::RevokedAt, Expr::value(now))
+        .filter(refresh_token::Column::Id.eq(current.id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(&txn)
+        .await?;
+
+    if rotated.rows_affected != 1 {
+        revoke_family(&txn, &current.family_id).await?;
+        txn.commit().await?;
+        return Err(SessionError::TokenReuse);
+    }
+
+    let user = user::Entity::find_by_id(current.user_id)
+        .one(&txn)
+        .await?
+        .ok_or(SessionError::InvalidToken)?;
+
+    let refresh_token =
+        insert_refresh_token(&txn, auth, current.user_id, &current.family_id).await?;
+    let access_token = generate_jwt(auth, user.id, user.email, user.role, current.family_id)?;
+    txn.commit().await?;
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+    })
+}
+
+/// Revokes the family the given refresh token belongs to. Unknown tokens are ignored.
+pub async fn end_session(db: &DatabaseConnection, presented: &str) -> Result<(), DbErr> {
+    let current = refresh_token::Entity::find()
+        .filter(refresh_token::Column::TokenHash.eq(hash_token(presented)))
+        .one(db)
+        .await?;
+
+    if let Some(current) = current {
+        revoke_family(db, &current.family_id).await?;
+    }
+    Ok(())
+}
+
+pub async fn revoke_family(db: &impl ConnectionTrait, family_id: &str) -> Result<(), DbErr> {
+    refresh_token::Entity::update_many()
+        .col_expr(
+            refresh_token::Column::RevokedAt,
+            Expr::value(Utc::now().fixed_offset()),
+        )
+        .filter(refresh_token::Column::FamilyId.eq(family_id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// A session is active while its family still has an unrevoked, unexpired token.
+pub async fn is_session_active(db: &DatabaseConnection, family_id: &str) -> Result<bool, DbErr> {
+    let live = refresh_token::Entity::find()
+        .filter(
+            Condition::all()
+                .add(refresh_token::Column::FamilyId.eq(family_id))
+                .add(refresh_token::Column::RevokedAt.is_null())
+                .add(refresh_token::Column::ExpiresAt.gt(Utc::now().fixed_offset())),
+        )
+        .count(db)
+        .await?;
+    Ok(live > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration as StdDuration;
+
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{Database, QuerySelect};
+
+    use super::*;
+    use crate::utils::config::{Config, FileConfig};
+
+    async fn connect() -> (DatabaseConnection, AuthConfig) {
+        dotenv::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let db = Database::connect(&url).await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let config = Config::from_sources(
+            |key| match key {
+                "DATABASE_URL" => Some(url.clone()),
+                "STORAGE_BACKEND" => Some("memory".to_string()),
+                _ => None,
+            },
+            FileConfig::default(),
+        )
+        .unwrap();
+        (db, config.auth)
+    }
+
+    async fn new_user(db: &DatabaseConnection) -> user::Model {
+        user::ActiveModel {
+            name: Set("Session".to_string()),
+            email: Set(format!("session-{}@example.com", Uuid::new_v4())),
+            password: Set(String::new()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap()
+    }
+
+    async fn family_of(db: &DatabaseConnection, token: &str) -> String {
+        refresh_token::Entity::find()
+            .filter(refresh_token::Column::TokenHash.eq(hash_token(token)))
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap()
+            .family_id
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn rotation_replaces_the_token_and_reuse_revokes_the_family() {
+        let (db, auth) = connect().await;
+        let user = new_user(&db).await;
+        let first = start_session(&db, &auth, &user).await.unwrap();
+        let family = family_of(&db, &first.refresh_token).await;
+
+        let second = rotate_session(&db, &auth, &first.refresh_token)
+            .await
+            .unwrap();
+        assert_ne!(second.refresh_token, first.refresh_token);
+        assert_eq!(family_of(&db, &second.refresh_token).await, family);
+        assert!(is_session_active(&db, &family).await.unwrap());
+
+        // The first token was rotated, so presenting it again means it leaked.
+        assert!(matches!(
+            rotate_session(&db, &auth, &first.refresh_token).await,
+            Err(SessionError::TokenReuse)
+        ));
+        assert!(!is_session_active(&db, &family).await.unwrap());
+        assert!(matches!(
+            rotate_session(&db, &auth, &second.refresh_token).await,
+            Err(SessionError::TokenReuse)
+        ));
+        assert!(matches!(
+            rotate_session(&db, &auth, "unknown").await,
+            Err(SessionError::InvalidToken)
+        ));
+
+        user::Entity::delete_by_id(user.id).exec(&db).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn concurrent_refresh_loser_revokes_the_family() {
+        let (db, auth) = connect().await;
+        let user = new_user(&db).await;
+        let session = start_session(&db, &auth, &user).await.unwrap();
+        let family = family_of(&db, &session.refresh_token).await;
+
+        // Hold the row so both refreshes read it as live and then queue on
+        // their update; whichever goes second finds it already rotated.
+        let lock = db.begin().await.unwrap();
+        refresh_token::Entity::find()
+            .filter(refresh_token::Column::FamilyId.eq(family.as_str()))
+            .lock_exclusive()
+            .all(&lock)
+            .await
+            .unwrap();
+        let release = async {
+            tokio::time::sleep(StdDuration::from_millis(300)).await;
+            lock.commit().await.unwrap();
+        };
+        let (a, b, ()) = tokio::join!(
+            rotate_session(&db, &auth, &session.refresh_token),
+            rotate_session(&db, &auth, &session.refresh_token),
+            release,
+        );
+
+        let (winner, loser) = if a.is_ok() { (a, b) } else { (b, a) };
+        let winner = winner.unwrap();
+        assert!(matches!(loser, Err(SessionError::TokenReuse)));
+        // The winner's fresh token went down with the rest of the family.
+        assert!(!is_session_active(&db, &family).await.unwrap());
+        assert!(matches!(
+            rotate_session(&db, &auth, &winner.refresh_token).await,
+            Err(SessionError::TokenReuse)
+        ));
+
+        user::Entity::delete_by_id(user.id).exec(&db).await.unwrap();
+    }
+}
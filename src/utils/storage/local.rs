@@ -0,0 +1,485 @@
+use std::{io, path::PathBuf, time::Duration};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::utils::{
+    app_error::AppError,
+    storage::{
+        Object, ObjectMeta, ObjectStore, ObjectWriter, PresignedRequest, UrlSigner, check_key,
+        object_exists,
+    },
+};
+
+/// Partly written objects, renamed into place once complete.
+const TEMP_DIR: &str = ".tmp";
+/// What the filesystem does not record about each object, mirroring the
+/// object tree. Keys cannot start with a dot, so neither directory clashes
+/// with one.
+const META_DIR: &str = ".meta";
+
+#[derive(Serialize, Deserialize)]
+struct Sidecar {
+    content_type: String,
+}
+
+fn storage_error(e: io::Error) -> AppError {
+    AppError::Storage(e.to_string())
+}
+
+/// Keeps objects as files under a directory on this instance's disk.
+/// Instances only share objects if they share the directory.
+pub struct LocalStorage {
+    root: PathBuf,
+    signer: UrlSigner,
+}
+
+impl LocalStorage {
+    /// Creates `root` if needed.
+    pub async fn open(root: impl Into<PathBuf>, signer: UrlSigner) -> Result<Self, AppError> {
+        let root = root.into();
+        fs::create_dir_all(root.join(TEMP_DIR))
+            .await
+            .map_err(|e| AppError::Storage(format!("cannot create {}: {e}", root.display())))?;
+        Ok(Self { root, signer })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    fn sidecar_path(&self, key: &str) -> PathBuf {
+        self.root.join(META_DIR).join(key)
+    }
+
+    async fn open_writer(
+        &self,
+        key: &str,
+        content_type: &str,
+        replace: bool,
+    ) -> Result<Box<dyn ObjectWriter>, AppError> {
+        check_key(key)?;
+        let temp = self.root.join(TEMP_DIR).join(Uuid::new_v4().to_string());
+        let file = fs::File::create(&temp).await.map_err(storage_error)?;
+        Ok(Box::new(LocalWriter {
+            file,
+            temp: Some(temp),
+            path: self.path(key),
+            sidecar_path: self.sidecar_path(key),
+            content_type: content_type.to_string(),
+            replace,
+        }))
+    }
+
+    /// `None` when there is no object under `key`.
+    async fn meta(&self, key: &str) -> Result<Option<ObjectMeta>, AppError> {
+        check_key(key)?;
+        let metadata = match fs::metadata(self.path(key)).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(storage_error(e)),
+        };
+        // Objects whose sidecar went missing are still served, untyped.
+        let content_type = fs::read(self.sidecar_path(key))
+            .await
+            .ok()
+            .and_then(|raw| serde_json::from_slice::<Sidecar>(&raw).ok())
+            .map(|sidecar| sidecar.content_type);
+        Ok(Some(ObjectMeta {
+            key: key.to_string(),
+            size: metadata.len(),
+            content_type,
+            last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+        }))
+    }
+
+    /// The keys directly under `dir`, flagged `true` for directories, in the
+    /// order their contents sort: a directory sorts as its key followed by
+    /// `/`. Temporary and sidecar files are left out.
+    async fn entries(&self, dir: &str) -> Result<Vec<(String, bool)>, AppError> {
+        let mut read_dir = match fs::read_dir(self.root.join(dir)).await {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(storage_error(e)),
+        };
+        let mut entries = Vec::new();
+        while let Some(entry) = read_dir.next_entry().await.map_err(storage_error)? {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let key = if dir.is_empty() {
+                name
+            } else {
+                format!("{dir}/{name}")
+            };
+            let file_type = entry.file_type().await.map_err(storage_error)?;
+            if file_type.is_dir() {
+                entries.push((key, true));
+            } else if file_type.is_file() {
+                entries.push((key, false));
+            }
+        }
+        entries.sort_by_cached_key(|(key, is_dir)| {
+            if *is_dir {
+                format!("{key}/")
+            } else {
+                key.clone()
+            }
+        });
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for LocalStorage {
+    async fn writer(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Box<dyn ObjectWriter>, AppError> {
+        self.open_writer(key, content_type, true).await
+    }
+
+    async fn create_writer(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Box<dyn ObjectWriter>, AppError> {
+        self.open_writer(key, content_type, false).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Object>, AppError> {
+        let Some(meta) = self.meta(key).await? else {
+            return Ok(None);
+        };
+        let file = match fs::File::open(self.path(key)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(storage_error(e)),
+        };
+        Ok(Some(Object {
+            meta,
+            body: ReaderStream::new(file).boxed(),
+        }))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>, AppError> {
+        self.meta(key).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        check_key(key)?;
+        for path in [self.path(key), self.sidecar_path(key)] {
+            match fs::remove_file(path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(storage_error(e)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, limit: usize) -> Result<Vec<ObjectMeta>, AppError> {
+        // Start from the deepest directory the prefix names.
+        let start = match prefix.rfind('/') {
+            Some(end) => &prefix[..end],
+            None => "",
+        };
+        if !start.is_empty() {
+            check_key(start)?;
+        }
+        let mut objects = Vec::new();
+        if limit == 0 {
+            return Ok(objects);
+        }
+        // Depth first with each directory in key order, so keys come out
+        // sorted and the walk can stop as soon as it has `limit` of them.
+        let mut pending = vec![self.entries(start).await?.into_iter()];
+        while let Some(entries) = pending.last_mut() {
+            let Some((key, is_dir)) = entries.next() else {
+                pending.pop();
+                continue;
+            };
+            if is_dir {
+                if format!("{key}/").starts_with(prefix) {
+                    pending.push(self.entries(&key).await?.into_iter());
+                }
+            } else if key.starts_with(prefix)
+                && let Some(meta) = self.meta(&key).await?
+            {
+                objects.push(meta);
+                if objects.len() == limit {
+                    break;
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    fn url(&self, key: &str) -> String {
+        self.signer.url(key)
+    }
+
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, AppError> {
+        check_key(key)?;
+        Ok(self.signer.presign_get(key, expires_in))
+    }
+
+    async fn presign_put(
+        &self,
+        key: &str,
+        content_type: &str,
+        size: u64,
+        expires_in: Duration,
+    ) -> Result<PresignedRequest, AppError> {
+        check_key(key)?;
+        Ok(self.signer.presign_put(key, content_type, size, expires_in))
+    }
+}
+
+/// Writes to a temporary file that `finish` renames over the key, so readers
+/// never see a partial object. When it may not replace an object it links
+/// the file into place instead, which fails if the key is taken.
+struct LocalWriter {
+    file: fs::File,
+    /// Cleared once renamed into place; a linked file's is removed on drop.
+    temp: Option<PathBuf>,
+    path: PathBuf,
+    sidecar_path: PathBuf,
+    content_type: String,
+    /// Whether an object already under the key may be replaced.
+    replace: bool,
+}
+
+#[async_trait]
+impl ObjectWriter for LocalWriter {
+    async fn write(&mut self, chunk: Bytes) -> Result<(), AppError> {
+        self.file.write_all(&chunk).await.map_err(storage_error)
+    }
+
+    async fn finish(mut self: Box<Self>) -> Result<(), AppError> {
+        self.file.flush().await.map_err(storage_error)?;
+        self.file.sync_data().await.map_err(storage_error)?;
+
+        for path in [&self.path, &self.sidecar_path] {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await.map_err(storage_error)?;
+            }
+        }
+        let sidecar = serde_json::to_vec(&Sidecar {
+            content_type: self.content_type.clone(),
+        })
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+        let Some(temp) = self.temp.clone() else {
+            return Ok(());
+        };
+
+        if self.replace {
+            fs::write(&self.sidecar_path, sidecar)
+                .await
+                .map_err(storage_error)?;
+            fs::rename(&temp, &self.path).await.map_err(storage_error)?;
+            self.temp = None;
+            return Ok(());
+        }
+
+        // The sidecar can only be written once the key is ours, so the object
+        // is briefly untyped.
+        match fs::hard_link(&temp, &self.path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(object_exists()),
+            Err(e) => return Err(storage_error(e)),
+        }
+        fs::write(&self.sidecar_path, sidecar)
+            .await
+            .map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+impl Drop for LocalWriter {
+    fn drop(&mut self) {
+        if let Some(temp) = self.temp.take() {
+            std::fs::remove_file(temp).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::app_error::ErrorCode;
+
+    /// A store in a fresh directory, removed again when dropped.
+    struct TempStore {
+        store: LocalStorage,
+    }
+
+    impl TempStore {
+        async fn new() -> Self {
+            let root = std::env::temp_dir().join(format!("local-storage-{}", Uuid::new_v4()));
+            let signer = UrlSigner {
+                secret: "local-test-secret".to_string(),
+                base_url: "http://localhost:8080".to_string(),
+            };
+            Self {
+                store: LocalStorage::open(root, signer).await.unwrap(),
+            }
+        }
+
+        async fn put(&self, key: &str, data: &'static [u8]) {
+            let mut writer = self.store.writer(key, "text/plain").await.unwrap();
+            writer.write(Bytes::from_static(data)).await.unwrap();
+            writer.finish().await.unwrap();
+        }
+
+        async fn read(&self, key: &str) -> Option<Vec<u8>> {
+            let object = self.store.get(key).await.unwrap()?;
+            let mut body = Vec::new();
+            let mut chunks = object.body;
+            while let Some(chunk) = chunks.next().await {
+                body.extend_from_slice(&chunk.unwrap());
+            }
+            Some(body)
+        }
+
+        async fn keys(&self, prefix: &str, limit: usize) -> Vec<String> {
+            let objects = self.store.list(prefix, limit).await.unwrap();
+            objects.into_iter().map(|meta| meta.key).collect()
+        }
+
+        fn temp_files(&self) -> usize {
+            std::fs::read_dir(self.store.root.join(TEMP_DIR))
+                .unwrap()
+                .count()
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.store.root).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn objects_round_trip() {
+        let temp = TempStore::new().await;
+        temp.put("uploads/1/a.txt", b"hello").await;
+
+        assert_eq!(temp.read("uploads/1/a.txt").await.unwrap(), b"hello");
+        let meta = temp.store.head("uploads/1/a.txt").await.unwrap().unwrap();
+        assert_eq!(meta.size, 5);
+        assert_eq!(meta.content_type.as_deref(), Some("text/plain"));
+        assert!(meta.last_modified.is_some());
+
+        temp.put("uploads/1/a.txt", b"replaced").await;
+        assert_eq!(temp.read("uploads/1/a.txt").await.unwrap(), b"replaced");
+
+        temp.store.delete("uploads/1/a.txt").await.unwrap();
+        assert!(temp.read("uploads/1/a.txt").await.is_none());
+        assert!(temp.store.head("uploads/1/a.txt").await.unwrap().is_none());
+        // Deleting again is not an error.
+        temp.store.delete("uploads/1/a.txt").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unfinished_writes_stay_invisible() {
+        let temp = TempStore::new().await;
+        let mut writer = temp
+            .store
+            .writer("uploads/1/a.txt", "text/plain")
+            .await
+            .unwrap();
+        writer.write(Bytes::from_static(b"partial")).await.unwrap();
+        assert!(temp.read("uploads/1/a.txt").await.is_none());
+        assert!(temp.keys("", 10).await.is_empty());
+        assert_eq!(temp.temp_files(), 1);
+
+        drop(writer);
+        assert!(temp.read("uploads/1/a.txt").await.is_none());
+        assert_eq!(temp.temp_files(), 0);
+
+        temp.put("uploads/1/a.txt", b"whole").await;
+        assert_eq!(temp.temp_files(), 0);
+    }
+
+    #[tokio::test]
+    async fn create_writer_keeps_existing_objects() {
+        let temp = TempStore::new().await;
+        let mut first = temp
+            .store
+            .create_writer("uploads/1/a.txt", "text/plain")
+            .await
+            .unwrap();
+        let mut second = temp
+            .store
+            .create_writer("uploads/1/a.txt", "text/html")
+            .await
+            .unwrap();
+        first.write(Bytes::from_static(b"first")).await.unwrap();
+        second.write(Bytes::from_static(b"second")).await.unwrap();
+        first.finish().await.unwrap();
+
+        let error = second.finish().await.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ResourceConflict);
+        assert_eq!(temp.read("uploads/1/a.txt").await.unwrap(), b"first");
+        let meta = temp.store.head("uploads/1/a.txt").await.unwrap().unwrap();
+        assert_eq!(meta.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(temp.temp_files(), 0);
+    }
+
+    #[tokio::test]
+    async fn objects_without_a_sidecar_are_untyped() {
+        let temp = TempStore::new().await;
+        temp.put("uploads/1/a.txt", b"hello").await;
+        std::fs::remove_file(temp.store.sidecar_path("uploads/1/a.txt")).unwrap();
+
+        let meta = temp.store.head("uploads/1/a.txt").await.unwrap().unwrap();
+        assert_eq!(meta.content_type, None);
+        assert_eq!(temp.read("uploads/1/a.txt").await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn keys_outside_the_root_are_rejected() {
+        let temp = TempStore::new().await;
+        for key in [
+            "../escape",
+            "uploads/../../escape",
+            "/etc/passwd",
+            ".meta/x",
+        ] {
+            assert!(temp.store.writer(key, "text/plain").await.is_err(), "{key}");
+            assert!(temp.store.get(key).await.is_err(), "{key}");
+            assert!(temp.store.delete(key).await.is_err(), "{key}");
+        }
+        assert!(temp.store.list("../", 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn listing_is_in_key_order_and_stops_at_the_limit() {
+        let temp = TempStore::new().await;
+        for key in ["a/b", "a-c", "a/a/z", "b", "ab", "a0"] {
+            temp.put(key, b"x").await;
+        }
+
+        assert_eq!(
+            temp.keys("", 10).await,
+            ["a-c", "a/a/z", "a/b", "a0", "ab", "b"]
+        );
+        assert_eq!(temp.keys("", 2).await, ["a-c", "a/a/z"]);
+        assert_eq!(temp.keys("", 0).await, Vec::<String>::new());
+        assert_eq!(temp.keys("a/", 10).await, ["a/a/z", "a/b"]);
+        assert_eq!(temp.keys("a/a", 10).await, ["a/a/z"]);
+        assert_eq!(temp.keys("a", 3).await, ["a-c", "a/a/z", "a/b"]);
+        assert!(temp.keys("missing/", 10).await.is_empty());
+    }
+}
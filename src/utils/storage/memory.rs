@@ -0,0 +1,179 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, stream};
+
+use crate::utils::{
+    app_error::AppError,
+    storage::{
+        Object, ObjectMeta, ObjectStore, ObjectWriter, PresignedRequest, UrlSigner, check_key,
+        object_exists,
+    },
+};
+
+struct Stored {
+    data: Bytes,
+    content_type: String,
+    last_modified: DateTime<Utc>,
+}
+
+impl Stored {
+    fn meta(&self, key: &str) -> ObjectMeta {
+        ObjectMeta {
+            key: key.to_string(),
+            size: self.data.len() as u64,
+            content_type: Some(self.content_type.clone()),
+            last_modified: Some(self.last_modified),
+        }
+    }
+}
+
+type Objects = Arc<Mutex<BTreeMap<String, Stored>>>;
+
+/// Keeps objects in process memory, for development and tests. Everything is
+/// lost on restart and each instance has its own objects.
+pub struct MemoryStorage {
+    objects: Objects,
+    signer: UrlSigner,
+}
+
+impl MemoryStorage {
+    pub fn new(signer: UrlSigner) -> Self {
+        Self {
+            objects: Objects::default(),
+            signer,
+        }
+    }
+
+    fn open_writer(
+        &self,
+        key: &str,
+        content_type: &str,
+        replace: bool,
+    ) -> Result<Box<dyn ObjectWriter>, AppError> {
+        check_key(key)?;
+        Ok(Box::new(MemoryWriter {
+            objects: self.objects.clone(),
+            key: key.to_string(),
+            content_type: content_type.to_string(),
+            replace,
+            buffer: BytesMut::new(),
+        }))
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryStorage {
+    async fn writer(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Box<dyn ObjectWriter>, AppError> {
+        self.open_writer(key, content_type, true)
+    }
+
+    async fn create_writer(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Box<dyn ObjectWriter>, AppError> {
+        self.open_writer(key, content_type, false)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Object>, AppError> {
+        let objects = self.objects.lock().expect("object store poisoned");
+        Ok(objects.get(key).map(|stored| Object {
+            meta: stored.meta(key),
+            body: stream::once(std::future::ready(Ok(stored.data.clone()))).boxed(),
+        }))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>, AppError> {
+        let objects = self.objects.lock().expect("object store poisoned");
+        Ok(objects.get(key).map(|stored| stored.meta(key)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        self.objects
+            .lock()
+            .expect("object store poisoned")
+            .remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, limit: usize) -> Result<Vec<ObjectMeta>, AppError> {
+        let objects = self.objects.lock().expect("object store poisoned");
+        Ok(objects
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .take(limit)
+            .map(|(key, stored)| stored.meta(key))
+            .collect())
+    }
+
+    fn url(&self, key: &str) -> String {
+        self.signer.url(key)
+    }
+
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, AppError> {
+        check_key(key)?;
+        Ok(self.signer.presign_get(key, expires_in))
+    }
+
+    async fn presign_put(
+        &self,
+        key: &str,
+        content_type: &str,
+        size: u64,
+        expires_in: Duration,
+    ) -> Result<PresignedRequest, AppError> {
+        check_key(key)?;
+        Ok(self.signer.presign_put(key, content_type, size, expires_in))
+    }
+}
+
+struct MemoryWriter {
+    objects: Objects,
+    key: String,
+    content_type: String,
+    /// Whether an object already under `key` may be replaced.
+    replace: bool,
+    buffer: BytesMut,
+}
+
+#[async_trait]
+impl ObjectWriter for MemoryWriter {
+    async fn write(&mut self, chunk: Bytes) -> Result<(), AppError> {
+        self.buffer.extend_from_slice(&chunk);
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> Result<(), AppError> {
+        let MemoryWriter {
+            objects,
+            key,
+            content_type,
+            replace,
+            buffer,
+        } = *self;
+        let mut objects = objects.lock().expect("object store poisoned");
+        if !replace && objects.contains_key(&key) {
+            return Err(object_exists());
+        }
+        objects.insert(
+            key,
+            Stored {
+                data: buffer.freeze(),
+                content_type,
+                last_modified: Utc::now(),
+            },
+        );
+        Ok(())
+    }
+}
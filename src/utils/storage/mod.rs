@@ -0,0 +1,369 @@
+use std::{collections::BTreeMap, io, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::utils::{
+    app_error::{AppError, ErrorCode},
+    config::{Config, StorageBackend},
+};
+
+pub mod local;
+pub mod memory;
+pub mod s3;
+
+pub use local::LocalStorage;
+pub use memory::MemoryStorage;
+pub use s3::S3Storage;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Keeps storage URL signatures distinct from anything else signed with the same secret.
+const URL_CONTEXT: &[u8] = b"storage-url.v1.";
+
+/// An object's contents, read as it is sent.
+pub type ByteChunks = BoxStream<'static, Result<Bytes, io::Error>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    /// Not reported by every listing.
+    pub content_type: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+pub struct Object {
+    pub meta: ObjectMeta,
+    pub body: ByteChunks,
+}
+
+/// A request the client makes itself, straight to the storage.
+#[derive(Debug, Clone, Serialize)]
+pub struct PresignedRequest {
+    pub method: String,
+    pub url: String,
+    /// Headers to send exactly as given; they are covered by the signature.
+    pub headers: BTreeMap<String, String>,
+}
+
+/// An object being written. Nothing is visible under its key until `finish`
+/// succeeds, and a writer dropped before then discards what it wrote.
+#[async_trait]
+pub trait ObjectWriter: Send {
+    async fn write(&mut self, chunk: Bytes) -> Result<(), AppError>;
+
+    async fn finish(self: Box<Self>) -> Result<(), AppError>;
+}
+
+/// Object storage for uploaded files. Keys are `/`-separated paths such as
+/// `uploads/<user id>/<uuid>.png`; writing an existing key replaces it.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    async fn writer(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Box<dyn ObjectWriter>, AppError>;
+
+    /// Like `writer`, but `finish` fails with [`object_exists`] rather than
+    /// replace an object under `key`, including one stored while this wrote.
+    async fn create_writer(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Box<dyn ObjectWriter>, AppError>;
+
+    #[expect(dead_code, reason = "no endpoint stores a whole object at once yet")]
+    async fn put(&self, key: &str, content_type: &str, data: Bytes) -> Result<(), AppError> {
+        let mut writer = self.writer(key, content_type).await?;
+        writer.write(data).await?;
+        writer.finish().await
+    }
+
+    /// `None` when there is no object under `key`.
+    async fn get(&self, key: &str) -> Result<Option<Object>, AppError>;
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>, AppError>;
+
+    /// Deleting a missing key is not an error.
+    async fn delete(&self, key: &str) -> Result<(), AppError>;
+
+    /// Up to `limit` objects whose keys start with `prefix`, in key order.
+    async fn list(&self, prefix: &str, limit: usize) -> Result<Vec<ObjectMeta>, AppError>;
+
+    /// Where the object lives. Only readable without a signature if the
+    /// backend itself is public.
+    fn url(&self, key: &str) -> String;
+
+    /// A URL that can read the object without credentials until it expires.
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, AppError>;
+
+    /// A `PUT` that stores exactly `size` bytes of `content_type` under `key`
+    /// without credentials until it expires. It only creates: once something
+    /// is stored under `key`, the request cannot replace it.
+    async fn presign_put(
+        &self,
+        key: &str,
+        content_type: &str,
+        size: u64,
+        expires_in: Duration,
+    ) -> Result<PresignedRequest, AppError>;
+}
+
+/// Builds the backend chosen by `STORAGE_BACKEND`.
+pub async fn from_config(config: &Config) -> Result<Arc<dyn ObjectStore>, AppError> {
+    let signer = UrlSigner::new(config);
+    Ok(match config.storage.backend {
+        StorageBackend::S3 => Arc::new(S3Storage::from_config(&config.s3).await),
+        StorageBackend::Local => {
+            Arc::new(LocalStorage::open(&config.storage.local_dir, signer).await?)
+        }
+        StorageBackend::Memory => Arc::new(MemoryStorage::new(signer)),
+    })
+}
+
+pub fn object_not_found() -> AppError {
+    AppError::NotFound(ErrorCode::ObjectNotFound, "Object not found".to_string())
+}
+
+pub fn object_exists() -> AppError {
+    AppError::Conflict(
+        ErrorCode::ResourceConflict,
+        "Object already exists".to_string(),
+    )
+}
+
+/// Keys the `local` and `memory` backends accept: relative paths of ASCII
+/// letters, digits, `-`, `_` and `.`, with no empty or dot-leading segments.
+/// That keeps them safe as file paths and in URLs without escaping.
+pub fn check_key(key: &str) -> Result<(), AppError> {
+    let valid = !key.is_empty()
+        && key.split('/').all(|segment| {
+            !segment.is_empty()
+                && !segment.starts_with('.')
+                && segment
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::Internal(format!("invalid storage key {key:?}")))
+    }
+}
+
+/// Signs and checks the `/storage/{key}` URLs through which this server
+/// serves objects of the `local` and `memory` backends.
+#[derive(Clone)]
+pub struct UrlSigner {
+    secret: String,
+    base_url: String,
+}
+
+impl UrlSigner {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            secret: config.auth.jwt_secret.clone(),
+            base_url: config.storage.public_url.clone(),
+        }
+    }
+
+    pub fn url(&self, key: &str) -> String {
+        format!("{}/storage/{key}", self.base_url)
+    }
+
+    pub fn presign_get(&self, key: &str, expires_in: Duration) -> String {
+        let expires = Utc::now().timestamp() + expires_in.as_secs() as i64;
+        let signature = self.sign("GET", key, expires, "");
+        format!("{}?expires={expires}&signature={signature}", self.url(key))
+    }
+
+    /// The signature also covers the `Content-Type` and `Content-Length` the
+    /// upload must be sent with.
+    pub fn presign_put(
+        &self,
+        key: &str,
+        content_type: &str,
+        size: u64,
+        expires_in: Duration,
+    ) -> PresignedRequest {
+        let expires = Utc::now().timestamp() + expires_in.as_secs() as i64;
+        let signature = self.sign("PUT", key, expires, &put_constraints(content_type, size));
+        PresignedRequest {
+            method: "PUT".to_string(),
+            url: format!("{}?expires={expires}&signature={signature}", self.url(key)),
+            headers: BTreeMap::from([
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("Content-Length".to_string(), size.to_string()),
+            ]),
+        }
+    }
+
+    /// Whether `signature` was issued for reading `key` and has not expired.
+    pub fn verify_get(&self, key: &str, expires: i64, signature: &str) -> bool {
+        self.verify("GET", key, expires, "", signature)
+    }
+
+    /// Whether `signature` was issued for writing `size` bytes of
+    /// `content_type` to `key` and has not expired.
+    pub fn verify_put(
+        &self,
+        key: &str,
+        content_type: &str,
+        size: u64,
+        expires: i64,
+        signature: &str,
+    ) -> bool {
+        self.verify(
+            "PUT",
+            key,
+            expires,
+            &put_constraints(content_type, size),
+            signature,
+        )
+    }
+
+    fn sign(&self, method: &str, key: &str, expires: i64, constraints: &str) -> String {
+        URL_SAFE_NO_PAD.encode(
+            self.mac(method, key, expires, constraints)
+                .finalize()
+                .into_bytes(),
+        )
+    }
+
+    fn verify(
+        &self,
+        method: &str,
+        key: &str,
+        expires: i64,
+        constraints: &str,
+        signature: &str,
+    ) -> bool {
+        if expires < Utc::now().timestamp() {
+            return false;
+        }
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+            return false;
+        };
+        self.mac(method, key, expires, constraints)
+            .verify_slice(&signature)
+            .is_ok()
+    }
+
+    fn mac(&self, method: &str, key: &str, expires: i64, constraints: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(URL_CONTEXT);
+        mac.update(format!("{method}\n{key}\n{expires}\n{constraints}").as_bytes());
+        mac
+    }
+}
+
+fn put_constraints(content_type: &str, size: u64) -> String {
+    format!("{content_type}\n{size}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> UrlSigner {
+        UrlSigner {
+            secret: "storage-test-secret".to_string(),
+            base_url: "http://localhost:8080".to_string(),
+        }
+    }
+
+    /// The `expires` and `signature` query parameters of a presigned URL.
+    fn query(url: &str) -> (i64, String) {
+        let query = url.split_once('?').unwrap().1;
+        let mut expires = None;
+        let mut signature = None;
+        for pair in query.split('&') {
+            match pair.split_once('=').unwrap() {
+                ("expires", value) => expires = Some(value.parse().unwrap()),
+                ("signature", value) => signature = Some(value.to_string()),
+                other => panic!("unexpected parameter {other:?}"),
+            }
+        }
+        (expires.unwrap(), signature.unwrap())
+    }
+
+    #[test]
+    fn get_signatures_cover_the_key_and_expiry() {
+        let signer = signer();
+        let url = signer.presign_get("uploads/1/a.png", Duration::from_secs(60));
+        assert!(url.starts_with("http://localhost:8080/storage/uploads/1/a.png?"));
+        let (expires, signature) = query(&url);
+
+        assert!(signer.verify_get("uploads/1/a.png", expires, &signature));
+        assert!(!signer.verify_get("uploads/1/b.png", expires, &signature));
+        assert!(!signer.verify_get("uploads/1/a.png", expires + 1, &signature));
+        assert!(!signer.verify_get("uploads/1/a.png", expires, "bm90IGl0"));
+        assert!(!signer.verify_get("uploads/1/a.png", expires, "not base64!"));
+        // A read signature does not allow writing.
+        assert!(!signer.verify_put("uploads/1/a.png", "image/png", 10, expires, &signature));
+
+        let other = UrlSigner {
+            secret: "another-secret".to_string(),
+            ..signer.clone()
+        };
+        assert!(!other.verify_get("uploads/1/a.png", expires, &signature));
+    }
+
+    #[test]
+    fn expired_signatures_are_rejected() {
+        let signer = signer();
+        let expired = Utc::now().timestamp() - 1;
+        let signature = signer.sign("GET", "uploads/1/a.png", expired, "");
+        assert!(!signer.verify_get("uploads/1/a.png", expired, &signature));
+    }
+
+    #[test]
+    fn put_signatures_bind_size_and_type() {
+        let signer = signer();
+        let request =
+            signer.presign_put("uploads/1/a.png", "image/png", 10, Duration::from_secs(60));
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.headers["Content-Type"], "image/png");
+        assert_eq!(request.headers["Content-Length"], "10");
+        let (expires, signature) = query(&request.url);
+
+        assert!(signer.verify_put("uploads/1/a.png", "image/png", 10, expires, &signature));
+        assert!(!signer.verify_put("uploads/1/a.png", "image/png", 11, expires, &signature));
+        assert!(!signer.verify_put("uploads/1/a.png", "text/html", 10, expires, &signature));
+        assert!(!signer.verify_put("uploads/1/b.png", "image/png", 10, expires, &signature));
+        assert!(!signer.verify_get("uploads/1/a.png", expires, &signature));
+    }
+
+    #[test]
+    fn keys_cannot_escape_the_store() {
+        for key in ["a.png", "uploads/1/a-b_c.png", "a/b/c"] {
+            assert!(check_key(key).is_ok(), "{key}");
+        }
+        for key in [
+            "",
+            "/etc/passwd",
+            "../secret",
+            "uploads/../../secret",
+            "uploads/./a",
+            "uploads//a",
+            "uploads/",
+            ".meta/uploads/a",
+            ".tmp/x",
+            "uploads\\..\\a",
+            "uploads/a b",
+            "uploads/%2e%2e/a",
+            "uploads/ä",
+        ] {
+            assert!(check_key(key).is_err(), "{key}");
+        }
+    }
+}
@@ -0,0 +1,704 @@
+use std::{io, time::Duration};
+
+use actix_web::rt;
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    Client,
+    config::{
+        Credentials, Region, RequestChecksumCalculation, ResponseChecksumValidation,
+        http::HttpResponse,
+    },
+    error::SdkError,
+    presigning::PresigningConfig,
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, stream};
+
+use crate::utils::{
+    app_error::AppError,
+    config::S3Config,
+    storage::{Object, ObjectMeta, ObjectStore, ObjectWriter, PresignedRequest, object_exists},
+};
+
+/// Bytes buffered before a part is sent. S3 requires at least 5 MiB for
+/// every part but the last, and allows 10,000 parts per upload.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+/// Keys S3 returns per `ListObjectsV2` page at most.
+const MAX_LIST_PAGE: usize = 1000;
+
+/// AWS S3, or an S3-compatible service such as MinIO, LocalStack or R2 when
+/// an endpoint is configured.
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+    /// Object URLs are `{object_base}/{key}`.
+    object_base: String,
+}
+
+impl S3Storage {
+    pub async fn from_config(config: &S3Config) -> Self {
+        let aws_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .region(Region::new(config.region.clone()))
+            .load()
+            .await;
+        let mut s3_config = aws_sdk_s3::config::Builder::from(&aws_config)
+            .force_path_style(config.force_path_style);
+        if let Some(endpoint_url) = &config.endpoint_url {
+            // Not every S3-compatible service accepts the checksums the SDK
+            // adds by default; only send them where the API requires one.
+            s3_config = s3_config
+                .endpoint_url(endpoint_url)
+                .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+                .response_checksum_validation(ResponseChecksumValidation::WhenRequired);
+        }
+        if let Some(credentials) = &config.credentials {
+            s3_config = s3_config.credentials_provider(Credentials::new(
+                &credentials.access_key_id,
+                &credentials.secret_access_key,
+                None,
+                None,
+                "config",
+            ));
+        }
+
+        Self {
+            client: Client::from_conf(s3_config.build()),
+            bucket: config.bucket.clone(),
+            object_base: object_base(config),
+        }
+    }
+
+    fn upload(&self, key: &str, content_type: &str, create_only: bool) -> S3Upload {
+        S3Upload {
+            client: self.client.clone(),
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            content_type: content_type.to_string(),
+            create_only,
+            buffer: Vec::new(),
+            upload_id: None,
+            parts: Vec::new(),
+        }
+    }
+}
+
+/// Where the bucket's objects are reachable: the configured public URL, or
+/// the bucket on the endpoint in the configured addressing style.
+fn object_base(config: &S3Config) -> String {
+    if let Some(public_url) = &config.public_url {
+        return public_url.clone();
+    }
+    let endpoint = config
+        .endpoint_url
+        .clone()
+        .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", config.region));
+    if config.force_path_style {
+        return format!("{endpoint}/{}", config.bucket);
+    }
+    match endpoint.split_once("://") {
+        Some((scheme, host)) => format!("{scheme}://{}.{host}", config.bucket),
+        None => format!("{}.{endpoint}", config.bucket),
+    }
+}
+
+/// Conditional writes that find the key taken fail with 412, or with 409
+/// when they race another conditional write to it.
+fn write_error<E>(e: SdkError<E, HttpResponse>) -> AppError
+where
+    E: std::error::Error + 'static,
+{
+    match e.raw_response().map(|response| response.status().as_u16()) {
+        Some(409 | 412) => object_exists(),
+        _ => e.into(),
+    }
+}
+
+fn timestamp(time: Option<&aws_sdk_s3::primitives::DateTime>) -> Option<DateTime<Utc>> {
+    time.and_then(|time| DateTime::from_timestamp(time.secs(), time.subsec_nanos()))
+}
+
+#[async_trait]
+impl ObjectStore for S3Storage {
+    async fn writer(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Box<dyn ObjectWriter>, AppError> {
+        Ok(Box::new(self.upload(key, content_type, false)))
+    }
+
+    async fn create_writer(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Box<dyn ObjectWriter>, AppError> {
+        Ok(Box::new(self.upload(key, content_type, true)))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Object>, AppError> {
+        let output = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let meta = ObjectMeta {
+            key: key.to_string(),
+            size: output.content_length.unwrap_or_default() as u64,
+            content_type: output.content_type,
+            last_modified: timestamp(output.last_modified.as_ref()),
+        };
+        let body = stream::unfold(Some(output.body), |body| async move {
+            let mut body = body?;
+            match body.try_next().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(body))),
+                Ok(None) => None,
+                Err(e) => Some((Err(io::Error::other(e)), None)),
+            }
+        });
+        Ok(Some(Object {
+            meta,
+            body: body.boxed(),
+        }))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>, AppError> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => Ok(Some(ObjectMeta {
+                key: key.to_string(),
+                size: output.content_length.unwrap_or_default() as u64,
+                content_type: output.content_type,
+                last_modified: timestamp(output.last_modified.as_ref()),
+            })),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, limit: usize) -> Result<Vec<ObjectMeta>, AppError> {
+        let mut objects = Vec::new();
+        let mut token = None;
+        while objects.len() < limit {
+            let page = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .max_keys((limit - objects.len()).min(MAX_LIST_PAGE) as i32)
+                .set_continuation_token(token)
+                .send()
+                .await?;
+            objects.extend(page.contents().iter().filter_map(|object| {
+                Some(ObjectMeta {
+                    key: object.key()?.to_string(),
+                    size: object.size().unwrap_or_default() as u64,
+                    content_type: None,
+                    last_modified: timestamp(object.last_modified()),
+                })
+            }));
+            token = page.next_continuation_token;
+            if token.is_none() {
+                break;
+            }
+        }
+        objects.truncate(limit);
+        Ok(objects)
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{key}", self.object_base)
+    }
+
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, AppError> {
+        let presigning = PresigningConfig::expires_in(expires_in)
+            .map_err(|e| AppError::Internal(format!("invalid presign expiry: {e}")))?;
+        let request = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(presigning)
+            .await?;
+        Ok(request.uri().to_string())
+    }
+
+    async fn presign_put(
+        &self,
+        key: &str,
+        content_type: &str,
+        size: u64,
+        expires_in: Duration,
+    ) -> Result<PresignedRequest, AppError> {
+        let presigning = PresigningConfig::expires_in(expires_in)
+            .map_err(|e| AppError::Internal(format!("invalid presign expiry: {e}")))?;
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .content_length(size as i64)
+            .if_none_match("*")
+            .presigned(presigning)
+            .await?;
+        Ok(PresignedRequest {
+            method: request.method().to_string(),
+            url: request.uri().to_string(),
+            headers: request
+                .headers()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        })
+    }
+}
+
+/// An object written to S3 as it arrives, one part at a time, so memory use
+/// stays at one part per upload whatever the object's size.
+///
+/// Objects that fit in a single part are sent with one `PutObject` instead.
+/// An upload dropped before `finish` succeeds is aborted, so a failed or
+/// disconnected request does not leave stored parts behind.
+struct S3Upload {
+    client: Client,
+    bucket: String,
+    key: String,
+    content_type: String,
+    /// Sent with `If-None-Match: *`, so an existing object is kept.
+    create_only: bool,
+    buffer: Vec<u8>,
+    upload_id: Option<String>,
+    parts: Vec<CompletedPart>,
+}
+
+#[async_trait]
+impl ObjectWriter for S3Upload {
+    /// Appends `chunk`, sending a part whenever a full one is buffered.
+    async fn write(&mut self, chunk: Bytes) -> Result<(), AppError> {
+        self.buffer.extend_from_slice(&chunk);
+        if self.buffer.len() >= PART_SIZE {
+            self.send_part().await?;
+        }
+        Ok(())
+    }
+
+    /// Stores whatever is still buffered and completes the object.
+    async fn finish(mut self: Box<Self>) -> Result<(), AppError> {
+        let Some(upload_id) = self.upload_id.clone() else {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .content_type(&self.content_type)
+                .set_if_none_match(self.if_none_match())
+                .body(ByteStream::from(std::mem::take(&mut self.buffer)))
+                .send()
+                .await
+                .map_err(write_error)?;
+            return Ok(());
+        };
+
+        if !self.buffer.is_empty() {
+            self.send_part().await?;
+        }
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(std::mem::take(&mut self.parts)))
+                    .build(),
+            )
+            .set_if_none_match(self.if_none_match())
+            .send()
+            .await
+            .map_err(write_error)?;
+        self.upload_id = None;
+        Ok(())
+    }
+}
+
+impl S3Upload {
+    fn if_none_match(&self) -> Option<String> {
+        self.create_only.then(|| "*".to_string())
+    }
+
+    async fn send_part(&mut self) -> Result<(), AppError> {
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let created = self
+                    .client
+                    .create_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&self.key)
+                    .content_type(&self.content_type)
+                    .send()
+                    .await?;
+                let upload_id = created.upload_id.ok_or_else(|| {
+                    AppError::Storage("CreateMultipartUpload returned no upload id".to_string())
+                })?;
+                self.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+
+        let part_number = self.parts.len() as i32 + 1;
+        let body = std::mem::replace(&mut self.buffer, Vec::with_capacity(PART_SIZE));
+        let uploaded = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+        self.parts.push(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(uploaded.e_tag)
+                .build(),
+        );
+        Ok(())
+    }
+}
+
+impl Drop for S3Upload {
+    fn drop(&mut self) {
+        let Some(upload_id) = self.upload_id.take() else {
+            return;
+        };
+        let abort = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id);
+        let key = self.key.clone();
+        rt::spawn(async move {
+            if let Err(e) = abort.send().await {
+                log::warn!(
+                    "failed to abort multipart upload of {key}: {}",
+                    aws_sdk_s3::error::DisplayErrorContext(e)
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::utils::{app_error::ErrorCode, config::S3Credentials};
+
+    fn config(endpoint_url: Option<&str>, force_path_style: bool) -> S3Config {
+        S3Config {
+            bucket: "uploads".to_string(),
+            region: "eu-west-1".to_string(),
+            endpoint_url: endpoint_url.map(str::to_string),
+            force_path_style,
+            credentials: None,
+            public_url: None,
+        }
+    }
+
+    #[test]
+    fn object_base_on_aws() {
+        assert_eq!(
+            object_base(&config(None, false)),
+            "https://uploads.s3.eu-west-1.amazonaws.com"
+        );
+        assert_eq!(
+            object_base(&config(None, true)),
+            "https://s3.eu-west-1.amazonaws.com/uploads"
+        );
+    }
+
+    #[test]
+    fn object_base_on_custom_endpoint() {
+        assert_eq!(
+            object_base(&config(Some("http://localhost:9000"), true)),
+            "http://localhost:9000/uploads"
+        );
+        assert_eq!(
+            object_base(&config(Some("https://storage.example.com"), false)),
+            "https://uploads.storage.example.com"
+        );
+    }
+
+    #[test]
+    fn object_base_prefers_public_url() {
+        let config = S3Config {
+            public_url: Some("https://cdn.example.com".to_string()),
+            ..config(Some("http://localhost:9000"), true)
+        };
+        assert_eq!(object_base(&config), "https://cdn.example.com");
+    }
+
+    /// A store on the S3-compatible service at `S3_TEST_ENDPOINT_URL`, such
+    /// as MinIO or LocalStack, with its test bucket created.
+    async fn stand_in() -> S3Storage {
+        let endpoint_url =
+            std::env::var("S3_TEST_ENDPOINT_URL").expect("S3_TEST_ENDPOINT_URL is set");
+        let env_or =
+            |key: &str, default: &str| std::env::var(key).unwrap_or_else(|_| default.to_string());
+        let config = S3Config {
+            bucket: env_or("S3_TEST_BUCKET", "curd-app-test"),
+            region: "us-east-1".to_string(),
+            endpoint_url: Some(endpoint_url),
+            force_path_style: true,
+            credentials: Some(S3Credentials {
+                access_key_id: env_or("S3_TEST_ACCESS_KEY_ID", "test"),
+                secret_access_key: env_or("S3_TEST_SECRET_ACCESS_KEY", "test"),
+            }),
+            public_url: None,
+        };
+        let storage = S3Storage::from_config(&config).await;
+        if storage
+            .client
+            .head_bucket()
+            .bucket(&storage.bucket)
+            .send()
+            .await
+            .is_err()
+        {
+            storage
+                .client
+                .create_bucket()
+                .bucket(&storage.bucket)
+                .send()
+                .await
+                .expect("test bucket can be created");
+        }
+        storage
+    }
+
+    fn test_key(name: &str) -> String {
+        format!("tests/{}/{name}", Uuid::new_v4())
+    }
+
+    /// Deterministic content that differs between parts.
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn read(storage: &S3Storage, key: &str) -> Vec<u8> {
+        let object = storage.get(key).await.unwrap().expect("object exists");
+        let chunks: Vec<Bytes> = object.body.try_collect().await.unwrap();
+        chunks.concat()
+    }
+
+    async fn pending_uploads(storage: &S3Storage, key: &str) -> usize {
+        storage
+            .client
+            .list_multipart_uploads()
+            .bucket(&storage.bucket)
+            .prefix(key)
+            .send()
+            .await
+            .unwrap()
+            .uploads()
+            .len()
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs S3_TEST_ENDPOINT_URL"]
+    async fn round_trip_with_static_credentials() {
+        let storage = stand_in().await;
+        let key = test_key("hello.txt");
+
+        let mut writer = storage.writer(&key, "text/plain").await.unwrap();
+        writer.write(Bytes::from_static(b"hello ")).await.unwrap();
+        writer.write(Bytes::from_static(b"world")).await.unwrap();
+        writer.finish().await.unwrap();
+
+        let meta = storage.head(&key).await.unwrap().expect("object exists");
+        assert_eq!(meta.size, 11);
+        assert_eq!(meta.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(read(&storage, &key).await, b"hello world");
+
+        let prefix = key.rsplit_once('/').unwrap().0;
+        let listed = storage.list(prefix, 10).await.unwrap();
+        assert_eq!(
+            listed.iter().map(|meta| &meta.key).collect::<Vec<_>>(),
+            [&key]
+        );
+
+        storage.delete(&key).await.unwrap();
+        assert!(storage.head(&key).await.unwrap().is_none());
+        assert!(storage.get(&key).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs S3_TEST_ENDPOINT_URL"]
+    async fn url_uses_path_style_on_the_endpoint() {
+        let storage = stand_in().await;
+        let endpoint = std::env::var("S3_TEST_ENDPOINT_URL").unwrap();
+
+        assert_eq!(
+            storage.url("a/b.png"),
+            format!(
+                "{}/{}/a/b.png",
+                endpoint.trim_end_matches('/'),
+                storage.bucket
+            )
+        );
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs S3_TEST_ENDPOINT_URL"]
+    async fn multipart_writer_finishes() {
+        let storage = stand_in().await;
+        let key = test_key("large.bin");
+        let data = content(2 * PART_SIZE + 1024);
+
+        let mut writer = storage
+            .writer(&key, "application/octet-stream")
+            .await
+            .unwrap();
+        for chunk in data.chunks(1024 * 1024) {
+            writer.write(Bytes::copy_from_slice(chunk)).await.unwrap();
+        }
+        writer.finish().await.unwrap();
+
+        let meta = storage.head(&key).await.unwrap().expect("object exists");
+        assert_eq!(meta.size, data.len() as u64);
+        assert!(read(&storage, &key).await == data);
+        assert_eq!(pending_uploads(&storage, &key).await, 0);
+
+        storage.delete(&key).await.unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs S3_TEST_ENDPOINT_URL"]
+    async fn dropped_multipart_writer_aborts() {
+        let storage = stand_in().await;
+        let key = test_key("abandoned.bin");
+
+        let mut writer = storage
+            .writer(&key, "application/octet-stream")
+            .await
+            .unwrap();
+        writer.write(Bytes::from(content(PART_SIZE))).await.unwrap();
+        assert_eq!(pending_uploads(&storage, &key).await, 1);
+        drop(writer);
+
+        // The abort is sent in the background.
+        let mut pending = 1;
+        for _ in 0..50 {
+            pending = pending_uploads(&storage, &key).await;
+            if pending == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(pending, 0, "multipart upload of {key} was not aborted");
+        assert!(storage.head(&key).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs S3_TEST_ENDPOINT_URL"]
+    async fn presigned_put_and_get() {
+        let storage = stand_in().await;
+        let key = test_key("photo.png");
+        let data = content(4096);
+
+        let request = storage
+            .presign_put(
+                &key,
+                "image/png",
+                data.len() as u64,
+                Duration::from_secs(60),
+            )
+            .await
+            .unwrap();
+        assert_eq!(request.method, "PUT");
+        let client = reqwest::Client::new();
+        let put = || {
+            let mut put = client.put(&request.url).body(data.clone());
+            for (name, value) in &request.headers {
+                // Sent from the body by the client itself.
+                if !name.eq_ignore_ascii_case("content-length") {
+                    put = put.header(name, value);
+                }
+            }
+            put.send()
+        };
+        let response = put().await.unwrap();
+        assert!(response.status().is_success(), "{response:?}");
+        // The URL cannot replace what it stored.
+        let response = put().await.unwrap();
+        assert_eq!(response.status(), 412, "{response:?}");
+
+        let meta = storage.head(&key).await.unwrap().expect("object exists");
+        assert_eq!(meta.size, data.len() as u64);
+        assert_eq!(meta.content_type.as_deref(), Some("image/png"));
+
+        let url = storage
+            .presign_get(&key, Duration::from_secs(60))
+            .await
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert!(response.status().is_success(), "{response:?}");
+        assert!(response.bytes().await.unwrap() == data);
+
+        storage.delete(&key).await.unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs S3_TEST_ENDPOINT_URL"]
+    async fn create_writer_keeps_existing_objects() {
+        let storage = stand_in().await;
+        // Sent whole, then as a multipart upload.
+        for len in [5, PART_SIZE + 1] {
+            let key = test_key("once.bin");
+
+            let mut writer = storage.create_writer(&key, "text/plain").await.unwrap();
+            writer.write(Bytes::from_static(b"first")).await.unwrap();
+            writer.finish().await.unwrap();
+
+            let mut writer = storage.create_writer(&key, "text/plain").await.unwrap();
+            writer.write(Bytes::from(content(len))).await.unwrap();
+            let error = writer.finish().await.unwrap_err();
+            assert_eq!(error.code(), ErrorCode::ResourceConflict, "{len} bytes");
+            assert_eq!(read(&storage, &key).await, b"first");
+
+            storage.delete(&key).await.unwrap();
+        }
+    }
+}
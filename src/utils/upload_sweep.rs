@@ -0,0 +1,182 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::rt;
+use chrono::{DateTime, Utc};
+use entity::{file, sea_orm_active_enums::FileStatus};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+
+use crate::utils::{app_error::AppError, storage::ObjectStore};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Rows loaded per query while sweeping.
+const SWEEP_BATCH: u64 = 100;
+
+/// Deletes expired direct uploads now and every [`SWEEP_INTERVAL`] after.
+/// Every instance sweeps; deleting what another already deleted is harmless.
+pub fn start(db: DatabaseConnection, storage: Arc<dyn ObjectStore>, ttl: Duration) {
+    rt::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match sweep(&db, storage.as_ref(), ttl).await {
+                Ok(0) => {}
+                Ok(swept) => log::info!("deleted {swept} expired pending uploads"),
+                Err(e) => log::warn!("failed to sweep pending uploads: {e}"),
+            }
+        }
+    });
+}
+
+/// Whether a pending upload presigned at `created_at` is past `ttl`.
+pub fn is_expired(upload: &file::Model, ttl: Duration) -> bool {
+    upload.status == FileStatus::Pending && upload.created_at < cutoff(ttl)
+}
+
+/// Pending uploads presigned before this have expired.
+fn cutoff(ttl: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| Utc::now().checked_sub_signed(ttl))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// Deletes uploads still `pending` `ttl` after they were presigned, object
+/// first and then row, and returns how many went. Whatever was sent to their
+/// URL is gone with them.
+pub async fn sweep(
+    db: &DatabaseConnection,
+    storage: &dyn ObjectStore,
+    ttl: Duration,
+) -> Result<usize, AppError> {
+    let cutoff = cutoff(ttl);
+    let mut swept = 0;
+    loop {
+        let expired = file::Entity::find()
+            .filter(file::Column::Status.eq(FileStatus::Pending))
+            .filter(file::Column::CreatedAt.lt(cutoff))
+            .order_by_asc(file::Column::Id)
+            .limit(SWEEP_BATCH)
+            .all(db)
+            .await?;
+        for upload in &expired {
+            storage.delete(&upload.key).await?;
+            file::Entity::delete_by_id(upload.id).exec(db).await?;
+        }
+        swept += expired.len();
+        if (expired.len() as u64) < SWEEP_BATCH {
+            return Ok(swept);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use entity::{sea_orm_active_enums::FilePurpose, user};
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{ActiveModelTrait, Database, Set};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::utils::{
+        config::{Config, FileConfig},
+        storage::{MemoryStorage, UrlSigner},
+    };
+
+    const TTL: Duration = Duration::from_secs(60 * 60);
+
+    async fn upload(
+        db: &DatabaseConnection,
+        storage: &MemoryStorage,
+        owner_id: i32,
+        status: FileStatus,
+        age: chrono::Duration,
+    ) -> file::Model {
+        let key = format!("uploads/{owner_id}/{}.png", Uuid::new_v4());
+        let mut writer = storage.writer(&key, "image/png").await.unwrap();
+        writer.write(Bytes::from_static(b"png")).await.unwrap();
+        writer.finish().await.unwrap();
+        file::ActiveModel {
+            key: Set(key),
+            owner_id: Set(owner_id),
+            original_name: Set("photo.png".to_string()),
+            size: Set(3),
+            content_type: Set("image/png".to_string()),
+            checksum: Set(None),
+            status: Set(status),
+            purpose: Set(FilePurpose::Attachment),
+            created_at: Set((Utc::now() - age).fixed_offset()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap()
+    }
+
+    /// Runs against the database in `DATABASE_URL`, as CI provides.
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn sweeps_only_expired_pending_uploads() {
+        dotenv::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let db = Database::connect(&url).await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let config = Config::from_sources(
+            |key| match key {
+                "DATABASE_URL" => Some(url.clone()),
+                "STORAGE_BACKEND" => Some("memory".to_string()),
+                _ => None,
+            },
+            FileConfig::default(),
+        )
+        .unwrap();
+        let storage = MemoryStorage::new(UrlSigner::new(&config));
+
+        let owner = user::ActiveModel {
+            name: Set("Sweep".to_string()),
+            email: Set(format!("sweep-{}@example.com", Uuid::new_v4())),
+            password: Set(String::new()),
+            ..Default::default()
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+        let hours = chrono::Duration::hours;
+        let expired = upload(&db, &storage, owner.id, FileStatus::Pending, hours(2)).await;
+        let fresh = upload(&db, &storage, owner.id, FileStatus::Pending, hours(0)).await;
+        let ready = upload(&db, &storage, owner.id, FileStatus::Ready, hours(2)).await;
+        assert!(is_expired(&expired, TTL));
+        assert!(!is_expired(&fresh, TTL));
+        assert!(!is_expired(&ready, TTL));
+
+        assert!(sweep(&db, &storage, TTL).await.unwrap() >= 1);
+
+        let remaining = file::Entity::find()
+            .filter(file::Column::OwnerId.eq(owner.id))
+            .order_by_asc(file::Column::Id)
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(remaining, [fresh.clone(), ready.clone()]);
+        assert!(storage.head(&expired.key).await.unwrap().is_none());
+        assert!(storage.head(&fresh.key).await.unwrap().is_some());
+        assert!(storage.head(&ready.key).await.unwrap().is_some());
+
+        // Their files must go through storage first.
+        assert!(
+            user::Entity::delete_by_id(owner.id)
+                .exec(&db)
+                .await
+                .is_err()
+        );
+        file::Entity::delete_many()
+            .filter(file::Column::OwnerId.eq(owner.id))
+            .exec(&db)
+            .await
+            .unwrap();
+        user::Entity::delete_by_id(owner.id)
+            .exec(&db)
+            .await
+            .unwrap();
+    }
+}
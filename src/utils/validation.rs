@@ -0,0 +1,215 @@
+use std::{future::Future, ops::Deref, pin::Pin};
+
+use actix_multipart::{
+    MultipartError,
+    form::{MultipartCollect, MultipartForm, MultipartFormConfig},
+};
+use actix_web::{
+    FromRequest, HttpRequest,
+    dev::Payload,
+    error::{JsonPayloadError, QueryPayloadError},
+    web,
+};
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::utils::{
+    app_error::{AppError, ErrorCode, FieldError},
+    config::StorageConfig,
+};
+
+pub const MAX_JSON_BODY_BYTES: usize = 256 * 1024;
+
+/// JSON body extractor that runs the payload's `Validate` rules before the
+/// handler sees it. Failures become a 422 listing every offending field.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let value = json.await?.into_inner();
+            value
+                .validate()
+                .map_err(|errors| AppError::Validation(field_errors(&errors)))?;
+            Ok(ValidatedJson(value))
+        })
+    }
+}
+
+/// `MultipartForm` that answers with the API's error format. The form's own
+/// error handler never sees a missing or wrong `Content-Type`; this maps those
+/// too.
+pub struct MultipartBody<T>(pub T);
+
+impl<T> FromRequest for MultipartBody<T>
+where
+    T: MultipartCollect + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let form = MultipartForm::<T>::from_request(req, payload);
+        Box::pin(async move {
+            match form.await {
+                Ok(MultipartForm(value)) => Ok(MultipartBody(value)),
+                Err(err) => Err(match err.as_error::<MultipartError>() {
+                    Some(e) => AppError::from(e).into(),
+                    None => err,
+                }),
+            }
+        })
+    }
+}
+
+/// Flattens validator output into field errors, sorted for stable responses.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut details: Vec<FieldError> = errors
+        .field_errors()
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().map(move |error| {
+                let message = error
+                    .message
+                    .as_ref()
+                    .map(|message| message.to_string())
+                    .unwrap_or_else(|| format!("Failed {} check", error.code));
+                FieldError::new(field.to_string(), message)
+            })
+        })
+        .collect();
+    details.sort_by(|a, b| a.field.cmp(&b.field).then(a.message.cmp(&b.message)));
+    details
+}
+
+/// Replaces actix's plain-text JSON errors with `AppError` responses.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(MAX_JSON_BODY_BYTES)
+        .error_handler(|err, _req| json_error(err).into())
+}
+
+/// Replaces actix's plain-text query string errors with `AppError` responses.
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| {
+        let QueryPayloadError::Deserialize(e) = err else {
+            return AppError::BadRequest(ErrorCode::InvalidRequest, err.to_string()).into();
+        };
+        AppError::BadRequest(
+            ErrorCode::InvalidRequest,
+            format!("Invalid query string: {e}"),
+        )
+        .into()
+    })
+}
+
+/// Caps every multipart form at the largest file any upload purpose allows.
+/// Each field reader still enforces its own, tighter limit as it streams.
+pub fn multipart_config(storage: &StorageConfig) -> MultipartFormConfig {
+    let largest = storage
+        .max_upload_bytes
+        .max(storage.max_avatar_bytes)
+        .max(storage.max_banner_bytes);
+    MultipartFormConfig::default()
+        .total_limit(usize::try_from(largest).unwrap_or(usize::MAX))
+        .error_handler(|err, _req| match err {
+            // Field readers report their own errors, already as `AppError`s.
+            MultipartError::Field { source, .. } => source,
+            err => AppError::from(err).into(),
+        })
+}
+
+fn json_error(err: JsonPayloadError) -> AppError {
+    match err {
+        JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+            AppError::PayloadTooLarge(
+                ErrorCode::PayloadTooLarge,
+                format!("Request body must not exceed {MAX_JSON_BODY_BYTES} bytes"),
+            )
+        }
+        JsonPayloadError::ContentType => AppError::UnsupportedMediaType(
+            ErrorCode::UnsupportedMediaType,
+            "Content-Type must be application/json".to_string(),
+        ),
+        JsonPayloadError::Deserialize(e) if e.is_data() => {
+            AppError::Validation(vec![FieldError::new(
+                field_from_serde_message(&e.to_string()).unwrap_or("body"),
+                e.to_string(),
+            )])
+        }
+        JsonPayloadError::Deserialize(e) => {
+            AppError::BadRequest(ErrorCode::InvalidRequest, format!("Malformed JSON: {e}"))
+        }
+        other => AppError::BadRequest(ErrorCode::InvalidRequest, other.to_string()),
+    }
+}
+
+/// Pulls the field name out of serde messages such as "missing field `email`".
+fn field_from_serde_message(message: &str) -> Option<&str> {
+    let start = message.find('`')? + 1;
+    let end = start + message[start..].find('`')?;
+    let field = &message[start..end];
+    message
+        .starts_with("missing field")
+        .then_some(field)
+        .or_else(|| message.starts_with("unknown field").then_some(field))
+}
+
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("must not be blank".into()));
+    }
+    Ok(())
+}
+
+/// Used alongside `url` so only web links are stored for avatars and banners.
+pub fn http_scheme(value: &str) -> Result<(), ValidationError> {
+    let lower = value.to_ascii_lowercase();
+    if !lower.starts_with("http://") && !lower.starts_with("https://") {
+        return Err(
+            ValidationError::new("url_scheme").with_message("must be an http or https URL".into())
+        );
+    }
+    Ok(())
+}
+
+/// A concrete media type such as `image/png`, as sent in `Content-Type`.
+pub fn media_type(value: &str) -> Result<(), ValidationError> {
+    match value.parse::<actix_web::mime::Mime>() {
+        Ok(mime) if mime.type_() != "*" && mime.subtype() != "*" => Ok(()),
+        _ => Err(ValidationError::new("media_type")
+            .with_message("must be a media type such as image/png".into())),
+    }
+}
+
+/// At least 8 characters with a letter and a digit. Length is capped to keep
+/// hashing cost bounded.
+pub fn password_strength(value: &str) -> Result<(), ValidationError> {
+    let length = value.chars().count();
+    if !(8..=128).contains(&length) {
+        return Err(ValidationError::new("password_length")
+            .with_message("must be between 8 and 128 characters".into()));
+    }
+    let has_letter = value.chars().any(char::is_alphabetic);
+    let has_digit = value.chars().any(|c| c.is_ascii_digit());
+    if !has_letter || !has_digit {
+        return Err(ValidationError::new("password_strength")
+            .with_message("must contain at least one letter and one digit".into()));
+    }
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use actix_web::rt;
+use sea_orm::{
+    ConnectionTrait, DatabaseBackend, DatabaseConnection, DbErr, FromQueryResult, Statement,
+    sqlx::postgres::PgListener,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, Receiver};
+use uuid::Uuid;
+
+use crate::utils::ws_hub::{Broadcast, Hub};
+
+/// Postgres channel every instance listens on.
+const CHANNEL: &str = "ws_broadcast";
+/// `NOTIFY` payloads must be under 8000 bytes; larger messages are written to
+/// `ws_broadcast_spill` and only their row id is sent.
+const MAX_INLINE_BYTES: usize = 7000;
+/// Broadcasts waiting to be relayed before new ones are dropped.
+const RELAY_BUFFER: usize = 1024;
+/// Spilled rows outlive any listener that could still need them by far.
+const SPILL_RETENTION: &str = "5 minutes";
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// What goes over the channel: the broadcast itself, or where it was spilled.
+#[derive(Debug, Serialize, Deserialize)]
+struct Notification {
+    /// Instance that published, which has already delivered locally.
+    origin: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    broadcast: Option<Broadcast>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spill: Option<i64>,
+}
+
+/// Relays the hub's broadcasts to every instance sharing the database, using
+/// a dedicated `LISTEN` connection to `url` that reconnects with backoff.
+/// Notifications sent while an instance is disconnected are not replayed.
+pub fn start_postgres(hub: &Hub, db: DatabaseConnection, url: String) {
+    let origin = Uuid::new_v4();
+    let (relay, relayed) = mpsc::channel(RELAY_BUFFER);
+    hub.set_relay(relay);
+
+    rt::spawn(publish(db.clone(), origin, relayed));
+    rt::spawn(listen(hub.clone(), db, url, origin));
+}
+
+async fn publish(db: DatabaseConnection, origin: Uuid, mut relayed: Receiver<Broadcast>) {
+    let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        tokio::select! {
+            broadcast = relayed.recv() => {
+                let Some(broadcast) = broadcast else { break };
+                if let Err(e) = notify(&db, origin, broadcast).await {
+                    log::warn!("failed to relay websocket broadcast: {e}");
+                }
+            }
+            _ = prune.tick() => {
+                let pruned = db
+                    .execute(Statement::from_string(
+                        DatabaseBackend::Postgres,
+                        format!(
+                            "DELETE FROM ws_broadcast_spill \
+                             WHERE created_at < now() - interval '{SPILL_RETENTION}'"
+                        ),
+                    ))
+                    .await;
+                if let Err(e) = pruned {
+                    log::warn!("failed to prune ws_broadcast_spill: {e}");
+                }
+            }
+        }
+    }
+}
+
+async fn notify(db: &DatabaseConnection, origin: Uuid, broadcast: Broadcast) -> Result<(), DbErr> {
+    #[derive(FromQueryResult)]
+    struct Spilled {
+        id: i64,
+    }
+
+    let inline = Notification {
+        origin,
+        broadcast: Some(broadcast),
+        spill: None,
+    };
+    let mut payload = serde_json::to_string(&inline).expect("notification serializes to JSON");
+
+    if payload.len() > MAX_INLINE_BYTES {
+        let message =
+            serde_json::to_string(&inline.broadcast).expect("broadcast serializes to JSON");
+        let spilled = Spilled::find_by_statement(Statement::from_sql_and_values(
+            DatabaseBackend::Postgres,
+            "INSERT INTO ws_broadcast_spill (message) VALUES ($1) RETURNING id",
+            [message.into()],
+        ))
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotInserted)?;
+
+        payload = serde_json::to_string(&Notification {
+            origin,
+            broadcast: None,
+            spill: Some(spilled.id),
+        })
+        .expect("notification serializes to JSON");
+    }
+
+    db.execute(Statement::from_sql_and_values(
+        DatabaseBackend::Postgres,
+        "SELECT pg_notify($1, $2)",
+        [CHANNEL.into(), payload.into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn listen(hub: Hub, db: DatabaseConnection, url: String, origin: Uuid) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match connect(&url).await {
+            Ok(mut listener) => {
+                log::info!("listening for websocket broadcasts on \"{CHANNEL}\"");
+                backoff = MIN_BACKOFF;
+                loop {
+                    match listener.try_recv().await {
+                        Ok(Some(notification)) => {
+                            receive(&hub, &db, origin, notification.payload()).await;
+                        }
+                        Ok(None) => {
+                            log::warn!("websocket broadcast listener lost its connection");
+                            break;
+                        }
+                        Err(e) => {
+                            log::warn!("websocket broadcast listener failed: {e}");
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => log::warn!("cannot start websocket broadcast listener: {e}"),
+        }
+
+        log::info!("reconnecting websocket broadcast listener in {backoff:?}");
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn connect(url: &str) -> Result<PgListener, sea_orm::sqlx::Error> {
+    let mut listener = PgListener::connect(url).await?;
+    listener.listen(CHANNEL).await?;
+    Ok(listener)
+}
+
+async fn receive(hub: &Hub, db: &DatabaseConnection, origin: Uuid, payload: &str) {
+    #[derive(FromQueryResult)]
+    struct Spilled {
+        message: String,
+    }
+
+    let notification = match serde_json::from_str::<Notification>(payload) {
+        Ok(notification) => notification,
+        Err(e) => {
+            log::warn!("ignoring malformed websocket broadcast: {e}");
+            return;
+        }
+    };
+    if notification.origin == origin {
+        return;
+    }
+
+    let broadcast = match (notification.broadcast, notification.spill) {
+        (Some(broadcast), _) => broadcast,
+        (None, Some(id)) => {
+            let spilled = Spilled::find_by_statement(Statement::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                "SELECT message FROM ws_broadcast_spill WHERE id = $1",
+                [id.into()],
+            ))
+            .one(db)
+            .await;
+            let message = match spilled {
+                Ok(Some(spilled)) => spilled.message,
+                Ok(None) => {
+                    log::warn!("spilled websocket broadcast {id} is gone");
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("failed to read spilled websocket broadcast {id}: {e}");
+                    return;
+                }
+            };
+            match serde_json::from_str(&message) {
+                Ok(broadcast) => broadcast,
+                Err(e) => {
+                    log::warn!("ignoring malformed spilled websocket broadcast {id}: {e}");
+                    return;
+                }
+            }
+        }
+        (None, None) => return,
+    };
+
+    hub.deliver(&broadcast);
+}
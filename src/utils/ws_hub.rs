@@ -0,0 +1,560 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender, error::TrySendError},
+    watch,
+};
+use uuid::Uuid;
+
+use crate::utils::{
+    app_error::{AppError, ErrorCode},
+    config::WebSocketConfig,
+    jwt::JwtClaims,
+    policy::{TopicAccess, TopicHook},
+    ws_protocol::{ServerMessage, WsError},
+};
+
+const MAX_TOPIC_LENGTH: usize = 128;
+
+/// Identifies one live socket within the hub.
+pub type ConnectionId = u64;
+
+/// A message queued for one connection. The frame is serialized once and
+/// shared by every subscriber.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub id: Arc<str>,
+    /// Position in this instance's replay log; see [`Hub::event_id`].
+    pub seq: u64,
+    pub frame: Arc<str>,
+    /// The subscription asked for redelivery until acked.
+    pub ack: bool,
+}
+
+/// A message published on a topic, as relayed between instances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Broadcast {
+    pub topic: String,
+    /// Message id, the same on every instance so clients can deduplicate.
+    pub id: String,
+    pub payload: Value,
+}
+
+struct TopicRule {
+    pattern: Vec<String>,
+    hook: TopicHook,
+}
+
+/// The topics clients may use, as `:`-separated patterns where `*` matches
+/// one segment, each with the hook that authorizes access to it. Topics that
+/// match no rule are refused.
+#[derive(Default)]
+pub struct Topics {
+    rules: Vec<TopicRule>,
+}
+
+impl Topics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule(mut self, pattern: &str, hook: TopicHook) -> Self {
+        self.rules.push(TopicRule {
+            pattern: pattern.split(':').map(str::to_string).collect(),
+            hook,
+        });
+        self
+    }
+
+    fn authorize(
+        &self,
+        claims: &JwtClaims,
+        topic: &str,
+        access: TopicAccess,
+    ) -> Result<(), WsError> {
+        let segments: Vec<&str> = topic.split(':').collect();
+        let valid = topic.len() <= MAX_TOPIC_LENGTH
+            && segments.iter().all(|segment| {
+                !segment.is_empty()
+                    && segment
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+            });
+        if !valid {
+            return Err(WsError::new(
+                ErrorCode::InvalidMessage,
+                format!("Invalid topic \"{topic}\""),
+            ));
+        }
+
+        let (rule, wildcards) = self
+            .rules
+            .iter()
+            .find_map(|rule| {
+                if rule.pattern.len() != segments.len() {
+                    return None;
+                }
+                let mut wildcards = Vec::new();
+                for (pattern, segment) in rule.pattern.iter().zip(&segments) {
+                    if pattern == "*" {
+                        wildcards.push(*segment);
+                    } else if pattern != segment {
+                        return None;
+                    }
+                }
+                Some((rule, wildcards))
+            })
+            .ok_or_else(|| {
+                WsError::new(
+                    ErrorCode::UnknownTopic,
+                    format!("Unknown topic \"{topic}\""),
+                )
+            })?;
+
+        if (rule.hook)(claims, &wildcards, access) {
+            Ok(())
+        } else {
+            Err(WsError::new(
+                ErrorCode::Forbidden,
+                format!("You are not allowed to access \"{topic}\""),
+            ))
+        }
+    }
+}
+
+/// What a socket gets from [`Hub::connect`].
+pub struct Registration {
+    pub id: ConnectionId,
+    /// Yields deliveries, and ends once the hub has dropped the connection.
+    pub deliveries: Receiver<Delivery>,
+    /// Flips to `true` when the server is shutting down.
+    pub shutdown: watch::Receiver<bool>,
+    /// This is the user's first live connection.
+    pub joined: bool,
+}
+
+/// Outcome of resuming a stream from a `Last-Event-ID`.
+pub enum Resume {
+    /// Every message after the id is still buffered: these are the ones on
+    /// the requested topics, oldest first.
+    Replay(Vec<Delivery>),
+    /// The id is from another instance, or older than the buffer, so
+    /// messages may have been missed.
+    Gap,
+}
+
+/// A delivered message kept for [`Hub::resume`].
+struct Logged {
+    seq: u64,
+    topic: String,
+    id: Arc<str>,
+    frame: Arc<str>,
+}
+
+/// Live counts on this instance.
+#[derive(Debug, Clone, Copy)]
+pub struct HubStats {
+    pub connections: usize,
+    pub users: usize,
+    pub topics: usize,
+}
+
+struct Connection {
+    user_id: i32,
+    /// Dropped when the connection is evicted, which ends its receiver. The
+    /// connection itself stays counted until its session disconnects.
+    sender: Option<Sender<Delivery>>,
+    /// Subscribed topics and whether each wants acked delivery.
+    topics: HashMap<String, bool>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: ConnectionId,
+    connections: HashMap<ConnectionId, Connection>,
+    topics: HashMap<String, HashSet<ConnectionId>>,
+    /// Live connections per user.
+    users: HashMap<i32, usize>,
+    /// Sequence number of the next delivered message.
+    next_seq: u64,
+    /// The most recent messages, oldest first.
+    log: VecDeque<Logged>,
+}
+
+impl Registry {
+    /// Forgets a connection. Returns `true` if it was its user's last one.
+    fn remove(&mut self, id: ConnectionId) -> bool {
+        self.evict(id);
+        let Some(connection) = self.connections.remove(&id) else {
+            return false;
+        };
+        let Some(count) = self.users.get_mut(&connection.user_id) else {
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.users.remove(&connection.user_id);
+            return true;
+        }
+        false
+    }
+
+    /// Stops all deliveries to a connection.
+    fn evict(&mut self, id: ConnectionId) {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return;
+        };
+        connection.sender = None;
+        let topics: Vec<String> = connection.topics.drain().map(|(topic, _)| topic).collect();
+        for topic in topics {
+            self.leave(&topic, id);
+        }
+    }
+
+    fn leave(&mut self, topic: &str, id: ConnectionId) {
+        if let Some(subscribers) = self.topics.get_mut(topic) {
+            subscribers.remove(&id);
+            if subscribers.is_empty() {
+                self.topics.remove(topic);
+            }
+        }
+    }
+}
+
+struct Inner {
+    topics: Topics,
+    buffer: usize,
+    max_connections: usize,
+    max_connections_per_user: usize,
+    registry: Mutex<Registry>,
+    shutdown: watch::Sender<bool>,
+    /// Forwards local broadcasts to other instances, when configured.
+    relay: OnceLock<Sender<Broadcast>>,
+    /// How many messages the replay log keeps.
+    replay_buffer: usize,
+    /// Prefix of this instance's event ids, so ids issued by another
+    /// instance, or before a restart, are recognised.
+    epoch: String,
+}
+
+/// Tracks live sockets and their topic subscriptions. Cloning is cheap; every
+/// clone shares the same registry, so handlers can publish through the copy
+/// in `AppState`.
+///
+/// Each connection gets a bounded queue. Publishing never waits: a connection
+/// whose queue is full is dropped from the hub, and its socket closes with
+/// `SLOW_CONSUMER` once it drains what was already queued.
+#[derive(Clone)]
+pub struct Hub {
+    inner: Arc<Inner>,
+}
+
+impl Hub {
+    /// `replay_buffer` is how many recent messages are kept for
+    /// [`Hub::resume`]; zero disables resuming.
+    pub fn new(topics: Topics, config: &WebSocketConfig, replay_buffer: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                topics,
+                buffer: config.outbound_buffer,
+                max_connections: config.max_connections,
+                max_connections_per_user: config.max_connections_per_user,
+                registry: Mutex::default(),
+                shutdown: watch::Sender::new(false),
+                relay: OnceLock::new(),
+                replay_buffer,
+                epoch: Uuid::new_v4().simple().to_string()[..8].to_string(),
+            }),
+        }
+    }
+
+    fn registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.inner.registry.lock().expect("hub registry poisoned")
+    }
+
+    /// Queue size per connection, also the cap on unacknowledged messages.
+    pub fn buffer(&self) -> usize {
+        self.inner.buffer
+    }
+
+    /// Registers a socket, enforcing the global and per-user connection caps.
+    /// Call before upgrading so a refused client gets an HTTP error.
+    pub fn connect(&self, claims: &JwtClaims) -> Result<Registration, AppError> {
+        if *self.inner.shutdown.borrow() {
+            return Err(AppError::ServiceUnavailable(
+                ErrorCode::ServerShuttingDown,
+                "Server is shutting down".to_string(),
+            ));
+        }
+
+        let mut registry = self.registry();
+        if registry.connections.len() >= self.inner.max_connections {
+            return Err(AppError::TooManyRequests(
+                ErrorCode::TooManyConnections,
+                "Too many WebSocket connections".to_string(),
+            ));
+        }
+        let user_connections = registry.users.entry(claims.user_id).or_default();
+        if *user_connections >= self.inner.max_connections_per_user {
+            return Err(AppError::TooManyRequests(
+                ErrorCode::TooManyConnections,
+                "Too many WebSocket connections for this user".to_string(),
+            ));
+        }
+        *user_connections += 1;
+        let joined = *user_connections == 1;
+
+        let (sender, deliveries) = mpsc::channel(self.inner.buffer);
+        registry.next_id += 1;
+        let id = registry.next_id;
+        registry.connections.insert(
+            id,
+            Connection {
+                user_id: claims.user_id,
+                sender: Some(sender),
+                topics: HashMap::new(),
+            },
+        );
+        Ok(Registration {
+            id,
+            deliveries,
+            shutdown: self.inner.shutdown.subscribe(),
+            joined,
+        })
+    }
+
+    /// Returns `true` if this was the user's last live connection.
+    pub fn disconnect(&self, id: ConnectionId) -> bool {
+        self.registry().remove(id)
+    }
+
+    pub fn subscription_count(&self, id: ConnectionId) -> usize {
+        self.registry()
+            .connections
+            .get(&id)
+            .map_or(0, |connection| connection.topics.len())
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.registry().connections.len()
+    }
+
+    pub fn stats(&self) -> HubStats {
+        let registry = self.registry();
+        HubStats {
+            connections: registry.connections.len(),
+            users: registry.users.len(),
+            topics: registry.topics.len(),
+        }
+    }
+
+    /// Users with live connections on this instance, and how many each has.
+    pub fn online_users(&self) -> Vec<(i32, usize)> {
+        let mut users: Vec<(i32, usize)> = self
+            .registry()
+            .users
+            .iter()
+            .map(|(user_id, count)| (*user_id, *count))
+            .collect();
+        users.sort_unstable();
+        users
+    }
+
+    /// Refuses new connections, asks every socket to close, and waits up to
+    /// `grace` for them to go.
+    pub async fn shutdown(&self, grace: std::time::Duration) {
+        self.inner.shutdown.send_replace(true);
+        let deadline = tokio::time::Instant::now() + grace;
+        while self.connection_count() > 0 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Runs the topic's authorization hook for a client request.
+    pub fn authorize(
+        &self,
+        claims: &JwtClaims,
+        topic: &str,
+        access: TopicAccess,
+    ) -> Result<(), WsError> {
+        self.inner.topics.authorize(claims, topic, access)
+    }
+
+    /// Subscribes a connection, or updates the ack option of an existing
+    /// subscription. Callers authorize first.
+    pub fn subscribe(&self, id: ConnectionId, topic: &str, ack: bool) {
+        Self::join(&mut self.registry(), id, topic, ack);
+    }
+
+    fn join(registry: &mut Registry, id: ConnectionId, topic: &str, ack: bool) {
+        let Some(connection) = registry.connections.get_mut(&id) else {
+            return;
+        };
+        connection.topics.insert(topic.to_string(), ack);
+        registry
+            .topics
+            .entry(topic.to_string())
+            .or_default()
+            .insert(id);
+    }
+
+    pub fn unsubscribe(&self, id: ConnectionId, topic: &str) {
+        let mut registry = self.registry();
+        let Some(connection) = registry.connections.get_mut(&id) else {
+            return;
+        };
+        if connection.topics.remove(topic).is_some() {
+            registry.leave(topic, id);
+        }
+    }
+
+    /// Id of the delivered message at `seq`, as sent to SSE clients.
+    pub fn event_id(&self, seq: u64) -> String {
+        format!("{}-{seq}", self.inner.epoch)
+    }
+
+    /// Subscribes a connection to `topics` and returns what it missed since
+    /// `last_event_id`, both under one lock so nothing is lost or repeated in
+    /// between. Callers authorize first.
+    pub fn resume(&self, id: ConnectionId, topics: &[String], last_event_id: &str) -> Resume {
+        let mut registry = self.registry();
+        for topic in topics {
+            Self::join(&mut registry, id, topic, false);
+        }
+
+        let Some(seq) = last_event_id
+            .split_once('-')
+            .filter(|(epoch, _)| *epoch == self.inner.epoch)
+            .and_then(|(_, seq)| seq.parse::<u64>().ok())
+        else {
+            return Resume::Gap;
+        };
+        let oldest = registry
+            .log
+            .front()
+            .map_or(registry.next_seq, |logged| logged.seq);
+        if seq >= registry.next_seq || seq + 1 < oldest {
+            return Resume::Gap;
+        }
+
+        Resume::Replay(
+            registry
+                .log
+                .iter()
+                .filter(|logged| logged.seq > seq && topics.contains(&logged.topic))
+                .map(|logged| Delivery {
+                    id: logged.id.clone(),
+                    seq: logged.seq,
+                    frame: logged.frame.clone(),
+                    ack: false,
+                })
+                .collect(),
+        )
+    }
+
+    /// Sends every later [`Hub::publish`] to `relay` as well. Set once, by the
+    /// cross-instance backend.
+    pub fn set_relay(&self, relay: Sender<Broadcast>) {
+        if self.inner.relay.set(relay).is_err() {
+            log::warn!("websocket broadcast relay is already set");
+        }
+    }
+
+    /// Publishes to every subscriber of `topic`, on this instance and, with a
+    /// relay, on the others. Returns how many local connections it reached.
+    /// No authorization hook runs here: this is the server speaking.
+    pub fn publish(&self, topic: &str, payload: Value) -> usize {
+        let broadcast = Broadcast {
+            topic: topic.to_string(),
+            id: Uuid::new_v4().to_string(),
+            payload,
+        };
+        let delivered = self.deliver(&broadcast);
+
+        if let Some(relay) = self.inner.relay.get()
+            && relay.try_send(broadcast).is_err()
+        {
+            log::warn!(
+                "websocket broadcast relay is backed up; {topic} not sent to other instances"
+            );
+        }
+        delivered
+    }
+
+    /// Publishes to the subscribers of `topic` on this instance only, for
+    /// events that describe this instance's own state.
+    pub fn publish_local(&self, topic: &str, payload: Value) -> usize {
+        self.deliver(&Broadcast {
+            topic: topic.to_string(),
+            id: Uuid::new_v4().to_string(),
+            payload,
+        })
+    }
+
+    /// Queues a broadcast for the local subscribers of its topic and returns
+    /// how many connections it reached.
+    pub fn deliver(&self, broadcast: &Broadcast) -> usize {
+        let topic = broadcast.topic.as_str();
+        let frame: Arc<str> =
+            ServerMessage::message(topic, broadcast.payload.clone(), &broadcast.id)
+                .to_frame()
+                .into();
+        let id: Arc<str> = broadcast.id.as_str().into();
+
+        let mut registry = self.registry();
+        let seq = registry.next_seq;
+        registry.next_seq += 1;
+        if self.inner.replay_buffer > 0 {
+            if registry.log.len() == self.inner.replay_buffer {
+                registry.log.pop_front();
+            }
+            registry.log.push_back(Logged {
+                seq,
+                topic: topic.to_string(),
+                id: id.clone(),
+                frame: frame.clone(),
+            });
+        }
+
+        let Some(subscribers) = registry.topics.get(topic) else {
+            return 0;
+        };
+
+        let mut delivered = 0;
+        let mut dropped = Vec::new();
+        for connection_id in subscribers {
+            let Some(connection) = registry.connections.get(connection_id) else {
+                continue;
+            };
+            let Some(sender) = &connection.sender else {
+                continue;
+            };
+            let delivery = Delivery {
+                id: id.clone(),
+                seq,
+                frame: frame.clone(),
+                ack: connection.topics.get(topic).copied().unwrap_or(false),
+            };
+            match sender.try_send(delivery) {
+                Ok(()) => delivered += 1,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!(
+                        "dropping slow websocket consumer {connection_id} (user {})",
+                        connection.user_id
+                    );
+                    dropped.push(*connection_id);
+                }
+                Err(TrySendError::Closed(_)) => dropped.push(*connection_id),
+            }
+        }
+        for connection_id in dropped {
+            registry.evict(connection_id);
+        }
+        delivered
+    }
+}
@@ -0,0 +1,173 @@
+use actix_web::ResponseError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::app_error::{AppError, ErrorCode};
+
+/// Version of the JSON envelope spoken on `/ws`. Clients may omit `v`; any
+/// other value is rejected so the envelope can evolve without guesswork.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+fn protocol_version() -> u8 {
+    PROTOCOL_VERSION
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientMessageType {
+    Subscribe,
+    Unsubscribe,
+    Publish,
+    Ack,
+    /// Stores a chat message; see [`SendMessage`].
+    Send,
+    /// Resends chat messages missed while offline; see [`ReplayOptions`].
+    Replay,
+}
+
+/// A frame sent by a client: `{v, type, topic, payload, id}`. For every type
+/// but `ack`, `id` is echoed on the reply; for `ack` it names the delivered
+/// message being acknowledged.
+#[derive(Debug, Deserialize)]
+pub struct ClientMessage {
+    #[serde(default = "protocol_version")]
+    pub v: u8,
+    #[serde(rename = "type")]
+    pub kind: ClientMessageType,
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub payload: Value,
+    pub id: Option<String>,
+}
+
+/// Optional `payload` of a subscribe request.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscribeOptions {
+    /// Redeliver messages on this topic until the client acks them.
+    #[serde(default)]
+    pub ack: bool,
+}
+
+/// `payload` of a send request.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SendMessage {
+    pub conversation_id: i32,
+    pub body: String,
+}
+
+/// `payload` of a replay request: messages with an id above `after_id`, in
+/// every conversation the caller belongs to, oldest first.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayOptions {
+    /// Last message id the client has seen; omit to start from the beginning.
+    #[serde(default)]
+    pub after_id: i32,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerMessageType {
+    /// A message published on a topic the client subscribed to.
+    Message,
+    /// The client's request with this `id` succeeded.
+    Ack,
+    /// The client's request with this `id` failed.
+    Error,
+}
+
+/// A frame sent by the server, in the same envelope as [`ClientMessage`].
+#[derive(Debug, Serialize)]
+pub struct ServerMessage<'a> {
+    pub v: u8,
+    #[serde(rename = "type")]
+    pub kind: ServerMessageType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<&'a str>,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub payload: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<&'a str>,
+}
+
+impl<'a> ServerMessage<'a> {
+    pub fn message(topic: &'a str, payload: Value, id: &'a str) -> Self {
+        Self::new(ServerMessageType::Message, Some(topic), payload, Some(id))
+    }
+
+    pub fn ack(id: Option<&'a str>) -> Self {
+        Self::new(ServerMessageType::Ack, None, Value::Null, id)
+    }
+
+    /// An ack carrying a result, such as the stored chat message.
+    pub fn with_payload(mut self, payload: Value) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    pub fn error(id: Option<&'a str>, error: &WsError) -> Self {
+        Self::new(
+            ServerMessageType::Error,
+            None,
+            serde_json::json!({ "code": error.code, "message": error.message }),
+            id,
+        )
+    }
+
+    fn new(
+        kind: ServerMessageType,
+        topic: Option<&'a str>,
+        payload: Value,
+        id: Option<&'a str>,
+    ) -> Self {
+        Self {
+            v: PROTOCOL_VERSION,
+            kind,
+            topic,
+            payload,
+            id,
+        }
+    }
+
+    pub fn to_frame(&self) -> String {
+        serde_json::to_string(self).expect("server message serializes to JSON")
+    }
+}
+
+/// A request the server refused, reported back as an `error` frame.
+#[derive(Debug)]
+pub struct WsError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl WsError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Handler errors keep their code and public message. Validation details are
+/// folded into the message, since error frames carry no `details`.
+impl From<AppError> for WsError {
+    fn from(error: AppError) -> Self {
+        if error.status_code().is_server_error() {
+            log::error!("websocket request failed: {error}");
+        }
+        let message = match error.details() {
+            Some(details) => details
+                .iter()
+                .map(|detail| format!("{}: {}", detail.field, detail.message))
+                .collect::<Vec<_>>()
+                .join("; "),
+            None => error.public_message(),
+        };
+        WsError::new(error.code(), message)
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use entity::ws_ticket;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use uuid::Uuid;
+
+use crate::utils::{app_error::AppError, jwt::JwtClaims};
+
+/// Short-lived, single-use tickets for opening a WebSocket. Browsers cannot set
+/// an `Authorization` header on the handshake, and putting the JWT itself in
+/// the URL would leak it into access logs, so clients trade their token for a
+/// ticket and pass that as `?ticket=` instead.
+///
+/// Tickets are stored in the database, hashed like refresh tokens, so any
+/// instance can redeem them.
+pub struct WsTickets {
+    ttl: Duration,
+}
+
+fn hash_ticket(ticket: &str) -> String {
+    sha256::digest(ticket)
+}
+
+impl WsTickets {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Stores the claims under a new random ticket.
+    pub async fn issue(
+        &self,
+        db: &DatabaseConnection,
+        claims: &JwtClaims,
+    ) -> Result<String, AppError> {
+        let ticket = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let now = Utc::now();
+        // Unredeemed tickets would otherwise pile up forever.
+        ws_ticket::Entity::delete_many()
+            .filter(ws_ticket::Column::ExpiresAt.lte(now))
+            .exec(db)
+            .await?;
+        let ttl = chrono::Duration::from_std(self.ttl)
+            .map_err(|e| AppError::Internal(format!("invalid ticket TTL: {e}")))?;
+        ws_ticket::ActiveModel {
+            ticket_hash: Set(hash_ticket(&ticket)),
+            claims: Set(
+                serde_json::to_string(claims).map_err(|e| AppError::Internal(e.to_string()))?
+            ),
+            expires_at: Set((now + ttl).fixed_offset()),
+        }
+        .insert(db)
+        .await?;
+        Ok(ticket)
+    }
+
+    /// Consumes the ticket, returning its claims if it has not expired. The
+    /// row is deleted as it is read, so only one redemption can succeed.
+    pub async fn redeem(
+        &self,
+        db: &DatabaseConnection,
+        ticket: &str,
+    ) -> Result<Option<JwtClaims>, AppError> {
+        let redeemed = ws_ticket::Entity::delete_many()
+            .filter(ws_ticket::Column::TicketHash.eq(hash_ticket(ticket)))
+            .exec_with_returning(db)
+            .await?;
+        Ok(redeemed
+            .into_iter()
+            .find(|row| row.expires_at > Utc::now())
+            .and_then(|row| serde_json::from_str(&row.claims).ok()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::sea_orm_active_enums::Role;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::Database;
+
+    use super::*;
+
+    fn claims() -> JwtClaims {
+        JwtClaims {
+            user_id: 7,
+            exp: 4_000_000_000,
+            iat: 1_700_000_000,
+            email: "ticket@example.com".to_string(),
+            role: Role::User,
+            sid: Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Runs against the database in `DATABASE_URL`, as CI provides.
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn tickets_redeem_once_on_any_instance() {
+        dotenv::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let db = Database::connect(url).await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        // Two instances share nothing but the database.
+        let issuer = WsTickets::new(Duration::from_secs(30));
+        let redeemer = WsTickets::new(Duration::from_secs(30));
+        let claims = claims();
+        let ticket = issuer.issue(&db, &claims).await.unwrap();
+
+        let redeemed = redeemer.redeem(&db, &ticket).await.unwrap().unwrap();
+        assert_eq!(redeemed.sid, claims.sid);
+        assert_eq!(redeemed.user_id, claims.user_id);
+        assert!(redeemer.redeem(&db, &ticket).await.unwrap().is_none());
+        assert!(issuer.redeem(&db, &ticket).await.unwrap().is_none());
+        assert!(issuer.redeem(&db, "unknown").await.unwrap().is_none());
+
+        let expired = WsTickets::new(Duration::ZERO);
+        let ticket = expired.issue(&db, &claims).await.unwrap();
+        assert!(redeemer.redeem(&db, &ticket).await.unwrap().is_none());
+    }
+}